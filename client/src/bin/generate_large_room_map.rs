@@ -5,46 +5,32 @@ fn main() {
     let output_path = "client/assets/large_room_map.json";
 
     // Ensure the directory exists
-    if let Some(parent) = Path::new(output_path).parent() {
-        if !parent.exists() {
-            println!("Creating directory: {:?}", parent);
-            std::fs::create_dir_all(parent).expect("Failed to create directory");
-        }
+    if let Some(parent) = Path::new(output_path).parent()
+        && !parent.exists()
+    {
+        println!("Creating directory: {:?}", parent);
+        std::fs::create_dir_all(parent).expect("Failed to create directory");
     }
 
     println!("Generating large room map to {}", output_path);
 
-    // Create a large room layout (30x30)
+    // Create a large room layout (30x30), the map picks the wall pieces
     let mut large_room = Vec::new();
 
     // Top wall row
-    let mut top_row = Vec::new();
-    top_row.push(2); // Top-left corner
-    for _ in 0..28 {
-        top_row.push(1); // Top wall
-    }
-    top_row.push(3); // Top-right corner
-    large_room.push(top_row);
+    large_room.push(vec![1; 30]);
 
     // Middle rows
     for _ in 0..28 {
         let mut row = Vec::new();
-        row.push(2); // Left wall
-        for _ in 0..28 {
-            row.push(0); // Empty space
-        }
-        row.push(3); // Right wall
+        row.push(1); // Left wall
+        row.extend([0; 28]); // Empty space
+        row.push(1); // Right wall
         large_room.push(row);
     }
 
     // Bottom wall row
-    let mut bottom_row = Vec::new();
-    bottom_row.push(2); // Bottom-left corner
-    for _ in 0..28 {
-        bottom_row.push(1); // Bottom wall
-    }
-    bottom_row.push(3); // Bottom-right corner
-    large_room.push(bottom_row);
+    large_room.push(vec![1; 30]);
 
     // Add some decorations (skulls) in the room
     let mut decorated_room = large_room.clone();
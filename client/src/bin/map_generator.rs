@@ -13,11 +13,11 @@ fn main() {
     };
 
     // Ensure the directory exists
-    if let Some(parent) = Path::new(&output_path).parent() {
-        if !parent.exists() {
-            println!("Creating directory: {:?}", parent);
-            std::fs::create_dir_all(parent).expect("Failed to create directory");
-        }
+    if let Some(parent) = Path::new(&output_path).parent()
+        && !parent.exists()
+    {
+        println!("Creating directory: {:?}", parent);
+        std::fs::create_dir_all(parent).expect("Failed to create directory");
     }
 
     println!("Generating custom map to {}", output_path);
@@ -26,66 +26,66 @@ fn main() {
 
     // First room - entrance hall
     let room1_layout = vec![
-        vec![2, 5, 1, 1, 1, 1, 1, 1, 1, 1, 6, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 3], // Door at position (7, 10) to room 1
-        vec![2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 3],
+        vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 1], // Door at position (7, 10) to room 1
+        vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
     ];
 
     // Second room - main hall with obstacles
     let room2_layout = vec![
-        vec![2, 5, 1, 1, 1, 1, 1, 1, 1, 1, 6, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 3], // Door at position (7, 1) to room 0
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 0, 0, 1, 1, 0, 0, 0, 1, 1, 0, 3],
-        vec![2, 0, 0, 1, 0, 0, 0, 0, 0, 1, 0, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 0, 1, 1, 1, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 0, 1, 0, 1, 0, 0, 0, 3],
-        vec![2, 0, 0, 1, 0, 0, 0, 0, 0, 1, 0, 3],
-        vec![2, 0, 0, 1, 1, 0, 0, 0, 1, 1, 0, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 3], // Door at position (7, 10) to room 2
-        vec![2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 3],
+        vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 1], // Door at position (7, 1) to room 0
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 0, 0, 1, 1, 0, 0, 0, 1, 1, 0, 1],
+        vec![1, 0, 0, 1, 0, 0, 0, 0, 0, 1, 0, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 0, 1, 1, 1, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 0, 1, 0, 1, 0, 0, 0, 1],
+        vec![1, 0, 0, 1, 0, 0, 0, 0, 0, 1, 0, 1],
+        vec![1, 0, 0, 1, 1, 0, 0, 0, 1, 1, 0, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 1], // Door at position (7, 10) to room 2
+        vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
     ];
 
     // Third room - treasure room with inner chamber
     let room3_layout = vec![
-        vec![2, 5, 1, 1, 1, 1, 1, 1, 1, 1, 6, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 3], // Door at position (7, 1) to room 1
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 0, 0, 1, 1, 1, 1, 1, 1, 0, 0, 3],
-        vec![2, 0, 0, 1, 0, 0, 0, 0, 1, 0, 0, 3],
-        vec![2, 0, 0, 1, 0, 1, 1, 0, 1, 0, 0, 3],
-        vec![2, 0, 0, 1, 0, 1, 0, 0, 1, 0, 0, 3],
-        vec![2, 0, 0, 1, 0, 0, 0, 0, 1, 0, 0, 3],
-        vec![2, 0, 0, 1, 1, 1, 1, 1, 1, 0, 0, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 3], // Door at position (7, 10) to room 3
-        vec![2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 3],
+        vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 1], // Door at position (7, 1) to room 1
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 0, 0, 1, 1, 1, 1, 1, 1, 0, 0, 1],
+        vec![1, 0, 0, 1, 0, 0, 0, 0, 1, 0, 0, 1],
+        vec![1, 0, 0, 1, 0, 1, 1, 0, 1, 0, 0, 1],
+        vec![1, 0, 0, 1, 0, 1, 0, 0, 1, 0, 0, 1],
+        vec![1, 0, 0, 1, 0, 0, 0, 0, 1, 0, 0, 1],
+        vec![1, 0, 0, 1, 1, 1, 1, 1, 1, 0, 0, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 1], // Door at position (7, 10) to room 3
+        vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
     ];
 
     // Fourth room - secret chamber
     let room4_layout = vec![
-        vec![2, 5, 1, 1, 1, 1, 1, 1, 1, 1, 6, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 3], // Door at position (7, 1) to room 2
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 1, 0, 0, 1, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 1, 0, 0, 1, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-        vec![2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 3],
+        vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 7, 0, 0, 0, 1], // Door at position (7, 1) to room 2
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 1, 0, 0, 1, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 1, 0, 0, 1, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+        vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
     ];
 
     // Define doors and their destinations
@@ -0,0 +1,44 @@
+use client::map::{Map, tiled::TileTable, validate};
+use std::env;
+
+fn main() {
+    // Parse command line arguments
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        eprintln!("Usage: validate_map <map> [<map> ...]");
+        eprintln!("Example: cargo r --bin validate_map -- client/assets/default_map.json");
+        std::process::exit(2);
+    }
+
+    let mut failed = false;
+    for path in &args {
+        let map = match Map::load(path, &TileTable::default()) {
+            Ok(map) => map,
+            Err(e) => {
+                eprintln!("{}", e);
+                failed = true;
+                continue;
+            }
+        };
+
+        let problems = validate::check(&map);
+        if problems.is_empty() {
+            println!("{}: no problems found", path);
+            continue;
+        }
+        failed = true;
+        let noun = if problems.len() == 1 {
+            "problem"
+        } else {
+            "problems"
+        };
+        println!("{}: {} {}", path, problems.len(), noun);
+        for problem in problems {
+            println!("  {}", problem);
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{
+    assets::AssetManager,
+    input::{MovementState, PLAYER_SIZE},
+    map::Map,
+    player::{CharacterType, Player},
+};
+use ggez::{
+    Context, GameResult,
+    graphics::{self, Drawable},
+};
+use protocol::{EntityKind, Facing, Position};
+
+/// Anything in the world besides ourselves, as told to us by the server.
+pub enum Entity {
+    /// Players and NPCs, drawn as animated characters
+    Character(Player),
+    /// Items and projectiles, drawn as a marker with their name
+    Object(WorldObject),
+}
+
+impl Entity {
+    pub fn new(kind: EntityKind, pos: Position, facing: Facing) -> Self {
+        match kind {
+            EntityKind::Player(name) => {
+                let mut player = Player::new(name, pos);
+                player.direction = facing;
+                Entity::Character(player)
+            }
+            EntityKind::Npc(name) => {
+                let mut npc = Player::new(name, pos);
+                npc.direction = facing;
+                // Tell NPCs apart from the knights everyone starts as
+                npc.character_type = CharacterType::Wizard;
+                Entity::Character(npc)
+            }
+            kind => Entity::Object(WorldObject {
+                kind,
+                pos,
+                direction: facing,
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Entity::Character(player) => &player.name,
+            Entity::Object(object) => object.kind.name(),
+        }
+    }
+
+    pub fn pos(&self) -> Position {
+        match self {
+            Entity::Character(player) => player.pos,
+            Entity::Object(object) => object.pos,
+        }
+    }
+
+    /// Zone path of the room the entity is in, empty if unknown
+    pub fn zone(&self) -> &[String] {
+        match self {
+            Entity::Character(player) => &player.zone,
+            Entity::Object(_) => &[],
+        }
+    }
+
+    /// The character behind this entity, if it is a player or NPC.
+    pub fn as_character_mut(&mut self) -> Option<&mut Player> {
+        match self {
+            Entity::Character(player) => Some(player),
+            Entity::Object(_) => None,
+        }
+    }
+
+    // Put the entity where interpolation has it this frame, see `Snapshots::sample`
+    pub fn place(&mut self, pos: Position, moving: bool) {
+        match self {
+            Entity::Character(player) => {
+                player.prev_pos = player.pos;
+                player.pos = pos;
+                player.is_moving = moving;
+            }
+            Entity::Object(object) => object.pos = pos,
+        }
+    }
+
+    pub fn face(&mut self, facing: Facing) {
+        match self {
+            Entity::Character(player) => player.direction = facing,
+            Entity::Object(object) => object.direction = facing,
+        }
+    }
+
+    pub fn update(&mut self, map: &Map, grid_size: i32, delta_time: f32) {
+        if let Entity::Character(player) = self {
+            // Remote characters are only moved by the server, never by local input
+            let no_movement = MovementState {
+                is_moving: player.is_moving,
+                direction: player.direction,
+                dx: 0,
+                dy: 0,
+            };
+            player.update(&no_movement, map, grid_size, delta_time);
+        }
+    }
+
+    pub fn draw(
+        &self,
+        ctx: &Context,
+        canvas: &mut graphics::Canvas,
+        asset_manager: &AssetManager,
+        alpha: f32,
+    ) -> GameResult<()> {
+        match self {
+            Entity::Character(player) => player.draw(ctx, canvas, asset_manager, alpha),
+            Entity::Object(object) => object.draw(ctx, canvas),
+        }
+    }
+}
+
+/// A non-character entity such as a dropped item or a flying arrow.
+pub struct WorldObject {
+    pub kind: EntityKind,
+    pub pos: Position,
+    pub direction: Facing,
+}
+
+impl WorldObject {
+    fn draw(&self, ctx: &Context, canvas: &mut graphics::Canvas) -> GameResult<()> {
+        // There are no sprites for objects yet, so draw a small marker
+        let (size, color) = match self.kind {
+            EntityKind::Projectile(_) => (PLAYER_SIZE as f32 * 0.25, graphics::Color::WHITE),
+            _ => (PLAYER_SIZE as f32 * 0.5, graphics::Color::YELLOW),
+        };
+        let offset = (PLAYER_SIZE as f32 - size) / 2.0;
+        let x = self.pos.x as f32 + offset;
+        let y = self.pos.y as f32 + offset;
+        canvas.draw(
+            &graphics::Quad,
+            graphics::DrawParam::default()
+                .dest([x, y])
+                .scale([size, size])
+                .color(color),
+        );
+
+        // Label it like a player name
+        let name_text = graphics::Text::new(self.kind.name());
+        let name_width = name_text.dimensions(ctx).map(|d| d.w).unwrap_or(0.0);
+        canvas.draw(
+            &name_text,
+            graphics::DrawParam::default()
+                .dest([x + size / 2.0 - name_width / 2.0, y - 20.0])
+                .color(graphics::Color::WHITE),
+        );
+
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use std::str::FromStr;
+use std::time::Duration;
 
 use ggez::{
     GameResult,
@@ -6,19 +6,32 @@ use ggez::{
     graphics::{self, Color, DrawParam, Drawable, Rect, Text},
     input::keyboard::KeyCode,
 };
-use protocol::Position;
+use protocol::{
+    Position, auth,
+    handshake::{self, Compatibility},
+    template,
+    zones::ZoneLink,
+};
 
 use crate::{
     assets::AssetManager,
     input::{self, MovementState},
     map::Map,
-    net::NetClient,
+    net::{ConnectionState, NetClient},
+    netsim::Conditions,
+    outbox::Outbox,
     player::Players,
+    prediction::Prediction,
 };
 
 // Constants
 pub const GRID_SIZE: i32 = 16;
 pub const CAMERA_ZOOM: f32 = 3.5;
+/// Simulation ticks per second, whatever the frame rate
+pub const TICK_RATE: u32 = 60;
+const TICK: Duration = Duration::from_nanos(1_000_000_000 / TICK_RATE as u64);
+// Most ticks run in one frame before the simulation gives up catching up
+const MAX_TICKS_PER_FRAME: u32 = 8;
 #[allow(unused)]
 pub const DIALOGUE_PADDING: f32 = 20.0;
 #[allow(unused)]
@@ -42,6 +55,14 @@ pub struct GameState {
 
     // Player management
     players: Players,
+    // Our inputs the server has not acknowledged yet
+    prediction: Prediction,
+    // Our movement waiting for the next network tick
+    outbox: Outbox,
+    // Turns held keys into whole pixel steps per tick
+    stepper: input::Stepper,
+    // Time not yet simulated, less than a tick after every update
+    accumulator: Duration,
 
     // Map
     map: Map,
@@ -51,6 +72,9 @@ pub struct GameState {
     password: String,
     input_focus: InputField,
     auth_action: AuthAction, // New field to track the current auth action
+    auth_status: AuthStatus,
+    // What may be sent in the clear to log in or register
+    login_options: LoginOptions,
 
     // Chat state
     is_chatting: bool,
@@ -69,6 +93,99 @@ enum AuthAction {
     Register,
 }
 
+impl AuthAction {
+    fn verb(&self) -> &'static str {
+        match self {
+            AuthAction::Login => "login",
+            AuthAction::Register => "registration",
+        }
+    }
+}
+
+// What the player allowed on the command line to be sent in the clear
+#[derive(Default)]
+struct LoginOptions {
+    // The password itself, to servers without challenge auth
+    legacy_login: bool,
+    // The stored key of a new account, which together with any later login
+    // proof lets an eavesdropper log in as that user
+    insecure_register: bool,
+}
+
+// The message starting a login or registration, or why it must not be sent.
+// Servers with challenge auth never see the password, but registering with it
+// still hands over the stored key. Connections are never encrypted, so either
+// secret only goes out when the player allowed it.
+fn auth_message(
+    action: &AuthAction,
+    username: &str,
+    password: &str,
+    info: &handshake::ServerInfo,
+    options: &LoginOptions,
+) -> Result<protocol::ClientToServer, String> {
+    use protocol::ClientToServer::*;
+
+    if info.supports_challenge_auth() {
+        return match action {
+            AuthAction::Login => Ok(AuthStart(username.to_string())),
+            AuthAction::Register if !options.insecure_register => Err(
+                "Registering would send the account's key unencrypted, start with \
+                 --insecure-register to allow it"
+                    .to_string(),
+            ),
+            AuthAction::Register => {
+                log::warn!("Registering over an unencrypted connection");
+                let mut salt = [0; 16];
+                getrandom::getrandom(&mut salt)
+                    .map_err(|e| format!("Could not generate a salt: {}", e))?;
+                let verifier = auth::Verifier::new(password, &salt, auth::DEFAULT_ITERATIONS);
+                Ok(RegisterVerifier(username.to_string(), verifier))
+            }
+        };
+    }
+
+    if !options.legacy_login {
+        return Err(
+            "Server only takes plaintext passwords, start with --legacy-login to allow it"
+                .to_string(),
+        );
+    }
+    log::warn!("Server lacks challenge auth, sending the password in plaintext");
+    Ok(match action {
+        AuthAction::Login => Login(username.to_string(), password.to_string()),
+        AuthAction::Register => Register(username.to_string(), password.to_string()),
+    })
+}
+
+// Adds a frame's time to the time not simulated yet and takes out the whole
+// ticks it makes up, returning how many
+fn ticks_due(accumulator: &mut Duration, frame: Duration) -> u32 {
+    // After a stall, such as dragging the window, skip ahead instead of catching up
+    *accumulator = (*accumulator + frame).min(TICK * MAX_TICKS_PER_FRAME);
+    let mut ticks = 0;
+    while *accumulator >= TICK {
+        *accumulator -= TICK;
+        ticks += 1;
+    }
+    ticks
+}
+
+// Whether losing the connection in `stage` sends the player back to the login
+// screen: without a session the next connection is not logged in, so the
+// password is needed again
+fn needs_login_again(stage: &Stage, has_session: bool) -> bool {
+    matches!(stage, Stage::InGame) && !has_session
+}
+
+// Where the last login or registration attempt stands
+enum AuthStatus {
+    Idle,
+    // Sent, the server has not answered yet
+    Waiting,
+    // Rejected or kicked, shown on the login screen until the next attempt
+    Failed(String),
+}
+
 impl GameState {
     pub fn new(ctx: &mut Context) -> Self {
         Self::new_with_mode(ctx, false)
@@ -140,7 +257,8 @@ impl GameState {
             start_pos.x,
             start_pos.y
         );
-        let players = Players::new("Player".to_string(), start_pos);
+        let mut players = Players::new("Player".to_string(), start_pos);
+        players.self_player.zone = map.zone_path();
 
         Self {
             stage: if offline_mode {
@@ -152,16 +270,44 @@ impl GameState {
             nc,
             asset_manager,
             players,
+            prediction: Prediction::new(),
+            outbox: Outbox::new(),
+            stepper: input::Stepper::default(),
+            accumulator: Duration::ZERO,
             map,
             username: String::new(),
             password: String::new(),
             input_focus: InputField::Username,
             auth_action: AuthAction::Login,
+            auth_status: AuthStatus::Idle,
+            login_options: LoginOptions::default(),
             is_chatting: false,
             chat_input: String::new(),
         }
     }
 
+    /// Sets how many pings in a row may go unanswered before reconnecting.
+    pub fn set_max_missed_pongs(&mut self, max_missed: u32) {
+        self.nc.set_max_missed_pongs(max_missed);
+    }
+
+    /// Puts the connection through a simulated bad network.
+    pub fn simulate_network(&mut self, conditions: Conditions) {
+        self.nc.simulate(conditions);
+    }
+
+    /// Allows the old plaintext login and registration for servers that lack
+    /// challenge auth.
+    pub fn set_legacy_login(&mut self, allowed: bool) {
+        self.login_options.legacy_login = allowed;
+    }
+
+    /// Allows registering with challenge auth over a connection anyone can
+    /// read, which sends the stored key in the clear.
+    pub fn set_insecure_register(&mut self, allowed: bool) {
+        self.login_options.insecure_register = allowed;
+    }
+
     // Helper method to load assets for a specific character type
     fn load_character_assets(
         ctx: &mut Context,
@@ -553,56 +699,90 @@ impl GameState {
         self.handle_login_input(ctx);
 
         // Check for Enter key to submit login/registration
+        // Logging in is pointless if the handshake found the server incompatible
         if ctx.keyboard.is_key_just_pressed(KeyCode::Return)
             && !self.username.is_empty()
             && !self.password.is_empty()
+            && !matches!(self.auth_status, AuthStatus::Waiting)
+            && !matches!(
+                self.server_compatibility(),
+                Some(Compatibility::Incompatible(_))
+            )
         {
-            match self.auth_action {
-                AuthAction::Login => {
-                    log::info!(
-                        "Sending login for '{}' with password '{}'",
-                        self.username,
-                        self.password
-                    );
-                    let _ = self.nc.send(protocol::ClientToServer::Login(
-                        self.username.clone(),
-                        self.password.clone(),
-                    ));
-                }
-                AuthAction::Register => {
-                    log::info!(
-                        "Sending registration for '{}' with password '{}'",
-                        self.username,
-                        self.password
-                    );
-                    let _ = self.nc.send(protocol::ClientToServer::Register(
-                        self.username.clone(),
-                        self.password.clone(),
-                    ));
-                }
-            }
+            self.auth_status = match self.send_auth() {
+                Ok(()) => AuthStatus::Waiting,
+                Err(reason) => AuthStatus::Failed(reason),
+            };
         }
 
         // Handle authentication
-        let line = self.nc.recv();
+        let replies = self.nc.recv();
         use crate::net::NCError::*;
-        match line {
-            Ok(ok) => {
-                println!("{}", ok);
-                // Check if login was successful and transition to InGame
-                if ok.contains("Logged in") || ok.contains("Registered user") {
-                    log::info!("Authentication successful, entering game.");
-
-                    // Set the player's name to the username used for login
-                    self.players.self_player.name = self.username.clone();
-                    log::trace!("Set player name to: {}", self.username);
-
-                    // Send the username to the server for identification
-                    let user_event = protocol::ClientToServer::SetUsername(self.username.clone());
-                    let _ = self.nc.send(user_event);
-
-                    // Transition to InGame stage
-                    self.stage = Stage::InGame;
+        match replies {
+            Ok(replies) => {
+                for reply in replies {
+                    log::debug!("Pre-auth reply: {:?}", reply);
+
+                    // Anything arriving after the login reply belongs to the game world
+                    if let Stage::InGame = self.stage {
+                        self.handle_server_message(reply);
+                        continue;
+                    }
+
+                    use protocol::ServerToClient::*;
+                    match reply {
+                        LoginOk | RegisterOk => {
+                            log::info!("Authentication successful, entering game.");
+
+                            // Set the player's name to the username used for login
+                            self.players.self_player.name = self.username.clone();
+                            log::trace!("Set player name to: {}", self.username);
+
+                            // Send the username to the server for identification
+                            let user_event =
+                                protocol::ClientToServer::SetUsername(self.username.clone());
+                            let _ = self.nc.send(user_event);
+
+                            // Transition to InGame stage
+                            self.auth_status = AuthStatus::Idle;
+                            self.stage = Stage::InGame;
+                        }
+                        LoginFailed(reason) | RegisterFailed(reason) => {
+                            log::warn!("Authentication failed: {}", reason);
+                            self.auth_status = AuthStatus::Failed(reason);
+                        }
+                        Kicked(reason) => {
+                            log::warn!("Kicked before entering the game: {}", reason);
+                            self.auth_status = AuthStatus::Failed(format!("Kicked: {}", reason));
+                        }
+                        AuthChallenge(challenge) => {
+                            // The proof itself is worked out off the game loop
+                            match auth::check_challenge(&challenge) {
+                                Ok(()) => {
+                                    let answer = self.nc.answer_challenge(
+                                        self.username.clone(),
+                                        self.password.clone(),
+                                        challenge,
+                                    );
+                                    if let Err(e) = answer {
+                                        self.auth_status = AuthStatus::Failed(format!(
+                                            "Could not answer the login: {:?}",
+                                            e
+                                        ));
+                                    }
+                                }
+                                Err(e) => {
+                                    log::warn!("Refusing the login challenge: {}", e);
+                                    self.auth_status = AuthStatus::Failed(format!(
+                                        "Unsafe login challenge: {}",
+                                        e
+                                    ));
+                                }
+                            }
+                        }
+                        Session(token) => self.nc.set_session(self.username.clone(), token),
+                        _ => {}
+                    }
                 }
             }
             Err(err) => match err {
@@ -612,6 +792,10 @@ impl GameState {
                 }
                 ConnectionError(e) => {
                     log::error!("Connection error: {}", e);
+                    // Nobody is left to answer the attempt
+                    if let AuthStatus::Waiting = self.auth_status {
+                        self.auth_status = AuthStatus::Failed(format!("Connection lost: {}", e));
+                    }
                 }
                 SendError => {
                     log::error!("Some random send error???")
@@ -620,6 +804,29 @@ impl GameState {
         }
     }
 
+    // Starts the login or registration picked on the login screen
+    fn send_auth(&mut self) -> Result<(), String> {
+        let Some(info) = self.nc.server_info() else {
+            return Err("Still waiting for the server to say hello".to_string());
+        };
+        let message = auth_message(
+            &self.auth_action,
+            &self.username,
+            &self.password,
+            info,
+            &self.login_options,
+        )?;
+
+        log::info!(
+            "Sending {} for '{}'",
+            self.auth_action.verb(),
+            self.username
+        );
+        self.nc
+            .send(message)
+            .map_err(|e| format!("Could not reach the server: {:?}", e))
+    }
+
     // New method to handle keyboard input for login fields
     fn handle_login_input(&mut self, ctx: &Context) {
         // Switch focus with Tab key
@@ -771,98 +978,123 @@ impl GameState {
                 ));
 
                 // Also display the message for the local player
-                self.players
-                    .set_player_chat_message(&self.username, self.chat_input.clone());
+                let shown = self.expand_placeholders(&self.chat_input);
+                self.players.set_player_chat_message(&self.username, shown);
 
                 // Clear the chat input
                 self.chat_input.clear();
             }
         }
 
-        // Get the delta time for animations
-        let delta_time = ctx.time.delta().as_secs_f32();
+        let movement = self.handle_game_input(ctx);
+        self.run_ticks(ctx, &movement);
+        self.outbox.flush(std::time::Instant::now(), &mut self.nc);
+
+        // Process network messages regardless of chat state
+        self.process_network_messages();
+    }
 
-        // Handle chat input if in chat mode
+    // Reads the keys held this frame; while chatting they type instead of moving us
+    fn handle_game_input(&mut self, ctx: &Context) -> MovementState {
         if self.is_chatting {
             self.handle_chat_input(ctx);
 
-            // Even when chatting, we still need to update other players' animations
-            // Create a no-movement state for the local player
-            let no_movement = MovementState {
+            // Other players keep animating, so we still tick with no movement
+            return MovementState {
                 is_moving: false,
                 direction: self.players.self_player.direction,
                 dx: 0,
                 dy: 0,
             };
+        }
 
-            // Update player animations but not position
-            self.players
-                .update(&no_movement, &self.map, GRID_SIZE, delta_time);
-        } else {
-            // Only process normal game input if not chatting
-            // Get input
-            let movement = input::handle_input(ctx);
-            let key_press = input::handle_key_press(ctx);
-
-            // Send movement to server
-            input::send_movement_to_server(
-                &mut self.nc,
-                &movement,
-                &self.username,
-                &self.players.self_player.pos,
-            );
+        // Handle character switching
+        if input::handle_key_press(ctx).switch_character {
+            self.players.switch_character();
+        }
+        input::handle_input(ctx)
+    }
 
-            // Update player position
-            self.players
-                .update(&movement, &self.map, GRID_SIZE, delta_time);
+    // Runs as many fixed simulation ticks as the time since the last frame calls for,
+    // so the world moves at the same speed whatever the frame rate
+    fn run_ticks(&mut self, ctx: &Context, held: &MovementState) {
+        for _ in 0..ticks_due(&mut self.accumulator, ctx.time.delta()) {
+            self.tick(held);
+        }
+    }
 
-            // Handle character switching
-            if key_press.switch_character {
-                self.players.switch_character();
-            }
+    // How far we are between the last tick and the next, for drawing in between
+    fn tick_progress(&self) -> f32 {
+        self.accumulator.as_secs_f32() / TICK.as_secs_f32()
+    }
 
-            // Check for door transitions
-            let player_pos = self.players.self_player.pos;
-            if let Some((new_room, new_x, new_y, facing)) =
-                self.map
-                    .check_door_transition(player_pos.x, player_pos.y, GRID_SIZE)
+    // One fixed step of the world: our movement, collisions, doors and animations
+    fn tick(&mut self, held: &MovementState) {
+        let delta_time = TICK.as_secs_f32();
+        let online = matches!(self.stage, Stage::InGame);
+        let movement = self.stepper.step(held, delta_time);
+
+        // Update player position without waiting for the server
+        self.players
+            .update(&movement, &self.map, GRID_SIZE, delta_time);
+
+        // Servers that acknowledge inputs get them numbered, the rest our position
+        if online && self.server_acks_inputs() {
+            if let Some(input) = self
+                .prediction
+                .input(movement.dx, movement.dy, movement.direction)
             {
-                // Update the current room
-                self.map.current_room = new_room;
+                self.outbox.input(input);
+            }
+        } else if online {
+            let player = &self.players.self_player;
+            self.outbox.move_to(player.pos, player.direction);
+        }
 
-                // Update player position to the new coordinates
-                self.players.self_player.pos.x = new_x as i32;
-                self.players.self_player.pos.y = new_y as i32;
-                self.players.self_player.direction = facing;
+        // Check for door transitions
+        let player_pos = self.players.self_player.pos;
+        if let Some((new_room, new_x, new_y, facing)) =
+            self.map
+                .check_door_transition(player_pos.x, player_pos.y, GRID_SIZE)
+        {
+            // Update the current room
+            self.map.current_room = new_room;
+
+            // Move the player to the middle of the new tile without sliding there
+            let pos = Position::new(
+                new_x as i32 * GRID_SIZE + GRID_SIZE / 2,
+                new_y as i32 * GRID_SIZE + GRID_SIZE / 2,
+            );
+            self.players.self_player.teleport(pos);
+            self.players.self_player.direction = facing;
+            self.players.self_player.zone = self.map.zone_path();
+
+            if online {
+                // Servers with zones move us over, so others see us in the right room
+                if self.server_has_zones() {
+                    self.outbox.goto(ZoneLink {
+                        zones: self.map.zone_path(),
+                        pos,
+                    });
+                }
 
-                // Send the new position to the server
+                // Send the new position to the server, which the inputs so far knew nothing of
+                self.prediction.teleported();
                 self.send_absolute_position();
             }
         }
 
-        // Process network messages regardless of chat state
-        for _ in 0..3 {
-            self.process_network_messages();
+        // Simulate other players in offline mode
+        if !online {
+            self.simulate_other_players(delta_time);
         }
     }
 
     // Helper method to send the player's absolute position to the server
     fn send_absolute_position(&mut self) {
-        // Send username for identification
-        let username_event = protocol::ClientToServer::SetUsername(self.username.clone());
-        let _ = self.nc.send(username_event);
-
-        // Send current facing direction
-        let facing_event =
-            protocol::ClientToServer::AttemptPlayerFacingChange(self.players.self_player.direction);
-        let _ = self.nc.send(facing_event);
-
-        // Send absolute position
-        let move_event = protocol::ClientToServer::SetPosition(
-            self.players.self_player.pos.x,
-            self.players.self_player.pos.y,
-        );
-        let _ = self.nc.send(move_event);
+        // Goes out with the next batch, ahead of any movement after it
+        let player = &self.players.self_player;
+        self.outbox.teleport(player.pos, player.direction);
 
         // Log the position being sent
         log::trace!(
@@ -872,236 +1104,237 @@ impl GameState {
         );
     }
 
+    // Whether the server acknowledges numbered inputs, see the `prediction` module
+    fn server_acks_inputs(&self) -> bool {
+        self.nc
+            .server_info()
+            .is_some_and(|info| info.has_capability(handshake::INPUT_ACK_CAPABILITY))
+    }
+
+    // Whether the server keeps track of rooms, see `Map::zone_path`
+    fn server_has_zones(&self) -> bool {
+        self.nc
+            .server_info()
+            .is_some_and(|info| info.has_capability(handshake::ZONES_CAPABILITY))
+    }
+
     // Method to process network messages for other players
     fn process_network_messages(&mut self) {
-        // Only process messages if we're not in offline mode
-        if self.nc.is_offline() {
-            return;
-        }
+        // Receive every complete message that arrived since the last frame
+        let messages = match self.nc.recv() {
+            Ok(messages) => messages,
+            Err(crate::net::NCError::ConnectionError(e)) => {
+                log::error!("Connection error: {}", e);
+                // The server resends everyone once we are back, and gets our position anew
+                self.players.clear_others();
+                self.prediction.teleported();
+                // Whatever was waiting is stale by the time we are back
+                self.outbox = Outbox::new();
+
+                if needs_login_again(&self.stage, self.nc.has_session()) {
+                    self.auth_status =
+                        AuthStatus::Failed(format!("Connection lost ({}), please log in again", e));
+                    self.stage = Stage::PreAuth;
+                }
+                return;
+            }
+            Err(_) => return,
+        };
 
-        // Process all available messages in a loop
-        let mut message_count = 0;
-        let max_messages_per_frame = 10; // Limit to prevent infinite loops
+        for server_message in messages {
+            log::trace!("Server message: {:?}", server_message);
+            self.handle_server_message(server_message);
+        }
+    }
 
-        loop {
-            // Try to receive a message from the server
-            match self.nc.recv() {
-                Ok(message) => {
-                    message_count += 1;
+    // Apply a single decoded server message to the game world
+    fn handle_server_message(&mut self, server_message: protocol::ServerToClient) {
+        match server_message {
+            protocol::ServerToClient::PlayerJoined(username, position, facing, zone) => {
+                // Skip if this is our own username
+                if username == self.username {
+                    log::info!("Skipping own player joined message: {}", username);
+                    return;
+                }
 
-                    // Skip "Invalid protocol" messages
-                    if message.contains("Invalid protocol") {
-                        continue;
-                    }
+                log::info!(
+                    "Player joined: {} at ({}, {})",
+                    username,
+                    position.x,
+                    position.y
+                );
+                self.players
+                    .add_or_update_player(username, position, facing, zone);
 
-                    // Log raw messages for debugging
-                    log::trace!("Raw server message: {}", message);
-
-                    // Parse the message to see if it's a player update
-                    if let Some(server_message) = self.nc.parse_server_message(&message) {
-                        match server_message {
-                            protocol::ServerToClient::PlayerJoined(username, position, facing) => {
-                                // Skip if this is our own username
-                                if username == self.username {
-                                    log::info!("Skipping own player joined message: {}", username);
-                                    continue;
-                                }
+                // Debug print all players
+                self.players.debug_print_players();
+            }
+            protocol::ServerToClient::PlayerLeft(username) => {
+                log::info!("Player left: {}", username);
+                self.players.remove_player(&username);
 
-                                log::info!(
-                                    "Player joined: {} at ({}, {})",
-                                    username,
-                                    position.x,
-                                    position.y
-                                );
-                                self.players
-                                    .add_or_update_player(username, position, facing);
-
-                                // Debug print all players
-                                self.players.debug_print_players();
-                            }
-                            protocol::ServerToClient::PlayerLeft(username) => {
-                                log::info!("Player left: {}", username);
-                                self.players.remove_player(&username);
+                // Debug print all players
+                self.players.debug_print_players();
+            }
+            protocol::ServerToClient::PlayerMoved(username, position, facing, zone) => {
+                // Skip if this is our own username
+                if username == self.username {
+                    log::trace!("Skipping own player moved message: {}", username);
+                    return;
+                }
 
-                                // Debug print all players
-                                self.players.debug_print_players();
-                            }
-                            protocol::ServerToClient::PlayerMoved(username, position, facing) => {
-                                // Skip if this is our own username
-                                if username == self.username {
-                                    log::trace!("Skipping own player moved message: {}", username);
-                                    continue;
-                                }
+                log::trace!(
+                    "Player moved: {} to ({}, {})",
+                    username,
+                    position.x,
+                    position.y
+                );
 
-                                // Only log position updates if the position is not (0,0)
-                                // This is to avoid logging facing-only updates
-                                if position.x != 0 || position.y != 0 {
-                                    log::trace!(
-                                        "Player moved: {} to ({}, {})",
-                                        username,
-                                        position.x,
-                                        position.y
-                                    );
-                                }
+                // Update the player's position and facing
+                self.players
+                    .update_player_position(&username, position, facing, zone);
+            }
+            protocol::ServerToClient::PlayerFaced(username, facing) => {
+                if username == self.username {
+                    return;
+                }
 
-                                // Update the player's position and facing
-                                self.players
-                                    .update_player_position(&username, position, facing);
-                            }
-                            protocol::ServerToClient::ChatMessage(username, message) => {
-                                log::info!("Chat message from {}: {}", username, message);
+                self.players.update_player_facing(&username, facing);
+            }
+            protocol::ServerToClient::EntitySpawned {
+                id,
+                kind,
+                pos,
+                facing,
+            } => {
+                // The server may include our own player in the world
+                if kind == protocol::EntityKind::Player(self.username.clone()) {
+                    log::info!("Skipping own entity {}", id);
+                    return;
+                }
 
-                                // Display the chat message above the player
-                                self.players.set_player_chat_message(&username, message);
-                            }
-                            _ => {
-                                log::info!("Received other message type: {:?}", server_message);
-                            }
-                        }
-                    } else if message.contains("player_joined") || message.contains("player_moved")
-                    {
-                        // Try to extract the username and position manually
-                        let parts: Vec<&str> = message.split_whitespace().collect();
-                        log::trace!("Message parts: {:?}", parts);
-
-                        if parts.len() >= 5 {
-                            if let Some(pos) = parts
-                                .iter()
-                                .position(|&p| p == "player_joined" || p == "player_moved")
-                            {
-                                if pos + 4 < parts.len() {
-                                    let cmd = parts[pos];
-                                    let username = parts[pos + 1].to_string();
-
-                                    // Skip if this is our own username
-                                    if username == self.username {
-                                        log::info!("Skipping own player message: {}", username);
-                                        continue;
-                                    }
+                log::info!(
+                    "Entity {} spawned: {:?} at ({}, {})",
+                    id,
+                    kind,
+                    pos.x,
+                    pos.y
+                );
+                self.players.spawn_entity(id, kind, pos, facing);
+            }
+            protocol::ServerToClient::EntityMoved(id, pos, facing) => {
+                self.players.move_entity(id, pos, facing);
+            }
+            protocol::ServerToClient::EntityDespawned(id) => {
+                log::info!("Entity {} despawned", id);
+                self.players.despawn_entity(id);
+            }
+            protocol::ServerToClient::PositionAck(seq, position, _) => {
+                // Replay what the server has not seen yet on top of where it puts us
+                let Some(predicted) = self
+                    .prediction
+                    .reconcile(seq, position, &self.map, GRID_SIZE)
+                else {
+                    return;
+                };
+                let pos = &mut self.players.self_player.pos;
+                if predicted != *pos {
+                    log::debug!(
+                        "Server corrected our position from ({}, {}) to ({}, {})",
+                        pos.x,
+                        pos.y,
+                        predicted.x,
+                        predicted.y
+                    );
+                    *pos = predicted;
+                }
+            }
+            protocol::ServerToClient::LoginOk => {
+                // Only a resumed session logs in again while in game
+                log::info!("Session resumed after reconnecting");
 
-                                    if let (Ok(x), Ok(y)) = (
-                                        parts[pos + 2].parse::<i32>(),
-                                        parts[pos + 3].parse::<i32>(),
-                                    ) {
-                                        let facing_str = parts[pos + 4];
-                                        let facing = protocol::Facing::from_str(facing_str)
-                                            .expect("string not valid");
-
-                                        let position = protocol::Position::new(x, y);
-
-                                        if cmd == "player_joined" {
-                                            log::trace!(
-                                                "Manual parse - Player joined: {} at ({}, {})",
-                                                username,
-                                                x,
-                                                y
-                                            );
-                                            self.players
-                                                .add_or_update_player(username, position, facing);
-
-                                            // Debug print all players
-                                            self.players.debug_print_players();
-                                        } else {
-                                            log::trace!(
-                                                "Manual parse - Player moved: {} to ({}, {})",
-                                                username,
-                                                x,
-                                                y
-                                            );
-                                            self.players.update_player_position(
-                                                &username, position, facing,
-                                            );
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
+                // A new connection needs to hear who we are once more
+                let user_event = protocol::ClientToServer::SetUsername(self.username.clone());
+                let _ = self.nc.send(user_event);
+                self.send_absolute_position();
+            }
+            protocol::ServerToClient::LoginFailed(reason) => {
+                log::warn!("Could not resume the session: {}", reason);
+
+                // The token expired, so the password is needed again
+                self.nc.clear_session();
+                self.players.clear_others();
+                self.auth_status = AuthStatus::Failed(format!(
+                    "Session expired ({}), please log in again",
+                    reason
+                ));
+                self.stage = Stage::PreAuth;
+            }
+            protocol::ServerToClient::Session(token) => {
+                // Usually follows right after the login reply
+                self.nc.set_session(self.username.clone(), token);
+            }
+            protocol::ServerToClient::Kicked(reason) => {
+                log::warn!("Kicked from the game: {}", reason);
+
+                // Back to the login screen, which tells the player why
+                self.nc.clear_session();
+                self.players.clear_others();
+                self.auth_status = AuthStatus::Failed(format!("Kicked: {}", reason));
+                self.stage = Stage::PreAuth;
+            }
+            protocol::ServerToClient::ChatMessage(username, message) => {
+                log::info!("Chat message from {}: {}", username, message);
 
-                    // Break if we've processed too many messages in one frame
-                    if message_count >= max_messages_per_frame {
-                        break;
-                    }
-                }
-                Err(_) => {
-                    // No more messages to process
-                    break;
-                }
+                // Display the chat message above the player, with our own ~{} placeholders filled in
+                let message = self.expand_placeholders(&message);
+                self.players.set_player_chat_message(&username, message);
+            }
+            _ => {
+                log::info!("Received other message type: {:?}", server_message);
             }
         }
     }
 
+    // Fill in the client side `~{key}` placeholders of text about to be shown
+    fn expand_placeholders(&self, text: &str) -> String {
+        let player = &self.players.self_player;
+        template::expand_client_placeholders(text, |key| match key {
+            "name" => Some(player.name.clone()),
+            "x" => Some((player.pos.x / GRID_SIZE).to_string()),
+            "y" => Some((player.pos.y / GRID_SIZE).to_string()),
+            "room" => Some(self.map.current_room.to_string()),
+            "character" => Some(player.character_type.folder_name().to_string()),
+            _ => None,
+        })
+    }
+
     fn update_offline(&mut self, ctx: &Context) {
         // Check for chat toggle with tilde key
         if ctx.keyboard.is_key_just_pressed(KeyCode::Grave) {
             self.is_chatting = !self.is_chatting;
             if !self.is_chatting && !self.chat_input.is_empty() {
                 // Display the message for the local player
-                self.players
-                    .set_player_chat_message(&self.username, self.chat_input.clone());
+                let shown = self.expand_placeholders(&self.chat_input);
+                self.players.set_player_chat_message(&self.username, shown);
 
                 // Clear the chat input
                 self.chat_input.clear();
             }
         }
 
-        // Get the delta time for animations
-        let delta_time = ctx.time.delta().as_secs_f32();
+        let movement = self.handle_game_input(ctx);
+        self.run_ticks(ctx, &movement);
 
-        // Handle chat input if in chat mode
-        if self.is_chatting {
-            self.handle_chat_input(ctx);
-
-            // Even when chatting, we still need to update other players' animations
-            // Create a no-movement state for the local player
-            let no_movement = MovementState {
-                is_moving: false,
-                direction: self.players.self_player.direction,
-                dx: 0,
-                dy: 0,
-            };
-
-            // Update player animations but not position
-            self.players
-                .update(&no_movement, &self.map, GRID_SIZE, delta_time);
-        } else {
-            // Handle input
-            let movement = input::handle_input(ctx);
-            let key_press = input::handle_key_press(ctx);
-
-            // Update player position
-            self.players
-                .update(&movement, &self.map, GRID_SIZE, delta_time);
-
-            // Handle character switching
-            if key_press.switch_character {
-                self.players.switch_character();
-            }
-
-            // Check for door transitions
-            let player_pos = self.players.self_player.pos;
-            if let Some((new_room, door_x, door_y, facing)) =
-                self.map
-                    .check_door_transition(player_pos.x, player_pos.y, GRID_SIZE)
-            {
-                // Update the current room
-                self.map.current_room = new_room;
-
-                // Update player position to the new coordinates
-                self.players.self_player.pos.x = door_x as i32;
-                self.players.self_player.pos.y = door_y as i32;
-                self.players.self_player.direction = facing;
-            }
-        }
-
-        // Simulate other players in offline mode
-        self.simulate_other_players(delta_time);
+        // Nothing offline listens to the stand-in server, but its events still
+        // have to be taken off the network thread's queue
+        let _ = self.nc.recv();
     }
 
     // New method to simulate other players in offline mode
     fn simulate_other_players(&mut self, delta_time: f32) {
         // Only add simulated players if we don't have any yet
-        if self.players.other_players.is_empty() {
+        if self.players.entities.is_empty() {
             // Add a simulated player that moves around
             let start_pos = protocol::Position::new(
                 (GRID_SIZE as f32 * 3.5) as i32,
@@ -1111,6 +1344,7 @@ impl GameState {
                 "SimPlayer".to_string(),
                 start_pos,
                 protocol::Facing::South,
+                self.map.zone_path(),
             );
 
             // Add another simulated player that stays still
@@ -1122,6 +1356,19 @@ impl GameState {
                 "StaticPlayer".to_string(),
                 start_pos2,
                 protocol::Facing::East,
+                self.map.zone_path(),
+            );
+
+            // And an item lying around, to show non-player entities
+            let item_pos = protocol::Position::new(
+                (GRID_SIZE as f32 * 4.5) as i32,
+                (GRID_SIZE as f32 * 5.5) as i32,
+            );
+            self.players.spawn_entity(
+                protocol::EntityId(1),
+                protocol::EntityKind::Item("Key".to_string()),
+                item_pos,
+                protocol::Facing::South,
             );
         }
 
@@ -1138,36 +1385,35 @@ impl GameState {
                 DIRECTION = (DIRECTION + 1) % 4;
 
                 // Find the simulated player
-                for player in &mut self.players.other_players {
-                    if player.name == "SimPlayer" {
-                        // Update the direction
-                        use protocol::Facing::*;
-                        player.direction = match DIRECTION {
-                            0 => North,
-                            1 => East,
-                            2 => South,
-                            3 => West,
-                            _ => South,
-                        };
-
-                        // Move the player in that direction
-                        let (dx, dy) = match player.direction {
-                            North => (0, -1),
-                            East => (1, 0),
-                            South => (0, 1),
-                            West => (-1, 0),
-                        };
-
-                        player.pos.x += dx * GRID_SIZE;
-                        player.pos.y += dy * GRID_SIZE;
-                        player.is_moving = true;
-
-                        // Keep the player within bounds
-                        player.pos.x = player.pos.x.max(GRID_SIZE).min(GRID_SIZE * 10);
-                        player.pos.y = player.pos.y.max(GRID_SIZE).min(GRID_SIZE * 10);
-
-                        break;
-                    }
+                if let Some(player) = self.players.player_mut("SimPlayer") {
+                    // Pick the next direction
+                    use protocol::Facing::*;
+                    let facing = match DIRECTION {
+                        0 => North,
+                        1 => East,
+                        2 => South,
+                        3 => West,
+                        _ => South,
+                    };
+
+                    // Move the player in that direction
+                    let (dx, dy) = match facing {
+                        North => (0, -1),
+                        East => (1, 0),
+                        South => (0, 1),
+                        West => (-1, 0),
+                    };
+
+                    // Keep the player within bounds
+                    let pos = protocol::Position::new(
+                        (player.pos.x + dx * GRID_SIZE).clamp(GRID_SIZE, GRID_SIZE * 10),
+                        (player.pos.y + dy * GRID_SIZE).clamp(GRID_SIZE, GRID_SIZE * 10),
+                    );
+
+                    // Report it like the server would, so it walks there smoothly
+                    let zone = player.zone.clone();
+                    self.players
+                        .update_player_position("SimPlayer", pos, facing, zone);
                 }
             }
         }
@@ -1189,6 +1435,8 @@ impl GameState {
             self.draw_offline_indicator(ctx, &mut canvas);
         }
 
+        self.draw_connection_banner(ctx, &mut canvas);
+
         canvas.finish(ctx)?;
         Ok(())
     }
@@ -1393,6 +1641,53 @@ impl GameState {
                 ])
                 .color(Color::YELLOW),
         );
+
+        // Draw the handshake outcome if the server is not fully compatible
+        let handshake_status = match self.server_compatibility() {
+            Some(Compatibility::Incompatible(reason)) => Some((reason, Color::RED)),
+            Some(Compatibility::Degraded(reason)) => Some((reason, Color::YELLOW)),
+            _ => None,
+        };
+        if let Some((message, color)) = handshake_status {
+            let status_text = Text::new(message);
+            let status_width = status_text.dimensions(ctx).unwrap().w;
+
+            canvas.draw(
+                &status_text,
+                DrawParam::default()
+                    .dest([
+                        screen_width / 2.0 - status_width / 2.0,
+                        screen_height / 2.0 + 180.0,
+                    ])
+                    .color(color),
+            );
+        }
+
+        // Draw how the last login or registration attempt went
+        let auth_status = match &self.auth_status {
+            AuthStatus::Idle => None,
+            AuthStatus::Waiting => Some(("Waiting for the server...", Color::YELLOW)),
+            AuthStatus::Failed(reason) => Some((reason.as_str(), Color::RED)),
+        };
+        if let Some((message, color)) = auth_status {
+            let status_text = Text::new(message);
+            let status_width = status_text.dimensions(ctx).unwrap().w;
+
+            canvas.draw(
+                &status_text,
+                DrawParam::default()
+                    .dest([
+                        screen_width / 2.0 - status_width / 2.0,
+                        screen_height / 2.0 + 210.0,
+                    ])
+                    .color(color),
+            );
+        }
+    }
+
+    // How well we can talk to the server, once it has answered our hello
+    fn server_compatibility(&self) -> Option<Compatibility> {
+        self.nc.server_info().map(|info| info.compatibility())
     }
 
     fn draw_in_game(&self, ctx: &Context, canvas: &mut graphics::Canvas) {
@@ -1404,19 +1699,16 @@ impl GameState {
 
         // Center the camera on the player's center (not top-left corner)
         // Add half the player size to center on the player sprite
-        let player_center_x = self.players.self_player.pos.x + input::PLAYER_SIZE / 2;
-        let player_center_y = self.players.self_player.pos.y + input::PLAYER_SIZE / 2;
+        let alpha = self.tick_progress();
+        let (player_x, player_y) = self.players.self_player.draw_pos(alpha);
+        let player_center_x = player_x + (input::PLAYER_SIZE / 2) as f32;
+        let player_center_y = player_y + (input::PLAYER_SIZE / 2) as f32;
 
-        let camera_x = player_center_x - zoomed_width as i32 / 2;
-        let camera_y = player_center_y - zoomed_height as i32 / 2;
+        let camera_x = player_center_x - zoomed_width / 2.0;
+        let camera_y = player_center_y - zoomed_height / 2.0;
 
         // Set the camera view
-        canvas.set_screen_coordinates(Rect::new(
-            camera_x as f32,
-            camera_y as f32,
-            zoomed_width,
-            zoomed_height,
-        ));
+        canvas.set_screen_coordinates(Rect::new(camera_x, camera_y, zoomed_width, zoomed_height));
 
         // Draw the map first (so it's behind the player)
         self.map
@@ -1424,19 +1716,29 @@ impl GameState {
             .unwrap();
 
         // Draw all players
-        self.players.draw(ctx, canvas, &self.asset_manager).unwrap();
+        self.players
+            .draw(ctx, canvas, &self.asset_manager, alpha)
+            .unwrap();
 
         // Draw position info for debugging - fixed to the camera view
-        let pos_text = Text::new(format!(
+        let mut pos_text = format!(
             "Pos: ({:.1}, {:.1}) - Room: {}",
             self.players.self_player.pos.x, self.players.self_player.pos.y, self.map.current_room
-        ));
+        );
+        if let Some((rtt, jitter)) = self.nc.latency() {
+            pos_text.push_str(&format!(
+                " - RTT: {}ms (±{}ms)",
+                rtt.as_millis(),
+                jitter.as_millis()
+            ));
+        }
+        let pos_text = Text::new(pos_text);
 
         // Draw UI elements in screen coordinates by adding the camera position
         canvas.draw(
             &pos_text,
             DrawParam::default()
-                .dest([(camera_x + 10) as f32, (camera_y + 10) as f32])
+                .dest([camera_x + 10.0, camera_y + 10.0])
                 .color(Color::WHITE),
         );
 
@@ -1475,8 +1777,8 @@ impl GameState {
 
             // Reset to game coordinates
             canvas.set_screen_coordinates(Rect::new(
-                camera_x as f32,
-                camera_y as f32,
+                camera_x,
+                camera_y,
                 zoomed_width,
                 zoomed_height,
             ));
@@ -1495,8 +1797,8 @@ impl GameState {
 
             // Reset to game coordinates
             canvas.set_screen_coordinates(Rect::new(
-                camera_x as f32,
-                camera_y as f32,
+                camera_x,
+                camera_y,
                 zoomed_width,
                 zoomed_height,
             ));
@@ -1512,19 +1814,16 @@ impl GameState {
 
         // Center the camera on the player's center (not top-left corner)
         // Add half the player size to center on the player sprite
-        let player_center_x = self.players.self_player.pos.x + input::PLAYER_SIZE / 2;
-        let player_center_y = self.players.self_player.pos.y + input::PLAYER_SIZE / 2;
+        let alpha = self.tick_progress();
+        let (player_x, player_y) = self.players.self_player.draw_pos(alpha);
+        let player_center_x = player_x + (input::PLAYER_SIZE / 2) as f32;
+        let player_center_y = player_y + (input::PLAYER_SIZE / 2) as f32;
 
-        let camera_x = player_center_x - zoomed_width as i32 / 2;
-        let camera_y = player_center_y - zoomed_height as i32 / 2;
+        let camera_x = player_center_x - zoomed_width / 2.0;
+        let camera_y = player_center_y - zoomed_height / 2.0;
 
         // Set the camera view
-        canvas.set_screen_coordinates(Rect::new(
-            camera_x as f32,
-            camera_y as f32,
-            zoomed_width,
-            zoomed_height,
-        ));
+        canvas.set_screen_coordinates(Rect::new(camera_x, camera_y, zoomed_width, zoomed_height));
 
         // Draw the map first (so it's behind the player)
         self.map
@@ -1532,7 +1831,9 @@ impl GameState {
             .unwrap();
 
         // Draw all players
-        self.players.draw(ctx, canvas, &self.asset_manager).unwrap();
+        self.players
+            .draw(ctx, canvas, &self.asset_manager, alpha)
+            .unwrap();
 
         // Draw position info for debugging - fixed to the camera view
         let pos_text = Text::new(format!(
@@ -1544,7 +1845,7 @@ impl GameState {
         canvas.draw(
             &pos_text,
             DrawParam::default()
-                .dest([(camera_x + 10) as f32, (camera_y + 10) as f32])
+                .dest([camera_x + 10.0, camera_y + 10.0])
                 .color(Color::WHITE),
         );
 
@@ -1583,8 +1884,8 @@ impl GameState {
 
             // Reset to game coordinates
             canvas.set_screen_coordinates(Rect::new(
-                camera_x as f32,
-                camera_y as f32,
+                camera_x,
+                camera_y,
                 zoomed_width,
                 zoomed_height,
             ));
@@ -1603,8 +1904,8 @@ impl GameState {
 
             // Reset to game coordinates
             canvas.set_screen_coordinates(Rect::new(
-                camera_x as f32,
-                camera_y as f32,
+                camera_x,
+                camera_y,
                 zoomed_width,
                 zoomed_height,
             ));
@@ -1626,22 +1927,58 @@ impl GameState {
         );
     }
 
+    // Tells the player we lost the server while the client keeps retrying
+    fn draw_connection_banner(&self, ctx: &Context, canvas: &mut graphics::Canvas) {
+        let ConnectionState::Reconnecting { attempt, retry_at } = self.nc.connection_state() else {
+            return;
+        };
+
+        let screen_width = ctx.gfx.window().inner_size().width as f32;
+        let screen_height = ctx.gfx.window().inner_size().height as f32;
+        canvas.set_screen_coordinates(Rect::new(0.0, 0.0, screen_width, screen_height));
+
+        let banner = graphics::Mesh::new_rectangle(
+            ctx,
+            graphics::DrawMode::fill(),
+            Rect::new(0.0, 0.0, screen_width, 30.0),
+            Color::new(0.6, 0.1, 0.1, 0.8),
+        )
+        .unwrap();
+        canvas.draw(&banner, DrawParam::default());
+
+        let wait = retry_at.saturating_duration_since(std::time::Instant::now());
+        let message = if attempt == 0 {
+            "Connection lost, reconnecting...".to_string()
+        } else {
+            format!(
+                "Connection lost, reconnecting in {}s (attempt {})",
+                wait.as_secs() + 1,
+                attempt + 1
+            )
+        };
+        let text = Text::new(message);
+        let text_width = text.dimensions(ctx).unwrap().w;
+        canvas.draw(
+            &text,
+            DrawParam::default()
+                .dest([screen_width / 2.0 - text_width / 2.0, 8.0])
+                .color(Color::WHITE),
+        );
+    }
+
     // Method to handle chat input
     fn handle_chat_input(&mut self, ctx: &Context) {
         // Handle Enter key to send the message
         if ctx.keyboard.is_key_just_pressed(KeyCode::Return) {
             if !self.chat_input.is_empty() {
-                // In online mode, send the chat message to the server
-                if !self.nc.is_offline() {
-                    let chat_msg = format!("chat {}\r\n", self.chat_input);
-                    let _ = self.nc.send(protocol::ClientToServer::ChatMessage(
-                        self.chat_input.clone()
-                    ));
-                }
+                // Send the chat message to the server
+                let _ = self.nc.send(protocol::ClientToServer::ChatMessage(
+                    self.chat_input.clone()
+                ));
 
                 // Always display the message for the local player
-                self.players
-                    .set_player_chat_message(&self.username, self.chat_input.clone());
+                let shown = self.expand_placeholders(&self.chat_input);
+                self.players.set_player_chat_message(&self.username, shown);
 
                 // Clear the chat input and exit chat mode
                 self.chat_input.clear();
@@ -1666,3 +2003,113 @@ impl GameState {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::ClientToServer;
+
+    fn server(capabilities: &[&str]) -> handshake::ServerInfo {
+        handshake::ServerInfo::new(
+            handshake::PROTOCOL_VERSION,
+            capabilities.iter().map(|c| c.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn test_speed_does_not_depend_on_the_frame_rate() {
+        let right = MovementState {
+            is_moving: true,
+            direction: protocol::Facing::East,
+            dx: 1,
+            dy: 0,
+        };
+        let ms = Duration::from_millis;
+        for frame in [ms(1), ms(7), ms(16), ms(17), ms(33), ms(100)] {
+            let (mut accumulator, mut stepper) = (Duration::ZERO, input::Stepper::default());
+            let frames = 3000 / frame.as_millis() as u32;
+            let mut moved = 0;
+            for _ in 0..frames {
+                for _ in 0..ticks_due(&mut accumulator, frame) {
+                    let step = stepper.step(&right, TICK.as_secs_f32());
+                    // 60 px/s at 60 ticks per second
+                    assert_eq!(step.dx, 1);
+                    moved += 1;
+                }
+            }
+            // A pixel for every whole tick that passed
+            let ticks = (frame * frames).as_nanos() / TICK.as_nanos();
+            assert_eq!(moved as u128, ticks, "at {:?}", frame);
+        }
+    }
+
+    #[test]
+    fn test_ticks_after_a_stall_are_capped() {
+        let mut accumulator = Duration::ZERO;
+        assert_eq!(
+            ticks_due(&mut accumulator, Duration::from_secs(2)),
+            MAX_TICKS_PER_FRAME
+        );
+        assert_eq!(accumulator, Duration::ZERO);
+
+        // Time short of a tick waits for the next frame
+        assert_eq!(ticks_due(&mut accumulator, TICK / 2), 0);
+        assert_eq!(ticks_due(&mut accumulator, TICK - TICK / 2), 1);
+        assert_eq!(ticks_due(&mut accumulator, TICK * 3), 3);
+    }
+
+    #[test]
+    fn test_lost_connection_without_session_needs_login() {
+        assert!(needs_login_again(&Stage::InGame, false));
+        // Resumed by the network thread on its own
+        assert!(!needs_login_again(&Stage::InGame, true));
+        // Not logged in anyway, or nobody to log in to
+        assert!(!needs_login_again(&Stage::PreAuth, false));
+        assert!(!needs_login_again(&Stage::Offline, false));
+    }
+
+    #[test]
+    fn test_registration_is_refused_by_default() {
+        let info = server(&[handshake::CHALLENGE_AUTH_CAPABILITY]);
+        let options = LoginOptions::default();
+        let refused = auth_message(&AuthAction::Register, "bob", "hunter2", &info, &options);
+        assert!(refused.unwrap_err().contains("--insecure-register"));
+
+        // Logging in sends nothing secret
+        assert_eq!(
+            auth_message(&AuthAction::Login, "bob", "hunter2", &info, &options),
+            Ok(ClientToServer::AuthStart("bob".to_string()))
+        );
+
+        let options = LoginOptions {
+            insecure_register: true,
+            ..LoginOptions::default()
+        };
+        let registered = auth_message(&AuthAction::Register, "bob", "hunter2", &info, &options);
+        assert!(matches!(
+            registered,
+            Ok(ClientToServer::RegisterVerifier(name, _)) if name == "bob"
+        ));
+    }
+
+    #[test]
+    fn test_plaintext_needs_legacy_login() {
+        let info = server(&[]);
+        for action in [AuthAction::Login, AuthAction::Register] {
+            let refused = auth_message(&action, "bob", "pw", &info, &LoginOptions::default());
+            assert!(refused.unwrap_err().contains("--legacy-login"));
+        }
+
+        let options = LoginOptions {
+            legacy_login: true,
+            ..LoginOptions::default()
+        };
+        assert_eq!(
+            auth_message(&AuthAction::Login, "bob", "pw", &info, &options),
+            Ok(ClientToServer::Login("bob".to_string(), "pw".to_string()))
+        );
+        // Plaintext login does not allow sending a stored key either
+        let info = server(&[handshake::CHALLENGE_AUTH_CAPABILITY]);
+        assert!(auth_message(&AuthAction::Register, "bob", "pw", &info, &options).is_err());
+    }
+}
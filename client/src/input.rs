@@ -1,15 +1,14 @@
-use std::str::FromStr;
-
-use crate::net::NetClient;
 use ggez::{Context, input::keyboard::KeyCode};
-use protocol::Position;
 
 // Game constants
-pub const MOVEMENT_SPEED: i32 = 1;
+/// How fast players walk, in pixels per second
+pub const MOVEMENT_SPEED: f32 = 60.0;
 #[allow(unused)]
 pub const WORLD_SIZE: i32 = 800;
 pub const PLAYER_SIZE: i32 = 16;
 
+/// Which way the player goes; `handle_input` gives the held direction as -1, 0
+/// or 1 per axis, and `Stepper::step` turns that into whole pixels for one tick
 pub struct MovementState {
     pub is_moving: bool,
     pub direction: protocol::Facing,
@@ -17,6 +16,52 @@ pub struct MovementState {
     pub dy: i32,
 }
 
+/// Moves at `MOVEMENT_SPEED` in whole pixel steps, carrying the fractions from
+/// one tick to the next
+#[derive(Default)]
+pub struct Stepper {
+    carry_x: f32,
+    carry_y: f32,
+}
+
+impl Stepper {
+    pub fn step(&mut self, held: &MovementState, delta_time: f32) -> MovementState {
+        // Diagonals are no faster than straight lines
+        let length = ((held.dx * held.dx + held.dy * held.dy) as f32).sqrt();
+        let speed = if length > 0.0 {
+            MOVEMENT_SPEED * delta_time / length
+        } else {
+            0.0
+        };
+
+        // Letting go of an axis drops whatever fraction it had left
+        let advance = |carry: &mut f32, axis: i32| {
+            if axis == 0 {
+                *carry = 0.0;
+                return 0;
+            }
+            let total = *carry + axis as f32 * speed;
+            // Rounding must not cost a pixel: a 60th of a second is a hair under
+            // one at 60 px/s
+            let nearest = total.round();
+            let whole = if (total - nearest).abs() < 1e-3 {
+                nearest
+            } else {
+                total.trunc()
+            };
+            *carry = total - whole;
+            whole as i32
+        };
+
+        MovementState {
+            is_moving: held.is_moving,
+            direction: held.direction,
+            dx: advance(&mut self.carry_x, held.dx),
+            dy: advance(&mut self.carry_y, held.dy),
+        }
+    }
+}
+
 // Add a struct to track key press events
 pub struct KeyPressState {
     pub switch_character: bool,
@@ -44,22 +89,22 @@ pub fn handle_input(ctx: &Context) -> MovementState {
 
     // Check for arrow key presses and WASD
     if ctx.keyboard.is_key_pressed(KeyCode::Up) || ctx.keyboard.is_key_pressed(KeyCode::W) {
-        dy -= MOVEMENT_SPEED;
+        dy -= 1;
         direction = protocol::Facing::North;
         is_moving = true;
     }
     if ctx.keyboard.is_key_pressed(KeyCode::Down) || ctx.keyboard.is_key_pressed(KeyCode::S) {
-        dy += MOVEMENT_SPEED;
+        dy += 1;
         direction = protocol::Facing::South;
         is_moving = true;
     }
     if ctx.keyboard.is_key_pressed(KeyCode::Left) || ctx.keyboard.is_key_pressed(KeyCode::A) {
-        dx -= MOVEMENT_SPEED;
+        dx -= 1;
         direction = protocol::Facing::West;
         is_moving = true;
     }
     if ctx.keyboard.is_key_pressed(KeyCode::Right) || ctx.keyboard.is_key_pressed(KeyCode::D) {
-        dx += MOVEMENT_SPEED;
+        dx += 1;
         direction = protocol::Facing::East;
         is_moving = true;
     }
@@ -72,26 +117,57 @@ pub fn handle_input(ctx: &Context) -> MovementState {
     }
 }
 
-pub fn send_movement_to_server(
-    nc: &mut NetClient,
-    movement: &MovementState,
-    username: &str,
-    player_pos: &protocol::Position,
-) {
-    // Always send the username for identification
-    let _ = nc.send(protocol::ClientToServer::SetUsername(username.to_string()));
-
-    // Send facing direction to server regardless of movement
-    let facing_msg = format!("face {}\r\n", movement.direction);
-    let facing = protocol::Facing::from_str(&facing_msg).unwrap();
-    let _ = nc.send(protocol::ClientToServer::AttemptPlayerFacingChange(facing));
-
-    // Only send movement if actually moving
-    if movement.is_moving {
-        // Send absolute position instead of relative movement
-        let pos = Position::new(player_pos.x, player_pos.y);
-        let pos_event = protocol::ClientToServer::AttemptPlayerMove(pos);
-
-        let _ = nc.send(pos_event);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protocol::Facing;
+
+    const TICK: f32 = 1.0 / 60.0;
+
+    fn held(dx: i32, dy: i32) -> MovementState {
+        MovementState {
+            is_moving: dx != 0 || dy != 0,
+            direction: Facing::South,
+            dx,
+            dy,
+        }
+    }
+
+    #[test]
+    fn test_a_pixel_per_tick() {
+        let mut stepper = Stepper::default();
+        for _ in 0..120 {
+            let step = stepper.step(&held(-1, 0), TICK);
+            assert_eq!((step.dx, step.dy), (-1, 0));
+        }
+    }
+
+    #[test]
+    fn test_diagonals_are_no_faster() {
+        let mut stepper = Stepper::default();
+        let (mut x, mut y) = (0, 0);
+        for _ in 0..60 {
+            let step = stepper.step(&held(1, 1), TICK);
+            assert!(step.dx <= 1 && step.dy <= 1);
+            x += step.dx;
+            y += step.dy;
+        }
+        // 60 px/s spread over both axes, 42.4 px each
+        assert_eq!((x, y), (42, 42));
+    }
+
+    #[test]
+    fn test_fractions_carry_over() {
+        // Half a pixel a tick moves every other tick
+        let mut stepper = Stepper::default();
+        let steps: Vec<i32> = (0..6)
+            .map(|_| stepper.step(&held(0, 1), TICK / 2.0).dy)
+            .collect();
+        assert_eq!(steps, vec![0, 1, 0, 1, 0, 1]);
+
+        // Letting go forgets the half pixel
+        stepper.step(&held(0, 1), TICK / 2.0);
+        stepper.step(&held(0, 0), TICK / 2.0);
+        assert_eq!(stepper.step(&held(0, 1), TICK / 2.0).dy, 0);
     }
 }
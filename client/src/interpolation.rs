@@ -0,0 +1,199 @@
+// Smooth movement for everything the server moves.
+//
+// Position updates only arrive at the server's tick rate, 5 to 10 times a
+// second, so drawing each one as it comes makes other players hop from spot to
+// spot. Instead every update is kept as a snapshot with its arrival time, and
+// the entity is drawn where it was `INTERPOLATION_DELAY` ago, somewhere between
+// the two snapshots around that moment.
+//
+// When updates run late and the render time passes the newest snapshot, the
+// entity carries on at its last speed for up to `MAX_EXTRAPOLATION`. If nothing
+// arrives by then it most likely stopped, so it stays where that left it rather
+// than jumping back. A jump longer than `TELEPORT_DISTANCE`, such as walking through a door,
+// is not interpolated: the entity snaps straight to its new spot.
+
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use protocol::Position;
+
+/// How far in the past remote entities are drawn, two ticks at the planned 10
+/// ticks per second so one late update does not stop them
+pub const INTERPOLATION_DELAY: Duration = Duration::from_millis(200);
+
+/// Longest an entity keeps moving past its newest snapshot before it stops
+pub const MAX_EXTRAPOLATION: Duration = Duration::from_millis(100);
+
+/// Jumps longer than this many pixels snap instead of sliding
+pub const TELEPORT_DISTANCE: i32 = 64;
+
+// Time one server tick is assumed to take when an entity starts moving again
+const TICK: Duration = Duration::from_millis(100);
+
+// Snapshots kept per entity, far more than the delay ever needs
+const MAX_SNAPSHOTS: usize = 32;
+
+/// The recent positions of one entity, as reported by the server
+#[derive(Debug, Clone)]
+pub struct Snapshots {
+    // Oldest first, never empty
+    buffer: VecDeque<(Instant, Position)>,
+}
+
+impl Snapshots {
+    pub fn new(pos: Position, now: Instant) -> Self {
+        Self {
+            buffer: VecDeque::from([(now, pos)]),
+        }
+    }
+
+    /// Records a position update that arrived at `now`
+    pub fn push(&mut self, pos: Position, now: Instant) {
+        let (last_time, last_pos) = *self.buffer.back().expect("snapshots are never empty");
+
+        let distance = (pos.x - last_pos.x).abs().max((pos.y - last_pos.y).abs());
+        if distance > TELEPORT_DISTANCE {
+            self.buffer.clear();
+        } else if now.duration_since(last_time) > TICK {
+            // Entities that stood still send nothing, so have them set off from
+            // where they stood one tick ago rather than slide over the whole pause
+            self.buffer.push_back((now - TICK, last_pos));
+        }
+
+        self.buffer.push_back((now, pos));
+        while self.buffer.len() > MAX_SNAPSHOTS {
+            self.buffer.pop_front();
+        }
+    }
+
+    /// Where to draw the entity at `now`, and whether it is on the move there
+    pub fn sample(&mut self, now: Instant) -> (Position, bool) {
+        let Some(render_time) = now.checked_sub(INTERPOLATION_DELAY) else {
+            return (self.newest(), false);
+        };
+
+        // Snapshots before the one just ahead of the render time are done with
+        while self.buffer.len() > 2 && self.buffer[1].0 <= render_time {
+            self.buffer.pop_front();
+        }
+
+        let (from_time, from) = self.buffer[0];
+        let Some(&(to_time, to)) = self.buffer.get(1) else {
+            return (from, false);
+        };
+        if render_time <= from_time {
+            return (from, false);
+        }
+
+        let span = to_time.duration_since(from_time).as_secs_f32();
+        if render_time <= to_time {
+            let t = render_time.duration_since(from_time).as_secs_f32() / span;
+            (lerp(from, to, t), from != to)
+        } else {
+            // Late updates: keep going the way the last two snapshots went, for a little while
+            if span == 0.0 {
+                return (to, false);
+            }
+            let overshoot = render_time.duration_since(to_time);
+            let moving = overshoot <= MAX_EXTRAPOLATION && from != to;
+            let t = 1.0 + overshoot.min(MAX_EXTRAPOLATION).as_secs_f32() / span;
+            (lerp(from, to, t), moving)
+        }
+    }
+
+    /// The latest position the server reported
+    pub fn newest(&self) -> Position {
+        self.buffer.back().expect("snapshots are never empty").1
+    }
+}
+
+fn lerp(from: Position, to: Position, t: f32) -> Position {
+    let mix = |a: i32, b: i32| (a as f32 + (b - a) as f32 * t).round() as i32;
+    Position::new(mix(from.x, to.x), mix(from.y, to.y))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_drawn_between_snapshots_at_render_delay() {
+        let start = Instant::now();
+        let mut snapshots = Snapshots::new(Position::new(0, 0), start);
+        snapshots.push(Position::new(10, 0), start + ms(100));
+
+        // Render time at the first snapshot, halfway and at the second one
+        let at = |snapshots: &mut Snapshots, millis| snapshots.sample(start + ms(millis));
+        assert_eq!(at(&mut snapshots, 200), (Position::new(0, 0), false));
+        assert_eq!(at(&mut snapshots, 250), (Position::new(5, 0), true));
+        assert_eq!(at(&mut snapshots, 300), (Position::new(10, 0), true));
+        assert_eq!(snapshots.newest(), Position::new(10, 0));
+
+        // After standing still it sets off one tick before the update, not from the start
+        snapshots.push(Position::new(20, 0), start + ms(1000));
+        assert_eq!(at(&mut snapshots, 1150), (Position::new(15, 0), true));
+    }
+
+    #[test]
+    fn test_extrapolation_stops_at_its_limit() {
+        let start = Instant::now();
+        let mut snapshots = Snapshots::new(Position::new(0, 0), start);
+        snapshots.push(Position::new(10, 0), start + ms(100));
+
+        // Half a tick late it carries on at the same speed
+        let late = INTERPOLATION_DELAY + ms(150);
+        assert_eq!(snapshots.sample(start + late), (Position::new(15, 0), true));
+        let limit = INTERPOLATION_DELAY + ms(100) + MAX_EXTRAPOLATION;
+        assert_eq!(
+            snapshots.sample(start + limit),
+            (Position::new(20, 0), true)
+        );
+        // Past the limit it stops there instead of jumping back
+        assert_eq!(
+            snapshots.sample(start + limit + ms(1)),
+            (Position::new(20, 0), false)
+        );
+        assert_eq!(
+            snapshots.sample(start + limit + ms(500)),
+            (Position::new(20, 0), false)
+        );
+
+        // Never backwards on the way there
+        let mut last = 10;
+        for millis in (0..300).step_by(5) {
+            let (pos, _) = snapshots.sample(start + INTERPOLATION_DELAY + ms(100 + millis));
+            assert!(pos.x >= last, "{} after {}", pos.x, last);
+            last = pos.x;
+        }
+    }
+
+    #[test]
+    fn test_big_jumps_snap() {
+        let start = Instant::now();
+        let mut snapshots = Snapshots::new(Position::new(0, 0), start);
+        snapshots.push(Position::new(TELEPORT_DISTANCE, 0), start + ms(100));
+        snapshots.push(
+            Position::new(TELEPORT_DISTANCE, TELEPORT_DISTANCE + 1),
+            start + ms(200),
+        );
+
+        // No sliding over the jump, not even for what came before it
+        let jumped = Position::new(TELEPORT_DISTANCE, TELEPORT_DISTANCE + 1);
+        assert_eq!(snapshots.sample(start + ms(250)), (jumped, false));
+        assert_eq!(snapshots.sample(start + ms(400)), (jumped, false));
+
+        // A jump of exactly the limit still slides
+        let mut snapshots = Snapshots::new(Position::new(0, 0), start);
+        snapshots.push(Position::new(TELEPORT_DISTANCE, 0), start + ms(100));
+        assert_eq!(
+            snapshots.sample(start + ms(250)),
+            (Position::new(TELEPORT_DISTANCE / 2, 0), true)
+        );
+    }
+}
@@ -1,10 +1,16 @@
 // lib.rs - Library exports for the client crate
 
 pub mod assets;
+pub mod entity;
 pub mod filter;
 pub mod game_state;
 pub mod input;
+pub mod interpolation;
 pub mod map;
 pub mod net;
+pub mod netsim;
+pub mod outbox;
 pub mod player;
+pub mod prediction;
+pub mod transport;
 pub mod ui;
@@ -2,15 +2,21 @@
 use game_state::GameState;
 use ggez::{Context, GameResult, event};
 use simple_logger::SimpleLogger;
-use std::env;
+use std::{env, str::FromStr, time::Duration};
 
 mod assets;
+mod entity;
 mod filter;
 mod game_state;
 mod input;
+mod interpolation;
 mod map;
 mod net;
+mod netsim;
+mod outbox;
 mod player;
+mod prediction;
+mod transport;
 
 pub fn main() -> GameResult {
     let mut simp_log = SimpleLogger::new();
@@ -34,13 +40,31 @@ pub fn main() -> GameResult {
     // Parse command line arguments
     let args: Vec<String> = env::args().collect();
     let offline_mode = args.iter().any(|arg| arg == "--offline" || arg == "-o");
+    // Servers without challenge auth only accept the password in plaintext
+    let legacy_login = args.iter().any(|arg| arg == "--legacy-login");
+    // Registering with challenge auth sends the account's stored key unencrypted
+    let insecure_register = args.iter().any(|arg| arg == "--insecure-register");
+    // Unanswered pings in a row before the client reconnects
+    let max_missed_pongs = args
+        .iter()
+        .position(|arg| arg == "--max-missed-pongs")
+        .map(|pos| match args.get(pos + 1).map(|n| n.parse::<u32>()) {
+            Some(Ok(n)) if n > 0 => n,
+            _ => {
+                eprintln!("Error: --max-missed-pongs requires a positive number");
+                std::process::exit(1);
+            }
+        });
+
+    // Simulated network conditions, for reproducing lag bugs locally
+    let simulation = network_conditions(&args);
 
     // Check for export map argument
     let export_map = args
         .iter()
         .position(|arg| arg == "--export-map" || arg == "-e");
 
-    // If export map argument is provided, create a map and export it to JSON
+    // If export map argument is provided, create a map and export it to JSON or text
     if let Some(pos) = export_map {
         // Get the output path, default to "map.json" if not provided
         let output_path = if pos + 1 < args.len() && !args[pos + 1].starts_with('-') {
@@ -51,7 +75,7 @@ pub fn main() -> GameResult {
 
         println!("Exporting map to {}", output_path);
         let map = map::Map::new();
-        if let Err(e) = map.to_json(output_path) {
+        if let Err(e) = map.save(output_path) {
             eprintln!("Error exporting map: {}", e);
             std::process::exit(1);
         }
@@ -67,7 +91,7 @@ pub fn main() -> GameResult {
     // Store the path to the map file if import is requested
     let map_path = if let Some(pos) = import_map {
         if pos + 1 < args.len() && !args[pos + 1].starts_with('-') {
-            println!("Will import map from {}", &args[pos + 1]);
+            println!("Will import map from {}", args[pos + 1]);
             Some(args[pos + 1].clone())
         } else {
             eprintln!("Error: --import-map requires a file path");
@@ -77,6 +101,15 @@ pub fn main() -> GameResult {
         None
     };
 
+    // Tile ids of Tiled maps, for tilesets not laid out like the default table
+    let tile_table = match flag_value::<String>(&args, "--tile-table", "a file path") {
+        Some(path) => map::tiled::TileTable::from_file(&path).unwrap_or_else(|e| {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }),
+        None => map::tiled::TileTable::default(),
+    };
+
     // Print a message about offline mode if not specified
     if !offline_mode {
         println!("Note: You can run in offline mode with '--offline' or '-o' flag");
@@ -84,10 +117,25 @@ pub fn main() -> GameResult {
     }
 
     // Print a message about exporting and importing the map
-    println!("Note: You can export the map to JSON with '--export-map' or '-e' flag");
+    println!(
+        "Note: You can export the map to JSON, or text for any other extension, with '--export-map' or '-e' flag"
+    );
     println!("Example: cargo r --bin client -- --export-map [output_path]");
-    println!("Note: You can import a map from JSON with '--import-map' or '-i' flag");
+    println!(
+        "Note: You can import a map from JSON, text or Tiled (.tmj) with '--import-map' or '-i' flag"
+    );
     println!("Example: cargo r --bin client -- --import-map [input_path]");
+    println!("Note: Tiled maps read their tile ids through '--tile-table <file>' if given");
+    println!("Note: Add '--legacy-login' to log in to servers that want the password in plaintext");
+    println!(
+        "Note: Registering sends a key unencrypted, add '--insecure-register' to allow it anyway"
+    );
+    println!(
+        "Note: Simulate a bad network with '--sim-delay <ms>', '--sim-jitter <ms>', '--sim-loss <percent>',"
+    );
+    println!(
+        "      '--sim-reorder', '--sim-bandwidth <bytes/s>', '--sim-disconnect <secs>' and '--sim-seed <n>'"
+    );
     println!("Note: The game will use the default map from assets/default_map.json if available");
 
     let a = filter::Filters::new();
@@ -116,11 +164,14 @@ pub fn main() -> GameResult {
     let (mut ctx, event_loop) = cb.build()?;
 
     // Create game state based on mode and map import
-    let state = if let Some(path) = map_path {
+    let mut state = if let Some(path) = map_path {
         // Import map and create game state
-        match map::Map::from_json(&path) {
+        match map::Map::load(&path, &tile_table) {
             Ok(imported_map) => {
                 log::info!("Successfully imported map from {}", path);
+                for problem in map::validate::check(&imported_map) {
+                    log::warn!("Map problem: {}", problem);
+                }
                 if offline_mode {
                     GameState::new_offline_with_map(&mut ctx, imported_map)
                 } else {
@@ -139,10 +190,70 @@ pub fn main() -> GameResult {
         log::info!("Starting game in online mode");
         GameState::new(&mut ctx)
     };
+    state.set_legacy_login(legacy_login);
+    state.set_insecure_register(insecure_register);
+    if let Some(max_missed) = max_missed_pongs {
+        state.set_max_missed_pongs(max_missed);
+    }
+    if let Some(conditions) = simulation {
+        state.simulate_network(conditions);
+    }
 
     event::run(ctx, event_loop, state)
 }
 
+// Reads the value following `flag`, exiting with an error if it is missing or malformed
+fn flag_value<T: FromStr>(args: &[String], flag: &str, expected: &str) -> Option<T> {
+    let pos = args.iter().position(|arg| arg == flag)?;
+    match args.get(pos + 1).map(|value| value.parse::<T>()) {
+        Some(Ok(value)) => Some(value),
+        _ => {
+            eprintln!("Error: {} requires {}", flag, expected);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Collects the `--sim-*` flags, `None` if none of them were given
+fn network_conditions(args: &[String]) -> Option<netsim::Conditions> {
+    if !args.iter().any(|arg| arg.starts_with("--sim-")) {
+        return None;
+    }
+
+    let millis =
+        |flag| flag_value(args, flag, "a number of milliseconds").map(Duration::from_millis);
+    let loss: f32 = flag_value(args, "--sim-loss", "a percentage").unwrap_or(0.0);
+    if !(0.0..=100.0).contains(&loss) {
+        eprintln!("Error: --sim-loss must be between 0 and 100");
+        std::process::exit(1);
+    }
+    let disconnect: Option<f32> = flag_value(args, "--sim-disconnect", "a number of seconds");
+    let disconnect = match disconnect {
+        Some(secs) if secs.is_finite() && secs > 0.0 => Some(Duration::from_secs_f32(secs)),
+        Some(_) => {
+            eprintln!("Error: --sim-disconnect must be a positive number of seconds");
+            std::process::exit(1);
+        }
+        None => None,
+    };
+    let seed = flag_value(args, "--sim-seed", "a number").unwrap_or_else(|| {
+        // A fresh seed every run; it is logged so a run can be repeated
+        let mut seed = [0; 8];
+        let _ = getrandom::getrandom(&mut seed);
+        u64::from_le_bytes(seed)
+    });
+
+    Some(netsim::Conditions {
+        delay: millis("--sim-delay").unwrap_or_default(),
+        jitter: millis("--sim-jitter").unwrap_or_default(),
+        reorder: args.iter().any(|arg| arg == "--sim-reorder"),
+        loss: loss / 100.0,
+        bandwidth: flag_value(args, "--sim-bandwidth", "a number of bytes per second"),
+        disconnect,
+        seed,
+    })
+}
+
 // Implement the EventHandler trait for GameState
 impl event::EventHandler<ggez::GameError> for GameState {
     fn update(&mut self, ctx: &mut Context) -> Result<(), ggez::GameError> {
@@ -0,0 +1,682 @@
+// The text map format, for drawing rooms in a text editor, as in `map.sample`:
+//
+//   # = Wall
+//   X = Door -> "hub/cellar"
+//
+//   [entrance]
+//   ####
+//   #  #
+//   ##X#
+//
+//   [cellar tileset=dungeon music=drips.ogg]
+//   #X#
+//   # #
+//   ###
+//
+// The file opens with a legend, one `<glyph> = <kind>` per line. Kinds are
+// `Floor`, `Void` (nothing at all), `Door`, `Spawn` and the wall and decoration
+// names of the JSON format, written either way: `WallLeft` or `wall_left`.
+// A space is floor unless the legend says otherwise. Doors may name where they
+// lead, as a room of the map or its zone path. Any other zone, the zone alone
+// or a place like `"town/market@x3y4"` is left for the server to find.
+//
+// Rooms follow, separated by blank lines. Each may start with a `[name]` line
+// carrying its tileset and music; rooms without one are called `room0`,
+// `room1` and so on. Rows shorter than the widest one are padded with void.
+
+use std::fmt;
+
+use super::{DoorTarget, Map, MapObject, Room, TileType, ZONE, find_target, format};
+
+// Glyphs the exporter gives doors, by the index of the room they lead to
+const DOOR_GLYPHS: &str = "0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+// Glyphs the exporter gives doors out of the map, one for every place they lead
+const EXIT_GLYPHS: &str = "!$%&?^|<>";
+
+/// A mistake in a text map and where it is, both counted from 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn error(line: usize, column: usize, message: impl Into<String>) -> ParseError {
+    ParseError {
+        line,
+        column,
+        message: message.into(),
+    }
+}
+
+// What a glyph of the legend stands for
+#[derive(Clone)]
+enum Glyph {
+    Floor,
+    Void,
+    Wall(TileType),
+    Decoration(TileType),
+    Spawn,
+    Door(Option<Target>),
+}
+
+// Where a door leads, as written, and where it was written for errors
+#[derive(Clone)]
+struct Target {
+    name: String,
+    line: usize,
+    column: usize,
+}
+
+// One room of the file before it is turned into a `Room`
+struct Block<'a> {
+    header: Option<(usize, &'a str)>,
+    rows: Vec<(usize, &'a str)>,
+}
+
+/// Reads a map in the text format
+pub fn parse(text: &str) -> Result<Map, ParseError> {
+    let lines: Vec<&str> = text.lines().collect();
+
+    // The legend runs up to the first line that is not part of it
+    let mut legend: Vec<(char, Glyph)> = Vec::new();
+    let mut next = 0;
+    while let Some(&line) = lines.get(next) {
+        let number = next + 1;
+        if !line.is_empty() {
+            let Some((glyph, entry)) = split_legend(line) else {
+                break;
+            };
+            if legend.iter().any(|(known, _)| *known == glyph) {
+                return Err(error(
+                    number,
+                    1,
+                    format!("{:?} is already in the legend", glyph),
+                ));
+            }
+            legend.push((glyph, parse_entry(line, number, entry)?));
+        }
+        next += 1;
+    }
+
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut open = false;
+    for (index, &line) in lines.iter().enumerate().skip(next) {
+        let number = index + 1;
+        if line.is_empty() {
+            open = false;
+            continue;
+        }
+        if !open {
+            open = true;
+            let header = line.trim_end();
+            if header.starts_with('[') && header.ends_with(']') && header.len() > 1 {
+                blocks.push(Block {
+                    header: Some((number, line)),
+                    rows: Vec::new(),
+                });
+                continue;
+            }
+            blocks.push(Block {
+                header: None,
+                rows: Vec::new(),
+            });
+        }
+        if let Some(block) = blocks.last_mut() {
+            block.rows.push((number, line));
+        }
+    }
+    if blocks.is_empty() {
+        return Err(error(lines.len() + 1, 1, "The map has no rooms"));
+    }
+
+    let mut rooms = Vec::with_capacity(blocks.len());
+    // Doors to link up once every room has its name: room, object and target
+    let mut targets = Vec::new();
+    for (index, block) in blocks.into_iter().enumerate() {
+        let mut room = empty_room(format!("room{}", index), &block.rows);
+        if let Some((number, line)) = block.header {
+            parse_header(line, number, &mut room)?;
+            if block.rows.is_empty() {
+                return Err(error(number, 1, format!("Room {} has no tiles", room.name)));
+            }
+        }
+        if rooms.iter().any(|other: &Room| other.name == room.name) {
+            let (number, _) = block
+                .header
+                .or(block.rows.first().copied())
+                .unwrap_or_default();
+            return Err(error(
+                number,
+                1,
+                format!("There is already a room named {}", room.name),
+            ));
+        }
+
+        for (y, &(number, row)) in block.rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let glyph = match legend.iter().find(|(known, _)| *known == c) {
+                    Some((_, glyph)) => glyph,
+                    None if c == ' ' => &Glyph::Floor,
+                    None => {
+                        return Err(error(
+                            number,
+                            x + 1,
+                            format!("{:?} is not in the legend", c),
+                        ));
+                    }
+                };
+                match glyph {
+                    Glyph::Floor => room.floor[y][x] = true,
+                    Glyph::Void => {}
+                    Glyph::Wall(tile) => room.grid[y][x] = *tile,
+                    Glyph::Decoration(tile) => {
+                        room.floor[y][x] = true;
+                        room.decorations.push((x, y, *tile));
+                    }
+                    Glyph::Spawn => {
+                        room.floor[y][x] = true;
+                        room.objects.push(MapObject::Spawn { x, y });
+                    }
+                    Glyph::Door(target) => {
+                        room.floor[y][x] = true;
+                        room.grid[y][x] = TileType::Door;
+                        if let Some(target) = target {
+                            targets.push((index, room.objects.len(), target.clone()));
+                        }
+                        room.objects.push(MapObject::Door { x, y, to: None });
+                    }
+                }
+            }
+        }
+        rooms.push(room);
+    }
+
+    for (index, object, target) in targets {
+        let to = find_target(&rooms, &target.name).ok_or_else(|| {
+            error(
+                target.line,
+                target.column,
+                format!("There is no room {:?}", target.name),
+            )
+        })?;
+        let door = &mut rooms[index].objects[object];
+        if let MapObject::Door { x, y, to: door_to } = door {
+            match to {
+                DoorTarget::Room(to) => *door_to = Some(to),
+                DoorTarget::Link(link) => *door = MapObject::Exit { x: *x, y: *y, link },
+            }
+        }
+    }
+
+    Ok(Map {
+        rooms,
+        current_room: 0,
+    })
+}
+
+// `<glyph> = <entry>`, if the line is a legend line
+fn split_legend(line: &str) -> Option<(char, &str)> {
+    let glyph = line.chars().next()?;
+    let entry = line[glyph.len_utf8()..].trim_start().strip_prefix('=')?;
+    Some((glyph, entry))
+}
+
+// `<kind>` or `Door -> "<target>"`
+fn parse_entry(line: &str, number: usize, entry: &str) -> Result<Glyph, ParseError> {
+    let (kind, target) = match entry.split_once("->") {
+        Some((kind, target)) => (kind.trim(), Some(target.trim())),
+        None => (entry.trim(), None),
+    };
+    if kind.is_empty() {
+        return Err(error(
+            number,
+            column(line, entry),
+            "Expected a tile kind after '='",
+        ));
+    }
+
+    let name = snake_case(kind);
+    let glyph = match name.as_str() {
+        "floor" => Glyph::Floor,
+        "void" => Glyph::Void,
+        "spawn" => Glyph::Spawn,
+        "door" => Glyph::Door(None),
+        _ => {
+            if let Some(tile) = format::wall_from_name(&name) {
+                Glyph::Wall(tile)
+            } else if let Some(tile) = format::decoration_from_name(&name) {
+                Glyph::Decoration(tile)
+            } else {
+                return Err(error(
+                    number,
+                    column(line, kind),
+                    format!("Unknown tile kind {:?}", kind),
+                ));
+            }
+        }
+    };
+
+    let Some(target) = target else {
+        return Ok(glyph);
+    };
+    if !matches!(glyph, Glyph::Door(_)) {
+        return Err(error(
+            number,
+            column(line, target),
+            format!("{} does not lead anywhere, only doors do", kind),
+        ));
+    }
+    let name = target
+        .strip_prefix('"')
+        .and_then(|target| target.strip_suffix('"'))
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| {
+            error(
+                number,
+                column(line, target),
+                "Expected a quoted room or zone path, like \"hub/room1\"",
+            )
+        })?;
+    Ok(Glyph::Door(Some(Target {
+        name: name.to_string(),
+        line: number,
+        column: column(line, target),
+    })))
+}
+
+// `[name key=value ...]`, with the keys `tileset` and `music`
+fn parse_header(line: &str, number: usize, room: &mut Room) -> Result<(), ParseError> {
+    let inside = &line.trim_end()[1..line.trim_end().len() - 1];
+    for (index, word) in inside.split_whitespace().enumerate() {
+        match word.split_once('=') {
+            None if index == 0 => room.name = word.to_string(),
+            Some(("tileset", value)) if !value.is_empty() => room.tileset = value.to_string(),
+            Some(("music", value)) if !value.is_empty() => room.music = Some(value.to_string()),
+            _ => {
+                return Err(error(
+                    number,
+                    column(line, word),
+                    format!(
+                        "Expected the room name first, then tileset=<name> or music=<file>, not {:?}",
+                        word
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+// A room as big as its rows with nothing in it
+fn empty_room(name: String, rows: &[(usize, &str)]) -> Room {
+    let height = rows.len();
+    let width = rows
+        .iter()
+        .map(|(_, row)| row.chars().count())
+        .max()
+        .unwrap_or(0);
+    Room {
+        name,
+        tileset: format::DEFAULT_TILESET.to_string(),
+        music: None,
+        grid: vec![vec![TileType::Empty; width]; height],
+        floor: vec![vec![false; width]; height],
+        width,
+        height,
+        decorations: Vec::new(),
+        objects: Vec::new(),
+    }
+}
+
+// Column of `part`, a slice of `line`, counted in characters from 1
+fn column(line: &str, part: &str) -> usize {
+    let offset = part.as_ptr() as usize - line.as_ptr() as usize;
+    line[..offset].chars().count() + 1
+}
+
+// `WallLeft` and `wall_left` both to `wall_left`
+pub(super) fn snake_case(kind: &str) -> String {
+    let mut name = String::new();
+    for (index, c) in kind.chars().enumerate() {
+        if c.is_uppercase() && index > 0 && !name.ends_with('_') {
+            name.push('_');
+        }
+        name.extend(c.to_lowercase());
+    }
+    name
+}
+
+// `wall_left` to `WallLeft`
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// Writes a map in the text format.
+///
+/// Every cell gets one glyph, so a decoration or spawn point on a wall or door,
+/// or floor under a wall, is left out. Room names, tilesets and music with
+/// spaces in them cannot be written, as headers are split at spaces.
+pub fn export(map: &Map) -> Result<String, String> {
+    let mut legend: Vec<(char, String)> = Vec::new();
+    let mut rooms = Vec::with_capacity(map.rooms.len());
+    for room in &map.rooms {
+        // The name is the only word of the header without a '='
+        header_word("A room name", &room.name)?;
+        if room.name.contains('=') {
+            return Err(format!(
+                "A room name is {:?}, which cannot hold a '=' in the text format",
+                room.name
+            ));
+        }
+        let mut text = format!("[{}", room.name);
+        if room.tileset != format::DEFAULT_TILESET {
+            header_word(&format!("The tileset of {}", room.name), &room.tileset)?;
+            text.push_str(&format!(" tileset={}", room.tileset));
+        }
+        if let Some(music) = &room.music {
+            header_word(&format!("The music of {}", room.name), music)?;
+            text.push_str(&format!(" music={}", music));
+        }
+        text.push_str("]\n");
+
+        for y in 0..room.height {
+            for x in 0..room.width {
+                let (glyph, kind) = cell(map, room, x, y)?;
+                if glyph != ' ' && !legend.iter().any(|(known, _)| *known == glyph) {
+                    legend.push((glyph, kind));
+                }
+                text.push(glyph);
+            }
+            text.push('\n');
+        }
+        rooms.push(text);
+    }
+
+    let mut text = String::new();
+    for (glyph, kind) in legend {
+        text.push_str(&format!("{} = {}\n", glyph, kind));
+    }
+    text.push('\n');
+    text.push_str(&rooms.join("\n"));
+    Ok(text)
+}
+
+// Fails on values a room header cannot hold, as it is split at spaces
+fn header_word(what: &str, value: &str) -> Result<(), String> {
+    if value.is_empty() || value.contains(char::is_whitespace) {
+        return Err(format!(
+            "{} is {:?}, which must be a single word in the text format",
+            what, value
+        ));
+    }
+    Ok(())
+}
+
+// The glyph for one cell and its legend entry
+fn cell(map: &Map, room: &Room, x: usize, y: usize) -> Result<(char, String), String> {
+    let exit = room.objects.iter().find_map(|object| match object {
+        MapObject::Exit {
+            x: exit_x,
+            y: exit_y,
+            link,
+        } if *exit_x == x && *exit_y == y => Some(link),
+        _ => None,
+    });
+    if let Some(link) = exit {
+        // Exits to the same place share a glyph
+        let mut links = Vec::new();
+        for object in map.rooms.iter().flat_map(|room| &room.objects) {
+            if let MapObject::Exit { link, .. } = object
+                && !links.contains(&link)
+            {
+                links.push(link);
+            }
+        }
+        let index = links.iter().position(|known| *known == link).unwrap_or(0);
+        let glyph = EXIT_GLYPHS.chars().nth(index).ok_or_else(|| {
+            format!(
+                "Only {} places outside the map fit in the text format",
+                EXIT_GLYPHS.chars().count()
+            )
+        })?;
+        return Ok((glyph, format!("Door -> \"{}\"", link)));
+    }
+
+    if let Some(to) = room.door_at(x, y) {
+        let Some(to) = to else {
+            return Ok(('+', "Door".to_string()));
+        };
+        let glyph = DOOR_GLYPHS.chars().nth(to).ok_or_else(|| {
+            format!(
+                "Only {} rooms fit in the text format",
+                DOOR_GLYPHS.chars().count()
+            )
+        })?;
+        let destination = map.rooms.get(to).ok_or_else(|| {
+            format!(
+                "A door in room {} leads to room {}, which does not exist",
+                room.name, to
+            )
+        })?;
+        return Ok((glyph, format!("Door -> \"{}/{}\"", ZONE, destination.name)));
+    }
+
+    let tile = room.grid[y][x];
+    if let Some(name) = format::wall_name(tile) {
+        let glyph = match tile {
+            TileType::Wall2 => '{',
+            TileType::Wall3 => '}',
+            TileType::Wall4 => '_',
+            TileType::Wall5 => '/',
+            TileType::Wall6 => '\\',
+            _ => '#',
+        };
+        return Ok((glyph, camel_case(name)));
+    }
+    if tile == TileType::Door {
+        return Ok(('+', "Door".to_string()));
+    }
+    if !room.floor[y][x] {
+        return Ok(('~', "Void".to_string()));
+    }
+
+    if room.objects.contains(&MapObject::Spawn { x, y }) {
+        return Ok(('@', "Spawn".to_string()));
+    }
+    let decoration = room
+        .decorations
+        .iter()
+        .find(|&&(dx, dy, _)| dx == x && dy == y)
+        .and_then(|&(_, _, tile)| format::decoration_name(tile));
+    if let Some(name) = decoration {
+        return Ok(('*', camel_case(name)));
+    }
+    Ok((' ', "Floor".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(text: &str) -> (usize, usize) {
+        let error = parse(text).err().expect("the map should not parse");
+        (error.line, error.column)
+    }
+
+    #[test]
+    fn test_sample() {
+        let text = include_str!("../../../map.sample");
+        let map = parse(text).unwrap();
+        assert_eq!(map.rooms.len(), 1);
+
+        let room = &map.rooms[0];
+        assert_eq!(
+            (room.name.as_str(), room.width, room.height),
+            ("room0", 4, 4)
+        );
+        assert_eq!(room.grid[0][0], TileType::Wall);
+        assert_eq!(room.grid[1][1], TileType::Empty);
+        assert_eq!(room.grid[3][2], TileType::Door);
+        assert!(room.floor[1][1] && !room.floor[0][0]);
+        // The zone is not a room of the file, so the door is left for the server
+        assert_eq!(
+            room.objects,
+            vec![MapObject::Exit {
+                x: 2,
+                y: 3,
+                link: "hub@x0y0".parse().unwrap()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_door_targets() {
+        let text = "\
+# = Wall
+A = Door -> \"cellar\"
+B = Door -> \"hub/cellar\"
+C = Door -> \"town/market\"
+D = Door -> \"town@x3y4\"
+
+[hall]
+#ABCD#
+
+[cellar]
+######
+";
+        let map = parse(text).unwrap();
+        let link = |text: &str| text.parse().unwrap();
+        assert_eq!(
+            map.rooms[0].objects,
+            vec![
+                MapObject::Door {
+                    x: 1,
+                    y: 0,
+                    to: Some(1)
+                },
+                MapObject::Door {
+                    x: 2,
+                    y: 0,
+                    to: Some(1)
+                },
+                MapObject::Exit {
+                    x: 3,
+                    y: 0,
+                    link: link("town/market@x0y0")
+                },
+                MapObject::Exit {
+                    x: 4,
+                    y: 0,
+                    link: link("town@x3y4")
+                },
+            ]
+        );
+
+        let exported = export(&map).unwrap();
+        assert!(exported.contains("! = Door -> \"town/market@x0y0\""));
+        assert!(exported.contains("$ = Door -> \"town@x3y4\""));
+        assert_eq!(format::save(&parse(&exported).unwrap()), format::save(&map));
+
+        // Rooms of this zone have to be in the file
+        assert_eq!(parse_error("X = Door -> \"hub/attic\"\n\nX\n"), (1, 13));
+        assert_eq!(parse_error("X = Door -> \"attic\"\n\nX\n"), (1, 13));
+        assert_eq!(parse_error("X = Door -> \"town@x3\"\n\nX\n"), (1, 13));
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let text = "\
+# = Wall
+{ = WallLeft
+s = skull
+@ = Spawn
+~ = Void
+X = Door -> \"hub/cellar\"
+Y = Door -> \"hub\"
+
+[entrance music=wind.ogg]
+{##~
+{s #
+{@X#
+
+[cellar tileset=dungeon]
+#Y#
+# #
+###
+";
+        let map = parse(text).unwrap();
+        let exported = export(&map).unwrap();
+        let again = parse(&exported).unwrap();
+        assert_eq!(format::save(&again), format::save(&map));
+        // Exporting is stable once the glyphs are the exporter's own
+        assert_eq!(export(&again).unwrap(), exported);
+
+        let dir = env!("CARGO_MANIFEST_DIR");
+        let mut map = Map::from_json(&format!("{}/assets/large_room_map.json", dir)).unwrap();
+        let again = parse(&export(&map).unwrap()).unwrap();
+        // The skull on the door at (5, 5) has no glyph of its own
+        map.rooms[0]
+            .decorations
+            .retain(|&(x, y, _)| (x, y) != (5, 5));
+        assert_eq!(format::save(&again), format::save(&map));
+    }
+
+    #[test]
+    fn test_export_refuses_headers_it_cannot_parse() {
+        let mut map = parse("# = Wall\n\n###\n# #\n###\n").unwrap();
+        map.rooms[0].name = "Entrance Hall".to_string();
+        assert!(export(&map).unwrap_err().contains("\"Entrance Hall\""));
+        map.rooms[0].name = "a=b".to_string();
+        assert!(export(&map).is_err());
+        map.rooms[0].name = "entrance".to_string();
+        map.rooms[0].music = Some("rain and wind.ogg".to_string());
+        assert!(export(&map).is_err());
+        map.rooms[0].music = None;
+        assert!(export(&map).unwrap().contains("[entrance]"));
+    }
+
+    #[test]
+    fn test_errors_say_where() {
+        // Unknown glyph in a room
+        assert_eq!(parse_error("# = Wall\n\n###\n#?#\n###\n"), (4, 2));
+        // Unknown kind in the legend
+        assert_eq!(parse_error("# = Wal\n\n#\n"), (1, 5));
+        // Only doors lead somewhere
+        assert_eq!(parse_error("# = Wall -> \"hub\"\n\n#\n"), (1, 13));
+        // Unquoted and unknown targets
+        assert_eq!(parse_error("X = Door -> hub\n\nX\n"), (1, 13));
+        assert_eq!(parse_error("X = Door -> \"hub/attic\"\n\nX\n"), (1, 13));
+        // Headers
+        assert_eq!(parse_error("# = Wall\n\n[hall size=2]\n#\n"), (3, 7));
+        assert_eq!(parse_error("# = Wall\n\n[hall]\n#\n\n[hall]\n#\n"), (6, 1));
+        assert_eq!(parse_error("# = Wall\n"), (2, 1));
+
+        let error = parse("# = Wall\n\n#?#\n").err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line 3, column 2: '?' is not in the legend"
+        );
+    }
+}
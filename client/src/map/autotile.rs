@@ -0,0 +1,145 @@
+// Picks wall tiles from where the walls are, so layouts only say which cells
+// are solid.
+//
+// The tileset is seen from the front and a little above, so which piece a wall
+// cell needs depends on the cells around it:
+//
+// - floor below it: the front face, `Wall`, or an inner corner, `Wall5` or
+//   `Wall6`, where the wall turns down into the side wall of a room
+// - floor on one side only: a side wall, `Wall2` with the floor to its right
+//   and `Wall3` with the floor to its left
+// - floor above it or on both sides: the top of the wall, `Wall4`
+// - floor only diagonally: an outer corner, which continues the side wall on
+//   that side, or the top of the wall where walls meet in a T
+//
+// Cells outside the grid count as solid, so the edges of a room face inwards.
+
+use super::TileType;
+
+/// Wall tiles for every solid cell of `solid`, `Empty` everywhere else
+pub fn autotile(solid: &[Vec<bool>]) -> Vec<Vec<TileType>> {
+    let is_solid = |x: usize, y: usize, dx: isize, dy: isize| -> bool {
+        let (Some(x), Some(y)) = (x.checked_add_signed(dx), y.checked_add_signed(dy)) else {
+            return true;
+        };
+        solid
+            .get(y)
+            .and_then(|row| row.get(x))
+            .copied()
+            .unwrap_or(true)
+    };
+
+    solid
+        .iter()
+        .enumerate()
+        .map(|(y, row)| {
+            (0..row.len())
+                .map(|x| {
+                    if !row[x] {
+                        return TileType::Empty;
+                    }
+                    let at = |dx, dy| is_solid(x, y, dx, dy);
+                    let (north, south) = (at(0, -1), at(0, 1));
+                    let (west, east) = (at(-1, 0), at(1, 0));
+
+                    if !south {
+                        if west && at(-1, 1) && at(-2, 1) {
+                            TileType::Wall5
+                        } else if east && at(1, 1) && at(2, 1) {
+                            TileType::Wall6
+                        } else {
+                            TileType::Wall
+                        }
+                    } else if west && !east {
+                        TileType::Wall2
+                    } else if east && !west {
+                        TileType::Wall3
+                    } else if !north || !west {
+                        TileType::Wall4
+                    } else {
+                        let open_east = !at(1, -1) || !at(1, 1);
+                        let open_west = !at(-1, -1) || !at(-1, 1);
+                        match (open_west, open_east) {
+                            (false, true) => TileType::Wall2,
+                            (true, false) => TileType::Wall3,
+                            _ => TileType::Wall4,
+                        }
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use TileType::*;
+
+    fn tiles(rows: &[&str]) -> Vec<Vec<TileType>> {
+        let solid: Vec<Vec<bool>> = rows
+            .iter()
+            .map(|row| row.chars().map(|c| c == '#').collect())
+            .collect();
+        autotile(&solid)
+    }
+
+    #[test]
+    fn test_box_room() {
+        let mut rows = vec!["############"];
+        rows.extend(["#..........#"; 10]);
+        rows.push("############");
+        let grid = tiles(&rows);
+
+        assert_eq!(
+            grid[0],
+            [
+                Wall2, Wall5, Wall, Wall, Wall, Wall, Wall, Wall, Wall, Wall, Wall6, Wall3
+            ]
+        );
+        for row in &grid[1..11] {
+            assert_eq!(row[0], Wall2);
+            assert_eq!(row[11], Wall3);
+            assert!(row[1..11].iter().all(|&tile| tile == Empty));
+        }
+        assert_eq!(
+            grid[11],
+            [
+                Wall2, Wall4, Wall4, Wall4, Wall4, Wall4, Wall4, Wall4, Wall4, Wall4, Wall4, Wall3
+            ]
+        );
+    }
+
+    #[test]
+    fn test_outer_corner() {
+        // The wall turns at (1, 1), which only sees floor diagonally, and
+        // carries on as the side wall below it
+        let grid = tiles(&["#####", "#####", "##...", "##..."]);
+        assert_eq!(grid[1][1], Wall2);
+        assert_eq!(grid[2][1], Wall2);
+        assert_eq!(grid[3][1], Wall2);
+        // Next to it the front of the wall turns down into that side wall
+        assert_eq!(grid[1][2], Wall5);
+        assert_eq!(grid[1][3], Wall);
+        // Walls with no floor around them are tops
+        assert_eq!(grid[1][0], Wall4);
+
+        // The same turned the other way
+        let grid = tiles(&["#####", "#####", "...##", "...##"]);
+        assert_eq!(grid[1][3], Wall3);
+        assert_eq!(grid[2][3], Wall3);
+        assert_eq!(grid[1][2], Wall6);
+    }
+
+    #[test]
+    fn test_t_junction() {
+        // Floor diagonally on both sides makes the top of the wall
+        let grid = tiles(&[".#.", "###", "###"]);
+        assert_eq!(grid[1][1], Wall4);
+        assert_eq!(grid[0], [Empty, Wall4, Empty]);
+
+        // A wall standing alone in the floor shows its front
+        let grid = tiles(&["...", ".#.", "..."]);
+        assert_eq!(grid[1][1], Wall);
+    }
+}
@@ -0,0 +1,531 @@
+// The map file format.
+//
+// Maps used to be a straight serde dump of `Map`, so renaming or adding a
+// `TileType` broke every saved map. Files now carry a `version` and describe
+// each room as separate layers under names of their own:
+//
+// - `floor`: the ground tile of every cell, `null` where there is none
+// - `walls`: the wall tile of every cell, `null` where there is none
+// - `decorations`: things drawn on top of the floor, like skulls
+// - `objects`: doors with the room they lead to, exits with the zone link they
+//   lead to, and spawn points
+//
+// Alongside the layers every room has a name, a tileset and optional music.
+// Files without a version are the old dump; `load` upgrades them on the fly, so
+// the old `default_map.json` and `large_room_map.json` keep working.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{Map, MapObject, Room, TileType, link_doors};
+
+/// The version `save` writes
+pub const FORMAT_VERSION: u64 = 2;
+
+/// Tileset of rooms that do not name one
+pub const DEFAULT_TILESET: &str = "dungeon";
+
+#[derive(Serialize, Deserialize)]
+struct MapFile {
+    version: u64,
+    rooms: Vec<RoomFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RoomFile {
+    name: String,
+    #[serde(default = "default_tileset")]
+    tileset: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    music: Option<String>,
+    width: usize,
+    height: usize,
+    layers: Layers,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Layers {
+    floor: Vec<Vec<Option<String>>>,
+    walls: Vec<Vec<Option<String>>>,
+    #[serde(default)]
+    decorations: Vec<DecorationFile>,
+    #[serde(default)]
+    objects: Vec<ObjectFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DecorationFile {
+    x: usize,
+    y: usize,
+    kind: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum ObjectFile {
+    Door {
+        x: usize,
+        y: usize,
+        to: Option<usize>,
+    },
+    Exit {
+        x: usize,
+        y: usize,
+        to: String,
+    },
+    Spawn {
+        x: usize,
+        y: usize,
+    },
+}
+
+fn default_tileset() -> String {
+    DEFAULT_TILESET.to_string()
+}
+
+/// Reads a map file of any version
+pub fn load(json: &str) -> Result<Map, String> {
+    let mut value: Value =
+        serde_json::from_str(json).map_err(|e| format!("Invalid map JSON: {}", e))?;
+
+    let version = match value.get("version") {
+        None => 1,
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| format!("Invalid map version {}", version))?,
+    };
+    if version == 1 {
+        log::info!("Upgrading a version 1 map to version {}", FORMAT_VERSION);
+        value = upgrade_v1(value)?;
+    } else if version != FORMAT_VERSION {
+        return Err(format!(
+            "Unsupported map version {}, this client reads up to {}",
+            version, FORMAT_VERSION
+        ));
+    }
+
+    let file: MapFile = serde_json::from_value(value).map_err(|e| format!("Invalid map: {}", e))?;
+    from_file(file)
+}
+
+/// Writes a map in the current version
+pub fn save(map: &Map) -> Result<String, String> {
+    serde_json::to_string_pretty(&to_file(map))
+        .map_err(|e| format!("Failed to serialize map to JSON: {}", e))
+}
+
+// Layers to the grid the game plays on
+fn from_file(file: MapFile) -> Result<Map, String> {
+    let room_count = file.rooms.len();
+    let mut rooms = Vec::with_capacity(room_count);
+    for room in file.rooms {
+        let name = room.name;
+        let (width, height) = (room.width, room.height);
+        let layers = room.layers;
+        check_layer(&name, "floor", &layers.floor, width, height)?;
+        check_layer(&name, "walls", &layers.walls, width, height)?;
+
+        let mut grid = vec![vec![TileType::Empty; width]; height];
+        let mut floor = vec![vec![false; width]; height];
+        for y in 0..height {
+            for x in 0..width {
+                floor[y][x] = layers.floor[y][x].is_some();
+                if let Some(wall) = &layers.walls[y][x] {
+                    grid[y][x] = wall_from_name(wall)
+                        .ok_or_else(|| format!("Unknown wall {:?} in room {}", wall, name))?;
+                }
+            }
+        }
+
+        let mut decorations = Vec::new();
+        for decoration in layers.decorations {
+            let kind = decoration_from_name(&decoration.kind).ok_or_else(|| {
+                format!("Unknown decoration {:?} in room {}", decoration.kind, name)
+            })?;
+            decorations.push((decoration.x, decoration.y, kind));
+        }
+
+        let mut objects = Vec::new();
+        for object in layers.objects {
+            objects.push(match object {
+                ObjectFile::Door { x, y, to } => {
+                    if let Some(row) = grid.get_mut(y)
+                        && let Some(cell) = row.get_mut(x)
+                    {
+                        *cell = TileType::Door;
+                    }
+                    MapObject::Door { x, y, to }
+                }
+                ObjectFile::Exit { x, y, to } => {
+                    if let Some(row) = grid.get_mut(y)
+                        && let Some(cell) = row.get_mut(x)
+                    {
+                        *cell = TileType::Door;
+                    }
+                    let link = to
+                        .parse()
+                        .map_err(|_| format!("Invalid zone link {:?} in room {}", to, name))?;
+                    MapObject::Exit { x, y, link }
+                }
+                ObjectFile::Spawn { x, y } => MapObject::Spawn { x, y },
+            });
+        }
+
+        rooms.push(Room {
+            name,
+            tileset: room.tileset,
+            music: room.music,
+            grid,
+            floor,
+            width,
+            height,
+            decorations,
+            objects,
+        });
+    }
+
+    if rooms.is_empty() {
+        return Err("The map has no rooms".to_string());
+    }
+    Ok(Map {
+        rooms,
+        current_room: 0,
+    })
+}
+
+fn check_layer<T>(
+    room: &str,
+    layer: &str,
+    rows: &[Vec<T>],
+    width: usize,
+    height: usize,
+) -> Result<(), String> {
+    if rows.len() != height || rows.iter().any(|row| row.len() != width) {
+        return Err(format!(
+            "The {} layer of room {} is not {}x{}",
+            layer, room, width, height
+        ));
+    }
+    Ok(())
+}
+
+// The grid the game plays on back to layers
+fn to_file(map: &Map) -> MapFile {
+    let rooms = map
+        .rooms
+        .iter()
+        .map(|room| {
+            let floor = room
+                .floor
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|&f| f.then(|| "floor".to_string()))
+                        .collect()
+                })
+                .collect();
+            let walls = room
+                .grid
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|&tile| wall_name(tile).map(str::to_string))
+                        .collect()
+                })
+                .collect();
+            let decorations = room
+                .decorations
+                .iter()
+                .filter_map(|&(x, y, tile)| {
+                    decoration_name(tile).map(|kind| DecorationFile {
+                        x,
+                        y,
+                        kind: kind.to_string(),
+                    })
+                })
+                .collect();
+            let objects = room
+                .objects
+                .iter()
+                .map(|object| match *object {
+                    MapObject::Door { x, y, to } => ObjectFile::Door { x, y, to },
+                    MapObject::Exit { x, y, ref link } => ObjectFile::Exit {
+                        x,
+                        y,
+                        to: link.to_string(),
+                    },
+                    MapObject::Spawn { x, y } => ObjectFile::Spawn { x, y },
+                })
+                .collect();
+
+            RoomFile {
+                name: room.name.clone(),
+                tileset: room.tileset.clone(),
+                music: room.music.clone(),
+                width: room.width,
+                height: room.height,
+                layers: Layers {
+                    floor,
+                    walls,
+                    decorations,
+                    objects,
+                },
+            }
+        })
+        .collect();
+
+    MapFile {
+        version: FORMAT_VERSION,
+        rooms,
+    }
+}
+
+// Names of the wall tiles in files, which stay put whatever `TileType` turns into
+pub(super) fn wall_name(tile: TileType) -> Option<&'static str> {
+    match tile {
+        TileType::Wall => Some("wall"),
+        TileType::Wall2 => Some("wall_left"),
+        TileType::Wall3 => Some("wall_right"),
+        TileType::Wall4 => Some("wall_bottom"),
+        TileType::Wall5 => Some("wall_inner_left"),
+        TileType::Wall6 => Some("wall_inner_right"),
+        TileType::Empty | TileType::Skull | TileType::Door => None,
+    }
+}
+
+pub(super) fn wall_from_name(name: &str) -> Option<TileType> {
+    match name {
+        "wall" => Some(TileType::Wall),
+        "wall_left" => Some(TileType::Wall2),
+        "wall_right" => Some(TileType::Wall3),
+        "wall_bottom" => Some(TileType::Wall4),
+        "wall_inner_left" => Some(TileType::Wall5),
+        "wall_inner_right" => Some(TileType::Wall6),
+        _ => None,
+    }
+}
+
+pub(super) fn decoration_name(tile: TileType) -> Option<&'static str> {
+    match tile {
+        TileType::Skull => Some("skull"),
+        _ => None,
+    }
+}
+
+pub(super) fn decoration_from_name(name: &str) -> Option<TileType> {
+    match name {
+        "skull" => Some(TileType::Skull),
+        _ => None,
+    }
+}
+
+// Version 1 is the old serde dump of `Map`:
+//
+//   {"rooms": [{"grid": [["Wall2", ...]], "width", "height",
+//               "decorations": [[5, 5, "Skull"]]}],
+//    "current_room": 0, "doors": [[7, 10, 1]]}
+//
+// Its doors do not say which room they are in, so they are matched up with the
+// door tiles like `Map::from_layouts` does.
+fn upgrade_v1(value: Value) -> Result<Value, String> {
+    #[derive(Deserialize)]
+    struct MapV1 {
+        rooms: Vec<RoomV1>,
+        #[serde(default)]
+        doors: Vec<(usize, usize, usize)>,
+    }
+
+    #[derive(Deserialize)]
+    struct RoomV1 {
+        grid: Vec<Vec<String>>,
+        width: usize,
+        height: usize,
+        #[serde(default)]
+        decorations: Vec<(usize, usize, String)>,
+    }
+
+    let old: MapV1 =
+        serde_json::from_value(value).map_err(|e| format!("Invalid version 1 map: {}", e))?;
+
+    let door_tiles = old
+        .rooms
+        .iter()
+        .map(|room| {
+            let mut tiles = Vec::new();
+            for (y, row) in room.grid.iter().enumerate() {
+                for (x, tile) in row.iter().enumerate() {
+                    if tile == "Door" {
+                        tiles.push((x, y));
+                    }
+                }
+            }
+            tiles
+        })
+        .collect();
+    let linked = link_doors(door_tiles, &old.doors);
+
+    let rooms = old
+        .rooms
+        .into_iter()
+        .zip(linked)
+        .enumerate()
+        .map(|(index, (room, doors))| {
+            let mut floor = Vec::with_capacity(room.height);
+            let mut walls = Vec::with_capacity(room.height);
+            for row in &room.grid {
+                let mut floor_row = Vec::with_capacity(row.len());
+                let mut walls_row = Vec::with_capacity(row.len());
+                for tile in row {
+                    // The old tile names were the `TileType` variants of the time
+                    let (ground, wall) = match tile.as_str() {
+                        "Empty" | "Door" => (true, None),
+                        "Wall" => (false, Some("wall")),
+                        "Wall2" => (false, Some("wall_left")),
+                        "Wall3" => (false, Some("wall_right")),
+                        "Wall4" => (false, Some("wall_bottom")),
+                        "Wall5" => (false, Some("wall_inner_left")),
+                        "Wall6" => (false, Some("wall_inner_right")),
+                        // Skulls in the grid were neither drawn nor solid
+                        "Skull" => (false, None),
+                        other => return Err(format!("Unknown version 1 tile {:?}", other)),
+                    };
+                    floor_row.push(ground.then(|| "floor".to_string()));
+                    walls_row.push(wall.map(str::to_string));
+                }
+                floor.push(floor_row);
+                walls.push(walls_row);
+            }
+
+            let objects = doors
+                .into_iter()
+                .filter_map(|door| match door {
+                    MapObject::Door { x, y, to } => Some(ObjectFile::Door { x, y, to }),
+                    MapObject::Exit { .. } | MapObject::Spawn { .. } => None,
+                })
+                .collect();
+
+            let decorations = room
+                .decorations
+                .into_iter()
+                .filter(|(_, _, kind)| kind == "Skull")
+                .map(|(x, y, _)| DecorationFile {
+                    x,
+                    y,
+                    kind: "skull".to_string(),
+                })
+                .collect();
+
+            Ok(RoomFile {
+                name: format!("room{}", index),
+                tileset: default_tileset(),
+                music: None,
+                width: room.width,
+                height: room.height,
+                layers: Layers {
+                    floor,
+                    walls,
+                    decorations,
+                    objects,
+                },
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    serde_json::to_value(MapFile { version: 2, rooms })
+        .map_err(|e| format!("Failed to upgrade the map: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two rooms whose doors share a spot, as in the old default map
+    const V1: &str = r#"{
+        "rooms": [
+            {
+                "grid": [
+                    ["Wall2", "Wall", "Wall3"],
+                    ["Wall2", "Empty", "Wall3"],
+                    ["Wall2", "Door", "Wall3"]
+                ],
+                "width": 3,
+                "height": 3,
+                "decorations": [[1, 1, "Skull"]]
+            },
+            {
+                "grid": [
+                    ["Wall5", "Wall", "Wall6"],
+                    ["Wall2", "Skull", "Wall3"],
+                    ["Wall4", "Door", "Wall4"]
+                ],
+                "width": 3,
+                "height": 3,
+                "decorations": []
+            }
+        ],
+        "current_room": 0,
+        "doors": [[1, 2, 1], [1, 2, 0]]
+    }"#;
+
+    fn door(x: usize, y: usize, to: usize) -> MapObject {
+        MapObject::Door { x, y, to: Some(to) }
+    }
+
+    #[test]
+    fn test_upgrade_v1() {
+        let upgraded = upgrade_v1(serde_json::from_str(V1).unwrap()).unwrap();
+        assert_eq!(upgraded["version"], 2);
+        let layers = &upgraded["rooms"][1]["layers"];
+        assert_eq!(layers["walls"][0][0], "wall_inner_left");
+        assert_eq!(layers["walls"][2][0], "wall_bottom");
+        assert_eq!(layers["floor"][2][1], "floor");
+        // Skulls in the grid were never drawn
+        assert_eq!(layers["walls"][1][1], Value::Null);
+        assert_eq!(layers["floor"][1][1], Value::Null);
+
+        let map = load(V1).unwrap();
+        let [first, second] = &map.rooms[..] else {
+            panic!("expected two rooms");
+        };
+        assert_eq!(
+            (first.name.as_str(), second.name.as_str()),
+            ("room0", "room1")
+        );
+        assert_eq!(first.tileset, DEFAULT_TILESET);
+        assert_eq!(first.grid[0][1], TileType::Wall);
+        assert_eq!(first.grid[2][1], TileType::Door);
+        assert!(first.floor[1][1] && first.floor[2][1] && !first.floor[0][1]);
+        assert_eq!(first.decorations, vec![(1, 1, TileType::Skull)]);
+        assert!(second.decorations.is_empty());
+
+        // Each door goes to the room it was not found in
+        assert_eq!(first.objects, vec![door(1, 2, 1)]);
+        assert_eq!(second.objects, vec![door(1, 2, 0)]);
+    }
+
+    #[test]
+    fn test_upgrade_default_map() {
+        let json = include_str!("../../assets/default_map.json");
+        let map = load(json).unwrap();
+        assert_eq!(map.rooms.len(), 4);
+        assert_eq!(map.rooms[0].objects, vec![door(7, 10, 1)]);
+        assert_eq!(map.rooms[1].objects, vec![door(7, 1, 0), door(7, 10, 2)]);
+        assert_eq!(map.rooms[3].objects, vec![door(7, 1, 2)]);
+        assert_eq!(map.rooms[0].decorations, vec![(5, 5, TileType::Skull)]);
+    }
+
+    #[test]
+    fn test_save_load_round_trip() {
+        let map = load(V1).unwrap();
+        let saved = save(&map).unwrap();
+        let value: Value = serde_json::from_str(&saved).unwrap();
+        assert_eq!(value["version"], FORMAT_VERSION);
+
+        let again = load(&saved).unwrap();
+        assert_eq!(again.rooms[0].objects, map.rooms[0].objects);
+        assert_eq!(again.rooms[1].grid, map.rooms[1].grid);
+        assert_eq!(save(&again).unwrap(), saved);
+
+        assert!(load(r#"{"version": 3, "rooms": []}"#).is_err());
+    }
+}
@@ -1,13 +1,92 @@
 use crate::assets::AssetManager;
 use ggez::{Context, GameResult, graphics};
-use protocol::Facing;
-use serde::{Deserialize, Serialize};
+use protocol::{Facing, Position, zones::ZoneLink};
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
 
+pub mod ascii;
+pub mod autotile;
+pub mod format;
+pub mod tiled;
+pub mod validate;
+
+// The only zone the server runs so far, which every room of the map belongs to
+const ZONE: &str = "hub";
+
+// Turns doors given as `(x, y, destination_room_index)`, which do not say which
+// room they are in, into door objects for the door tiles of every room.
+//
+// Every door goes to the first room, in order, with an unclaimed door tile at
+// its spot other than its destination. Tiles left over lead wherever the first
+// door at their spot does, which is how the game used to pick.
+fn link_doors(
+    door_tiles: Vec<Vec<(usize, usize)>>,
+    doors: &[(usize, usize, usize)],
+) -> Vec<Vec<MapObject>> {
+    let mut claimed = vec![false; doors.len()];
+    door_tiles
+        .into_iter()
+        .enumerate()
+        .map(|(index, tiles)| {
+            tiles
+                .into_iter()
+                .map(|(x, y)| {
+                    let fits = |&(door_x, door_y, dest): &(usize, usize, usize)| {
+                        door_x == x && door_y == y && dest != index
+                    };
+                    let unclaimed = (0..doors.len()).find(|&i| !claimed[i] && fits(&doors[i]));
+                    let to = match unclaimed {
+                        Some(i) => {
+                            claimed[i] = true;
+                            Some(doors[i].2)
+                        }
+                        None => doors.iter().find(|door| fits(door)).map(|door| door.2),
+                    };
+                    MapObject::Door { x, y, to }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+// Where a door leads, as found by `find_target`
+enum DoorTarget {
+    Room(usize),
+    Link(ZoneLink),
+}
+
+// Where a door leading to `target` goes. Rooms of the map are given by their
+// name or zone path, like `hub/room1`. The zone alone, other zones and targets
+// with a position, like `town/market@x3y4`, are left as a link for the server
+// to resolve, at the zone's origin without a position. `None` for rooms of
+// this zone the map does not have.
+fn find_target(rooms: &[Room], target: &str) -> Option<DoorTarget> {
+    if target.contains('@') {
+        return target.parse().ok().map(DoorTarget::Link);
+    }
+    let path: Vec<&str> = target.split('/').collect();
+    if path.iter().any(|zone| zone.is_empty()) {
+        return None;
+    }
+    let name = match path.as_slice() {
+        [name] if *name != ZONE => name,
+        [ZONE, name] => name,
+        _ => {
+            return Some(DoorTarget::Link(ZoneLink {
+                zones: path.iter().map(|zone| zone.to_string()).collect(),
+                pos: Position::new(0, 0),
+            }));
+        }
+    };
+    rooms
+        .iter()
+        .position(|room| room.name == *name)
+        .map(DoorTarget::Room)
+}
+
 // Wall types for different wall appearances
-#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum TileType {
     Empty,
     Wall,
@@ -20,23 +99,49 @@ pub enum TileType {
     Door,  // Door to transition between rooms
 }
 
+// Things placed in a room besides its tiles
+#[derive(Debug, Clone, PartialEq)]
+pub enum MapObject {
+    // A door tile leading to another room, or nowhere yet
+    Door {
+        x: usize,
+        y: usize,
+        to: Option<usize>,
+    },
+    // A door tile leading out of the map, to a place the server knows
+    Exit {
+        x: usize,
+        y: usize,
+        link: ZoneLink,
+    },
+    // Where players may appear
+    Spawn {
+        x: usize,
+        y: usize,
+    },
+}
+
 // Define a room with its own grid layout
-#[derive(Serialize, Deserialize)]
 pub struct Room {
+    // Unique within the map, the last part of the room's zone path
+    name: String,
+    tileset: String,
+    music: Option<String>,
+    // Walls and doors, everything else is walkable
     grid: Vec<Vec<TileType>>,
+    // Cells with floor drawn under them
+    floor: Vec<Vec<bool>>,
     width: usize,
     height: usize,
     // Store decoration positions separately to draw them on top of floor tiles
     decorations: Vec<(usize, usize, TileType)>,
+    objects: Vec<MapObject>,
 }
 
 // Define the map as a collection of rooms with doors connecting them
-#[derive(Serialize, Deserialize)]
 pub struct Map {
     rooms: Vec<Room>,
     pub current_room: usize,
-    // Store door positions and their destination room index
-    doors: Vec<(usize, usize, usize)>, // (x, y, destination_room_index)
 }
 
 impl Room {
@@ -44,35 +149,66 @@ impl Room {
         let height = layout.len();
         let width = if height > 0 { layout[0].len() } else { 0 };
 
-        // Convert the basic grid to a grid with proper wall types
-        let mut grid = vec![vec![TileType::Empty; width]; height];
-
-        for y in 0..height {
-            for x in 0..width {
-                grid[y][x] = match layout[y][x] {
-                    0 => TileType::Empty,
-                    1 => TileType::Wall,
-                    2 => TileType::Wall2,
-                    3 => TileType::Wall3,
-                    4 => TileType::Wall4,
-                    5 => TileType::Wall5,
-                    6 => TileType::Wall6,
-                    7 => TileType::Door,
-                    _ => TileType::Wall, // Default to regular wall for any other value
-                };
+        // 0 is floor, 7 a door and anything else a wall, whose look is worked
+        // out from its neighbours; older layouts with wall pieces 2 to 6 still work
+        let solid: Vec<Vec<bool>> = layout
+            .iter()
+            .map(|row| row.iter().map(|&cell| cell != 0 && cell != 7).collect())
+            .collect();
+        let mut grid = autotile::autotile(&solid);
+        for (y, row) in layout.iter().enumerate() {
+            for (x, &cell) in row.iter().enumerate() {
+                if cell == 7 {
+                    grid[y][x] = TileType::Door;
+                }
             }
         }
 
+        // Floor goes under everything that is walked on
+        let floor = grid
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|&tile| matches!(tile, TileType::Empty | TileType::Door))
+                    .collect()
+            })
+            .collect();
+
         // Create decorations list with a skull at position (5, 5)
         let decorations = vec![(5, 5, TileType::Skull)];
 
         Self {
+            name: String::new(),
+            tileset: format::DEFAULT_TILESET.to_string(),
+            music: None,
             grid,
+            floor,
             width,
             height,
             decorations,
+            objects: Vec::new(),
         }
     }
+
+    // Where the door at a tile leads, if there is one
+    fn door_at(&self, x: usize, y: usize) -> Option<Option<usize>> {
+        self.objects.iter().find_map(|object| match *object {
+            MapObject::Door {
+                x: door_x,
+                y: door_y,
+                to,
+            } if door_x == x && door_y == y => Some(to),
+            _ => None,
+        })
+    }
+
+    // The first door leading to a given room
+    fn door_to(&self, room: usize) -> Option<(usize, usize)> {
+        self.objects.iter().find_map(|object| match *object {
+            MapObject::Door { x, y, to } if to == Some(room) => Some((x, y)),
+            _ => None,
+        })
+    }
 }
 
 impl Default for Map {
@@ -139,38 +275,61 @@ impl Map {
     fn create_fallback_map() -> Self {
         // Simple room layout - just a box with empty space
         let room_layout = vec![
-            vec![2, 5, 1, 1, 1, 1, 1, 1, 1, 1, 6, 3],
-            vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-            vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-            vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-            vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-            vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-            vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-            vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-            vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-            vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-            vec![2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3],
-            vec![2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 3],
+            vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
+            vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1],
+            vec![1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1],
         ];
 
         Self::from_layouts(vec![room_layout], vec![])
     }
 
-    // Create a map from custom room layouts and door connections
+    // Create a map from custom room layouts and door connections, see `link_doors`
     pub fn from_layouts(
         room_layouts: Vec<Vec<Vec<u8>>>,
         doors: Vec<(usize, usize, usize)>,
     ) -> Self {
         // Create rooms from layouts
-        let rooms = room_layouts.into_iter().map(Room::new).collect();
+        let mut rooms: Vec<Room> = room_layouts.into_iter().map(Room::new).collect();
+
+        let door_tiles = rooms
+            .iter()
+            .map(|room| {
+                let mut tiles = Vec::new();
+                for (y, row) in room.grid.iter().enumerate() {
+                    for (x, &tile) in row.iter().enumerate() {
+                        if tile == TileType::Door {
+                            tiles.push((x, y));
+                        }
+                    }
+                }
+                tiles
+            })
+            .collect();
+        let linked = link_doors(door_tiles, &doors);
+        for (index, (room, objects)) in rooms.iter_mut().zip(linked).enumerate() {
+            room.name = format!("room{}", index);
+            room.objects = objects;
+        }
 
         Self {
             rooms,
             current_room: 0,
-            doors,
         }
     }
 
+    pub fn room(&self) -> &Room {
+        &self.rooms[self.current_room]
+    }
+
     pub fn draw(
         &self,
         _ctx: &Context,
@@ -190,7 +349,7 @@ impl Map {
         if let Some(floor_asset) = asset_manager.get_asset("floor") {
             for y in 0..room.height {
                 for x in 0..room.width {
-                    if room.grid[y][x] == TileType::Empty || room.grid[y][x] == TileType::Door {
+                    if room.floor[y][x] {
                         // Draw floor at this position (doors have floor underneath)
                         let dest = [(x as i32 * grid_size) as f32, (y as i32 * grid_size) as f32];
                         canvas.draw(&floor_asset.img, graphics::DrawParam::default().dest(dest));
@@ -300,23 +459,26 @@ impl Map {
 
         // Draw decorations on top of floor tiles
         for (x, y, tile_type) in &room.decorations {
-            match tile_type {
-                TileType::Skull => {
-                    if let Some(skull_asset) = asset_manager.get_asset("skull") {
-                        let dest = [
-                            (*x as i32 * grid_size) as f32,
-                            (*y as i32 * grid_size) as f32,
-                        ];
-                        canvas.draw(&skull_asset.img, graphics::DrawParam::default().dest(dest));
-                    }
-                }
-                _ => {} // Skip other decoration types for now
+            // Skip other decoration types for now
+            if *tile_type == TileType::Skull
+                && let Some(skull_asset) = asset_manager.get_asset("skull")
+            {
+                let dest = [
+                    (*x as i32 * grid_size) as f32,
+                    (*y as i32 * grid_size) as f32,
+                ];
+                canvas.draw(&skull_asset.img, graphics::DrawParam::default().dest(dest));
             }
         }
 
         Ok(())
     }
 
+    // Zone path of the current room as the server knows it, e.g. `hub/room1`
+    pub fn zone_path(&self) -> Vec<String> {
+        vec![ZONE.to_string(), self.room().name.clone()]
+    }
+
     // Check if a position is valid (not a wall)
     pub fn is_valid_position(&self, x: i32, y: i32, grid_size: i32) -> bool {
         let room = &self.rooms[self.current_room];
@@ -359,6 +521,8 @@ impl Map {
     }
 
     // Check if player is on a door tile and handle room transition
+    //
+    // Returns the room behind the door, the tile to arrive on and the way to face
     pub fn check_door_transition(
         &mut self,
         x: i32,
@@ -369,77 +533,69 @@ impl Map {
         let center_x = (x / grid_size) as usize;
         let center_y = (y / grid_size) as usize;
 
-        // Check if the player is standing on a door
-        for (door_x, door_y, dest_room) in &self.doors {
-            if *door_x == center_x && *door_y == center_y && self.current_room != *dest_room {
-                // Transition to the destination room
-                let prev_room = self.current_room;
-                let new_room = *dest_room;
-
-                log::info!(
-                    "Door transition from room {} to room {}",
-                    prev_room,
-                    new_room
-                );
-
-                // Find the corresponding door in the destination room
-                for (other_door_x, other_door_y, other_dest_room) in &self.doors {
-                    if *other_dest_room == prev_room && new_room == self.current_room {
-                        // Determine the direction to offset the player from the door
-                        // This prevents the player from immediately triggering the door again
-                        use protocol::Facing::*;
-
-                        // Calculate the position of the door relative to the room boundaries
-                        let is_top_edge = *other_door_y <= 1;
-                        let is_bottom_edge = *other_door_y >= self.rooms[new_room].height - 2;
-                        let is_left_edge = *other_door_x <= 1;
-                        let is_right_edge = *other_door_x >= self.rooms[new_room].width - 2;
-
-                        let direction = if is_top_edge {
-                            // Door is at the top of the room, move player down
-                            South
-                        } else if is_bottom_edge {
-                            // Door is at the bottom of the room, move player up
-                            North
-                        } else if is_left_edge {
-                            // Door is at the left of the room, move player right
-                            East
-                        } else if is_right_edge {
-                            // Door is at the right of the room, move player left
-                            West
-                        } else {
-                            // Default direction if door position is ambiguous
-                            South
-                        };
-
-                        log::info!(
-                            "Found matching door at ({}, {}) in room {}, moving player {:?}",
-                            other_door_x,
-                            other_door_y,
-                            new_room,
-                            direction
-                        );
-
-                        // Return the new room, door position, and direction to offset
-                        return Some((new_room, *other_door_x, *other_door_y, direction));
-                    }
-                }
+        // Check if the player is standing on a door that leads somewhere
+        let prev_room = self.current_room;
+        let new_room = self.room().door_at(center_x, center_y)??;
+        if new_room == prev_room || new_room >= self.rooms.len() {
+            return None;
+        }
 
-                // If we didn't find a matching door, just place the player at a safe position in the new room
-                log::warn!("No matching door found in destination room, using default position");
-                let safe_x = self.rooms[new_room].width / 2;
-                let safe_y = self.rooms[new_room].height / 2;
-                return Some((new_room, safe_x, safe_y, Facing::South));
-            }
+        log::info!(
+            "Door transition from room {} to room {}",
+            prev_room,
+            new_room
+        );
+
+        // Find the corresponding door in the destination room
+        let room = &self.rooms[new_room];
+        if let Some((other_door_x, other_door_y)) = room.door_to(prev_room) {
+            // Determine the direction to offset the player from the door
+            // This prevents the player from immediately triggering the door again
+            use protocol::Facing::*;
+
+            // Calculate the position of the door relative to the room boundaries
+            let is_top_edge = other_door_y <= 1;
+            let is_bottom_edge = other_door_y + 2 >= room.height;
+            let is_left_edge = other_door_x <= 1;
+            let is_right_edge = other_door_x + 2 >= room.width;
+
+            let (direction, arrive_x, arrive_y) = if is_top_edge {
+                // Door is at the top of the room, move player down
+                (South, other_door_x, other_door_y + 1)
+            } else if is_bottom_edge {
+                // Door is at the bottom of the room, move player up
+                (North, other_door_x, other_door_y.saturating_sub(1))
+            } else if is_left_edge {
+                // Door is at the left of the room, move player right
+                (East, other_door_x + 1, other_door_y)
+            } else if is_right_edge {
+                // Door is at the right of the room, move player left
+                (West, other_door_x.saturating_sub(1), other_door_y)
+            } else {
+                // Default direction if door position is ambiguous
+                (South, other_door_x, other_door_y + 1)
+            };
+
+            log::info!(
+                "Found matching door at ({}, {}) in room {}, moving player {:?}",
+                other_door_x,
+                other_door_y,
+                new_room,
+                direction
+            );
+
+            // Return the new room, the tile next to the door, and direction
+            return Some((new_room, arrive_x, arrive_y, direction));
         }
 
-        None
+        // If we didn't find a matching door, just place the player at a safe position in the new room
+        log::warn!("No matching door found in destination room, using default position");
+        Some((new_room, room.width / 2, room.height / 2, Facing::South))
     }
 
     // Convert the map to JSON and save it to a file
     pub fn to_json(&self, path: &str) -> Result<(), String> {
-        let json = serde_json::to_string_pretty(self)
-            .map_err(|e| format!("Failed to serialize map to JSON: {}", e))?;
+        let json = format::save(self)?;
 
         let mut file =
             File::create(path).map_err(|e| format!("Failed to create file {}: {}", path, e))?;
@@ -451,15 +607,76 @@ impl Map {
         Ok(())
     }
 
-    // Load a map from a JSON file
+    // Load a map from a JSON file of any format version
     pub fn from_json(path: &str) -> Result<Self, String> {
         let file = std::fs::read_to_string(path)
             .map_err(|e| format!("Failed to read file {}: {}", path, e))?;
 
-        let map: Map = serde_json::from_str(&file)
-            .map_err(|e| format!("Failed to deserialize map from JSON: {}", e))?;
+        let map =
+            format::load(&file).map_err(|e| format!("Failed to load map from {}: {}", path, e))?;
 
         println!("Map loaded from {}", path);
         Ok(map)
     }
+
+    // Convert the map to the text format, see `ascii`, and save it to a file
+    pub fn to_ascii(&self, path: &str) -> Result<(), String> {
+        let text = ascii::export(self)?;
+
+        std::fs::write(path, text)
+            .map_err(|e| format!("Failed to write to file {}: {}", path, e))?;
+
+        println!("Map saved to {}", path);
+        Ok(())
+    }
+
+    // Load a map from a file in the text format
+    pub fn from_ascii(path: &str) -> Result<Self, String> {
+        let file = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read file {}: {}", path, e))?;
+
+        let map =
+            ascii::parse(&file).map_err(|e| format!("Failed to load map from {}: {}", path, e))?;
+
+        println!("Map loaded from {}", path);
+        Ok(map)
+    }
+
+    // Load a map from a Tiled JSON file, see `tiled`
+    pub fn from_tiled(path: &str, tiles: &tiled::TileTable) -> Result<Self, String> {
+        let file = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read file {}: {}", path, e))?;
+
+        let map = tiled::load(&file, tiles)
+            .map_err(|e| format!("Failed to load map from {}: {}", path, e))?;
+
+        println!("Map loaded from {}", path);
+        Ok(map)
+    }
+
+    // Save the map as JSON if the path ends in `.json`, as text otherwise
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        if has_extension(path, "json") {
+            self.to_json(path)
+        } else {
+            self.to_ascii(path)
+        }
+    }
+
+    // Load a map saved by `save`, or made in Tiled if the path ends in `.tmj`
+    pub fn load(path: &str, tiles: &tiled::TileTable) -> Result<Self, String> {
+        if has_extension(path, "json") {
+            Self::from_json(path)
+        } else if has_extension(path, "tmj") {
+            Self::from_tiled(path, tiles)
+        } else {
+            Self::from_ascii(path)
+        }
+    }
+}
+
+fn has_extension(path: &str, extension: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
 }
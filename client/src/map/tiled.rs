@@ -0,0 +1,543 @@
+// Maps made in the Tiled editor, saved as JSON (`.tmj`).
+//
+// A Tiled map becomes one room, or one room per top-level group layer when it
+// has any, named after the group. Within a room:
+//
+// - tile layers are stacked in order, each tile standing for a kind of the JSON
+//   format: `floor`, a wall or decoration name, `door` or `spawn`
+// - object layers hold objects of class `door` or `spawn`, placed on the tile
+//   under their center; a door's `to` property names the room or zone it leads
+//   to, as in the text format
+// - the custom properties `tileset` and `music` of the group, or of the map for
+//   single-room maps, describe the room
+//
+// Which kind a tile is comes from a `kind` property on the tile in Tiled, or
+// else from the `TileTable`. By default tile id n is what n stood for in room
+// layouts before walls were picked automatically: 0 floor, 1 wall, 2 to 6 the
+// wall pieces, 7 a door, and 8 a skull. Only the CSV layer format of finite
+// maps is read.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::{DoorTarget, Map, MapObject, Room, TileType, ascii, find_target, format};
+
+// The top bits of a tile in a layer flip it, which is the same tile to us
+const FLIP_FLAGS: u32 = 0xf000_0000;
+
+// What a tile or object stands for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Floor,
+    Wall(TileType),
+    Decoration(TileType),
+    Door,
+    Spawn,
+}
+
+fn kind_from_name(name: &str) -> Option<Kind> {
+    let name = ascii::snake_case(name);
+    match name.as_str() {
+        "floor" => Some(Kind::Floor),
+        "door" => Some(Kind::Door),
+        "spawn" => Some(Kind::Spawn),
+        _ => format::wall_from_name(&name)
+            .map(Kind::Wall)
+            .or_else(|| format::decoration_from_name(&name).map(Kind::Decoration)),
+    }
+}
+
+/// Which kind of tile every Tiled tile id stands for
+#[derive(Debug, Clone)]
+pub struct TileTable {
+    kinds: HashMap<u32, Kind>,
+}
+
+impl Default for TileTable {
+    fn default() -> Self {
+        let kinds = [
+            Kind::Floor,
+            Kind::Wall(TileType::Wall),
+            Kind::Wall(TileType::Wall2),
+            Kind::Wall(TileType::Wall3),
+            Kind::Wall(TileType::Wall4),
+            Kind::Wall(TileType::Wall5),
+            Kind::Wall(TileType::Wall6),
+            Kind::Door,
+            Kind::Decoration(TileType::Skull),
+        ];
+        Self {
+            kinds: (0..).zip(kinds).collect(),
+        }
+    }
+}
+
+impl TileTable {
+    /// Reads a table like `{"0": "floor", "1": "wall", "12": "skull"}`
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let names: HashMap<u32, String> =
+            serde_json::from_str(json).map_err(|e| format!("Invalid tile table: {}", e))?;
+        let kinds = names
+            .into_iter()
+            .map(|(id, name)| {
+                kind_from_name(&name)
+                    .map(|kind| (id, kind))
+                    .ok_or_else(|| format!("Unknown tile kind {:?} for tile {}", name, id))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { kinds })
+    }
+
+    pub fn from_file(path: &str) -> Result<Self, String> {
+        let file = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read file {}: {}", path, e))?;
+        Self::from_json(&file).map_err(|e| format!("{} in {}", e, path))
+    }
+}
+
+#[derive(Deserialize)]
+struct TiledMap {
+    width: usize,
+    height: usize,
+    tilewidth: f64,
+    tileheight: f64,
+    #[serde(default)]
+    infinite: bool,
+    layers: Vec<Layer>,
+    #[serde(default)]
+    tilesets: Vec<Tileset>,
+    #[serde(default)]
+    properties: Vec<Property>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Layer {
+    #[serde(rename = "tilelayer")]
+    Tiles { name: String, data: Option<Value> },
+    #[serde(rename = "objectgroup")]
+    Objects {
+        #[serde(default)]
+        objects: Vec<Object>,
+    },
+    Group {
+        name: String,
+        #[serde(default)]
+        layers: Vec<Layer>,
+        #[serde(default)]
+        properties: Vec<Property>,
+    },
+    #[serde(rename = "imagelayer")]
+    Image {},
+}
+
+#[derive(Deserialize)]
+struct Tileset {
+    firstgid: u32,
+    #[serde(default)]
+    tiles: Vec<TileInfo>,
+}
+
+#[derive(Deserialize)]
+struct TileInfo {
+    id: u32,
+    #[serde(default)]
+    properties: Vec<Property>,
+}
+
+#[derive(Deserialize)]
+struct Object {
+    #[serde(default)]
+    name: String,
+    // `type` up to Tiled 1.8, `class` since
+    #[serde(default, alias = "class")]
+    r#type: String,
+    x: f64,
+    y: f64,
+    #[serde(default)]
+    width: f64,
+    #[serde(default)]
+    height: f64,
+    // Set on tile objects, which hang up from their position rather than down
+    gid: Option<u32>,
+    #[serde(default)]
+    properties: Vec<Property>,
+}
+
+#[derive(Deserialize)]
+struct Property {
+    name: String,
+    value: Value,
+}
+
+fn property<'a>(properties: &'a [Property], name: &str) -> Option<&'a str> {
+    properties
+        .iter()
+        .find(|property| property.name == name)
+        .and_then(|property| property.value.as_str())
+}
+
+/// Reads a Tiled JSON map, with `table` for tiles that do not say what they are
+pub fn load(json: &str, table: &TileTable) -> Result<Map, String> {
+    let tiled: TiledMap =
+        serde_json::from_str(json).map_err(|e| format!("Invalid Tiled map: {}", e))?;
+    if tiled.infinite {
+        return Err("Infinite Tiled maps are not supported".to_string());
+    }
+
+    // Tiles with a `kind` of their own, by global id
+    let mut kinds = HashMap::new();
+    for tileset in &tiled.tilesets {
+        for tile in &tileset.tiles {
+            if let Some(name) = property(&tile.properties, "kind") {
+                let kind = kind_from_name(name).ok_or_else(|| {
+                    format!(
+                        "Unknown tile kind {:?} on tile {} of a tileset",
+                        name, tile.id
+                    )
+                })?;
+                kinds.insert(tileset.firstgid + tile.id, kind);
+            }
+        }
+    }
+    let lookup = |gid: u32| -> Option<Kind> {
+        if let Some(&kind) = kinds.get(&gid) {
+            return Some(kind);
+        }
+        let firstgid = tiled
+            .tilesets
+            .iter()
+            .map(|tileset| tileset.firstgid)
+            .filter(|&firstgid| firstgid <= gid)
+            .max()
+            .unwrap_or(1);
+        table.kinds.get(&(gid - firstgid)).copied()
+    };
+
+    let groups: Vec<_> = tiled
+        .layers
+        .iter()
+        .filter_map(|layer| match layer {
+            Layer::Group {
+                name,
+                layers,
+                properties,
+            } => Some((name.clone(), layers.as_slice(), properties.as_slice())),
+            _ => None,
+        })
+        .collect();
+    let groups = if groups.is_empty() {
+        let name = property(&tiled.properties, "name").unwrap_or("room0");
+        vec![(
+            name.to_string(),
+            tiled.layers.as_slice(),
+            tiled.properties.as_slice(),
+        )]
+    } else {
+        if tiled.layers.len() > groups.len() {
+            log::warn!("Ignoring the Tiled layers outside of groups, every group is a room");
+        }
+        groups
+    };
+
+    let (width, height) = (tiled.width, tiled.height);
+    let mut rooms = Vec::with_capacity(groups.len());
+    // Doors to link up once every room has its name: room, object and target
+    let mut targets = Vec::new();
+    for (index, (name, layers, properties)) in groups.into_iter().enumerate() {
+        if rooms.iter().any(|room: &Room| room.name == name) {
+            return Err(format!("There is more than one room named {}", name));
+        }
+        let mut room = Room {
+            name,
+            tileset: property(properties, "tileset")
+                .unwrap_or(format::DEFAULT_TILESET)
+                .to_string(),
+            music: property(properties, "music").map(str::to_string),
+            grid: vec![vec![TileType::Empty; width]; height],
+            floor: vec![vec![false; width]; height],
+            width,
+            height,
+            decorations: Vec::new(),
+            objects: Vec::new(),
+        };
+
+        for layer in layers {
+            match layer {
+                Layer::Tiles { name, data } => {
+                    let tiles = layer_tiles(name, data.as_ref(), width * height)?;
+                    for (i, gid) in tiles.into_iter().enumerate() {
+                        let gid = gid & !FLIP_FLAGS;
+                        if gid == 0 {
+                            continue;
+                        }
+                        let kind = lookup(gid)
+                            .ok_or_else(|| format!("Tile {} in layer {} has no kind", gid, name))?;
+                        place(&mut room, kind, i % width, i / width);
+                    }
+                }
+                Layer::Objects { objects } => {
+                    for object in objects {
+                        let kind = match object.r#type.to_lowercase().as_str() {
+                            "door" => Kind::Door,
+                            "spawn" => Kind::Spawn,
+                            _ => continue,
+                        };
+                        let center_x = object.x + object.width / 2.0;
+                        let center_y = match object.gid {
+                            Some(_) => object.y - object.height / 2.0,
+                            None => object.y + object.height / 2.0,
+                        };
+                        let x = (center_x / tiled.tilewidth).floor();
+                        let y = (center_y / tiled.tileheight).floor();
+                        if x < 0.0 || y < 0.0 || x >= width as f64 || y >= height as f64 {
+                            return Err(format!(
+                                "Object {:?} in room {} is outside the map",
+                                object.name, room.name
+                            ));
+                        }
+                        let (x, y) = (x as usize, y as usize);
+                        let object_index = place(&mut room, kind, x, y);
+                        if let (Some(object_index), Some(target)) =
+                            (object_index, property(&object.properties, "to"))
+                        {
+                            targets.push((index, object_index, target.to_string()));
+                        }
+                    }
+                }
+                // Groups within rooms are not rooms themselves, and images are not tiles
+                Layer::Group { .. } | Layer::Image {} => {}
+            }
+        }
+        rooms.push(room);
+    }
+
+    for (index, object, target) in targets {
+        let to = find_target(&rooms, &target).ok_or_else(|| {
+            format!(
+                "A door in room {} leads to {:?}, which is not a room",
+                rooms[index].name, target
+            )
+        })?;
+        let door = &mut rooms[index].objects[object];
+        if let MapObject::Door { x, y, to: door_to } = door {
+            match to {
+                DoorTarget::Room(to) => *door_to = Some(to),
+                DoorTarget::Link(link) => *door = MapObject::Exit { x: *x, y: *y, link },
+            }
+        }
+    }
+
+    Ok(Map {
+        rooms,
+        current_room: 0,
+    })
+}
+
+// The global tile ids of a tile layer, row by row
+fn layer_tiles(name: &str, data: Option<&Value>, size: usize) -> Result<Vec<u32>, String> {
+    let tiles: Vec<u32> = match data {
+        Some(Value::Array(tiles)) => tiles
+            .iter()
+            .map(|tile| tile.as_u64().and_then(|tile| u32::try_from(tile).ok()))
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("Layer {} has tiles that are not tile ids", name))?,
+        Some(Value::String(_)) => {
+            return Err(format!(
+                "Layer {} is encoded, save the map with the CSV layer format",
+                name
+            ));
+        }
+        _ => return Err(format!("Layer {} has no tiles", name)),
+    };
+    if tiles.len() != size {
+        return Err(format!(
+            "Layer {} has {} tiles, the map needs {}",
+            name,
+            tiles.len(),
+            size
+        ));
+    }
+    Ok(tiles)
+}
+
+// Puts a tile or object into the room, returning the index of the door or spawn
+// object standing for it
+fn place(room: &mut Room, kind: Kind, x: usize, y: usize) -> Option<usize> {
+    match kind {
+        Kind::Floor => room.floor[y][x] = true,
+        Kind::Wall(tile) => room.grid[y][x] = tile,
+        Kind::Decoration(tile) => {
+            // Decorations stand on the floor, as in the text format
+            room.floor[y][x] = true;
+            room.decorations.push((x, y, tile));
+        }
+        Kind::Door => {
+            room.floor[y][x] = true;
+            room.grid[y][x] = TileType::Door;
+            // A door object on a door tile says where that door leads
+            if let Some(index) = room.objects.iter().position(|existing| {
+                matches!(*existing, MapObject::Door { x: door_x, y: door_y, .. } if door_x == x && door_y == y)
+            }) {
+                return Some(index);
+            }
+            room.objects.push(MapObject::Door { x, y, to: None });
+            return Some(room.objects.len() - 1);
+        }
+        Kind::Spawn => {
+            room.objects.push(MapObject::Spawn { x, y });
+            return Some(room.objects.len() - 1);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two rooms of 3x3 tiles. The second tileset gives its tile 3 a kind of
+    // its own, everything else goes by the default table. 2147483650 is tile 2
+    // flipped horizontally.
+    const TWO_ROOMS: &str = r#"{
+        "width": 3,
+        "height": 3,
+        "tilewidth": 16,
+        "tileheight": 16,
+        "infinite": false,
+        "tilesets": [
+            {"firstgid": 1, "source": "dungeon.tsx"},
+            {
+                "firstgid": 10,
+                "tiles": [
+                    {"id": 3, "properties": [
+                        {"name": "kind", "type": "string", "value": "WallBottom"}
+                    ]}
+                ]
+            }
+        ],
+        "layers": [
+            {
+                "type": "group",
+                "name": "hall",
+                "properties": [{"name": "music", "type": "string", "value": "hall.ogg"}],
+                "layers": [
+                    {"type": "tilelayer", "name": "ground", "data": [
+                        2147483650, 2, 11,
+                        1, 9, 1,
+                        13, 8, 13
+                    ]},
+                    {"type": "objectgroup", "name": "doors", "objects": [
+                        {"name": "down", "class": "door",
+                         "x": 16, "y": 32, "width": 16, "height": 16,
+                         "properties": [{"name": "to", "type": "string", "value": "hub/cellar"}]}
+                    ]}
+                ]
+            },
+            {
+                "type": "group",
+                "name": "cellar",
+                "properties": [{"name": "tileset", "type": "string", "value": "crypt"}],
+                "layers": [
+                    {"type": "tilelayer", "name": "ground", "data": [
+                        2, 8, 2,
+                        1, 1, 1,
+                        2, 2, 2
+                    ]},
+                    {"type": "objectgroup", "name": "things", "objects": [
+                        {"class": "door", "x": 16, "y": 0, "width": 16, "height": 16,
+                         "properties": [{"name": "to", "type": "string", "value": "hall"}]},
+                        {"type": "spawn", "gid": 1, "x": 16, "y": 32, "width": 16, "height": 16},
+                        {"class": "chest", "x": 0, "y": 0}
+                    ]}
+                ]
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn test_groups_become_rooms() {
+        let map = load(TWO_ROOMS, &TileTable::default()).unwrap();
+        let [hall, cellar] = &map.rooms[..] else {
+            panic!("expected two rooms");
+        };
+        assert_eq!(hall.name, "hall");
+        assert_eq!(hall.tileset, format::DEFAULT_TILESET);
+        assert_eq!(hall.music.as_deref(), Some("hall.ogg"));
+        assert_eq!(cellar.name, "cellar");
+        assert_eq!(cellar.tileset, "crypt");
+        assert_eq!(cellar.music, None);
+    }
+
+    #[test]
+    fn test_tiles() {
+        let map = load(TWO_ROOMS, &TileTable::default()).unwrap();
+        let hall = &map.rooms[0];
+        // Flipped or not, tile 2 is a wall
+        assert_eq!(hall.grid[0][0], TileType::Wall);
+        assert_eq!(hall.grid[0][1], TileType::Wall);
+        // Ids count from the firstgid of their tileset
+        assert_eq!(hall.grid[0][2], TileType::Wall);
+        // A `kind` property beats the table, which has 3 as a wall piece
+        assert_eq!(hall.grid[2][0], TileType::Wall4);
+        assert_eq!(hall.grid[2][1], TileType::Door);
+        assert!(hall.floor[1][0] && hall.floor[1][2] && !hall.floor[0][0]);
+        // Decorations get floor under them
+        assert_eq!(hall.decorations, vec![(1, 1, TileType::Skull)]);
+        assert!(hall.floor[1][1]);
+
+        // Other tilesets map their ids with a table of their own
+        let table =
+            TileTable::from_json(r#"{"0": "floor", "1": "WallRight", "7": "door", "8": "skull"}"#)
+                .unwrap();
+        let map = load(TWO_ROOMS, &table).unwrap();
+        assert_eq!(map.rooms[1].grid[0][0], TileType::Wall3);
+        assert_eq!(map.rooms[1].grid[1][1], TileType::Empty);
+        assert_eq!(map.rooms[0].grid[2][0], TileType::Wall4);
+        let partial = TileTable::from_json(r#"{"0": "floor"}"#).unwrap();
+        assert!(load(TWO_ROOMS, &partial).is_err());
+        assert!(TileTable::from_json(r#"{"1": "lava"}"#).is_err());
+    }
+
+    #[test]
+    fn test_objects() {
+        let map = load(TWO_ROOMS, &TileTable::default()).unwrap();
+        // The door object says where the door tile under it leads
+        assert_eq!(
+            map.rooms[0].objects,
+            vec![MapObject::Door {
+                x: 1,
+                y: 2,
+                to: Some(1)
+            }]
+        );
+        // Tile objects hang up from their position; unknown classes are skipped
+        assert_eq!(
+            map.rooms[1].objects,
+            vec![
+                MapObject::Door {
+                    x: 1,
+                    y: 0,
+                    to: Some(0)
+                },
+                MapObject::Spawn { x: 1, y: 1 },
+            ]
+        );
+
+        let nowhere = TWO_ROOMS.replace("hub/cellar", "hub/attic");
+        assert!(load(&nowhere, &TileTable::default()).is_err());
+        // Other zones are left to the server
+        let outside = TWO_ROOMS.replace("hub/cellar", "town/market@x3y4");
+        let map = load(&outside, &TileTable::default()).unwrap();
+        assert_eq!(
+            map.rooms[0].objects,
+            vec![MapObject::Exit {
+                x: 1,
+                y: 2,
+                link: "town/market@x3y4".parse().unwrap()
+            }]
+        );
+    }
+}
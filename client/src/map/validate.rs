@@ -0,0 +1,519 @@
+// Checks for maps that load fine but break in play.
+//
+// The game copes with most of these at runtime, e.g. a door without a door back
+// drops the player in the middle of the next room, so nothing else notices them.
+// `check` lists them all; the `validate_map` binary runs it over map files and
+// `assert_valid` fails a test on any of them.
+//
+// Floor counts as reachable when it connects to a door or spawn point of its
+// room without crossing a wall. Rooms with neither have nothing to measure by,
+// so there only floor apart from the largest area is reported.
+
+use std::{collections::VecDeque, fmt};
+
+use super::{Map, MapObject, Room, TileType};
+
+/// Something wrong with a map, in the room named first
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// A row of the room is not as wide as the room
+    NotRectangular {
+        room: String,
+        row: usize,
+        width: usize,
+        expected: usize,
+    },
+    /// The room has more or fewer rows than it is high
+    WrongHeight {
+        room: String,
+        height: usize,
+        expected: usize,
+    },
+    /// A decoration, door or spawn point lies outside the room
+    OutOfRange {
+        room: String,
+        what: &'static str,
+        x: usize,
+        y: usize,
+    },
+    /// A door leads to a room the map does not have
+    DanglingDoor {
+        room: String,
+        x: usize,
+        y: usize,
+        to: usize,
+    },
+    /// A door leads to a room without a door back
+    UnpairedDoor {
+        room: String,
+        x: usize,
+        y: usize,
+        to: String,
+    },
+    /// A door leads back into its own room, which does nothing
+    DoorToOwnRoom { room: String, x: usize, y: usize },
+    /// A door leads nowhere
+    UnlinkedDoor { room: String, x: usize, y: usize },
+    /// A spawn point is inside a wall
+    SpawnInWall { room: String, x: usize, y: usize },
+    /// Floor no door or spawn point connects to, given by its top left cell
+    Unreachable {
+        room: String,
+        x: usize,
+        y: usize,
+        cells: usize,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::NotRectangular {
+                room,
+                row,
+                width,
+                expected,
+            } => write!(
+                f,
+                "{}: row {} is {} cells wide, the room is {}",
+                room, row, width, expected
+            ),
+            Problem::WrongHeight {
+                room,
+                height,
+                expected,
+            } => write!(
+                f,
+                "{}: the room has {} rows but is {} high",
+                room, height, expected
+            ),
+            Problem::OutOfRange { room, what, x, y } => {
+                write!(
+                    f,
+                    "{}: the {} at ({}, {}) is outside the room",
+                    room, what, x, y
+                )
+            }
+            Problem::DanglingDoor { room, x, y, to } => write!(
+                f,
+                "{}: the door at ({}, {}) leads to room {}, which does not exist",
+                room, x, y, to
+            ),
+            Problem::UnpairedDoor { room, x, y, to } => write!(
+                f,
+                "{}: the door at ({}, {}) leads to {}, which has no door back",
+                room, x, y, to
+            ),
+            Problem::DoorToOwnRoom { room, x, y } => write!(
+                f,
+                "{}: the door at ({}, {}) leads back into its own room",
+                room, x, y
+            ),
+            Problem::UnlinkedDoor { room, x, y } => {
+                write!(f, "{}: the door at ({}, {}) leads nowhere", room, x, y)
+            }
+            Problem::SpawnInWall { room, x, y } => write!(
+                f,
+                "{}: the spawn point at ({}, {}) is inside a wall",
+                room, x, y
+            ),
+            Problem::Unreachable { room, x, y, cells } => write!(
+                f,
+                "{}: {} floor cells from ({}, {}) cannot be reached from any door or spawn point",
+                room, cells, x, y
+            ),
+        }
+    }
+}
+
+/// Everything wrong with a map, room by room
+pub fn check(map: &Map) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    for (index, room) in map.rooms.iter().enumerate() {
+        check_shape(room, &mut problems);
+        check_objects(map, index, room, &mut problems);
+        check_reachable(room, &mut problems);
+    }
+    problems
+}
+
+/// Fails with every problem of the map, for tests of maps we ship
+#[allow(unused)]
+pub fn assert_valid(map: &Map) {
+    let problems = check(map);
+    assert!(
+        problems.is_empty(),
+        "The map has problems:\n{}",
+        problems
+            .iter()
+            .map(|problem| format!("  {}", problem))
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+}
+
+fn check_shape(room: &Room, problems: &mut Vec<Problem>) {
+    if room.grid.len() != room.height || room.floor.len() != room.height {
+        problems.push(Problem::WrongHeight {
+            room: room.name.clone(),
+            height: room.grid.len().min(room.floor.len()),
+            expected: room.height,
+        });
+    }
+    for (row, (grid, floor)) in room.grid.iter().zip(&room.floor).enumerate() {
+        if grid.len() != room.width || floor.len() != room.width {
+            problems.push(Problem::NotRectangular {
+                room: room.name.clone(),
+                row,
+                width: grid.len().min(floor.len()),
+                expected: room.width,
+            });
+        }
+    }
+
+    for &(x, y, _) in &room.decorations {
+        if tile(room, x, y).is_none() {
+            problems.push(Problem::OutOfRange {
+                room: room.name.clone(),
+                what: "decoration",
+                x,
+                y,
+            });
+        }
+    }
+}
+
+fn check_objects(map: &Map, index: usize, room: &Room, problems: &mut Vec<Problem>) {
+    let name = || room.name.clone();
+    for object in &room.objects {
+        match *object {
+            MapObject::Door { x, y, to } => {
+                if tile(room, x, y).is_none() {
+                    problems.push(Problem::OutOfRange {
+                        room: name(),
+                        what: "door",
+                        x,
+                        y,
+                    });
+                }
+                match to {
+                    None => problems.push(Problem::UnlinkedDoor { room: name(), x, y }),
+                    Some(to) if to == index => {
+                        problems.push(Problem::DoorToOwnRoom { room: name(), x, y })
+                    }
+                    Some(to) => match map.rooms.get(to) {
+                        None => problems.push(Problem::DanglingDoor {
+                            room: name(),
+                            x,
+                            y,
+                            to,
+                        }),
+                        Some(other) if other.door_to(index).is_none() => {
+                            problems.push(Problem::UnpairedDoor {
+                                room: name(),
+                                x,
+                                y,
+                                to: other.name.clone(),
+                            })
+                        }
+                        Some(_) => {}
+                    },
+                }
+            }
+            // Where an exit leads is up to the server
+            MapObject::Exit { x, y, .. } => {
+                if tile(room, x, y).is_none() {
+                    problems.push(Problem::OutOfRange {
+                        room: name(),
+                        what: "door",
+                        x,
+                        y,
+                    });
+                }
+            }
+            MapObject::Spawn { x, y } => match tile(room, x, y) {
+                None => problems.push(Problem::OutOfRange {
+                    room: name(),
+                    what: "spawn point",
+                    x,
+                    y,
+                }),
+                Some(tile) if is_wall(tile) => {
+                    problems.push(Problem::SpawnInWall { room: name(), x, y })
+                }
+                Some(_) => {}
+            },
+        }
+    }
+}
+
+// Flood fills the room from its doors and spawn points
+fn check_reachable(room: &Room, problems: &mut Vec<Problem>) {
+    let walkable = |x: usize, y: usize| tile(room, x, y).is_some_and(|tile| !is_wall(tile));
+
+    // Every walkable area of the room: its top left cell, its floor cells and
+    // whether a door or spawn point is in it
+    let mut areas = Vec::new();
+    let mut seen = vec![vec![false; room.width]; room.height];
+    for y in 0..room.height {
+        for x in 0..room.width {
+            if seen[y][x] || !walkable(x, y) {
+                continue;
+            }
+            let mut floor = 0;
+            let mut cells = Vec::new();
+            let mut queue = VecDeque::from([(x, y)]);
+            seen[y][x] = true;
+            while let Some((cx, cy)) = queue.pop_front() {
+                cells.push((cx, cy));
+                if room.floor.get(cy).and_then(|row| row.get(cx)) == Some(&true) {
+                    floor += 1;
+                }
+                let neighbours = [
+                    (cx.wrapping_sub(1), cy),
+                    (cx + 1, cy),
+                    (cx, cy.wrapping_sub(1)),
+                    (cx, cy + 1),
+                ];
+                for (nx, ny) in neighbours {
+                    if nx < room.width && ny < room.height && !seen[ny][nx] && walkable(nx, ny) {
+                        seen[ny][nx] = true;
+                        queue.push_back((nx, ny));
+                    }
+                }
+            }
+            let entered = room.objects.iter().any(|object| {
+                let (MapObject::Door { x, y, .. }
+                | MapObject::Exit { x, y, .. }
+                | MapObject::Spawn { x, y }) = *object;
+                cells.contains(&(x, y))
+            });
+            if floor > 0 {
+                areas.push(((x, y), floor, entered));
+            }
+        }
+    }
+
+    // Without doors or spawn points only the largest area is taken to be the room
+    if !areas.iter().any(|&(_, _, entered)| entered)
+        && let Some(largest) = areas
+            .iter()
+            .enumerate()
+            .max_by_key(|&(i, &(_, floor, _))| (floor, std::cmp::Reverse(i)))
+            .map(|(i, _)| i)
+    {
+        areas[largest].2 = true;
+    }
+
+    for ((x, y), cells, entered) in areas {
+        if !entered {
+            problems.push(Problem::Unreachable {
+                room: room.name.clone(),
+                x,
+                y,
+                cells,
+            });
+        }
+    }
+}
+
+// The tile at a cell, `None` outside the room
+fn tile(room: &Room, x: usize, y: usize) -> Option<TileType> {
+    if x >= room.width || y >= room.height {
+        return None;
+    }
+    room.grid.get(y).and_then(|row| row.get(x)).copied()
+}
+
+fn is_wall(tile: TileType) -> bool {
+    !matches!(tile, TileType::Empty | TileType::Skull | TileType::Door)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::ascii;
+
+    // Two rooms with a door to each other
+    const PAIR: &str = "\
+# = Wall
+X = Door -> \"hub/room1\"
+Y = Door -> \"hub/room0\"
+
+####
+#  #
+##X#
+
+##Y#
+#  #
+####
+";
+
+    fn parse(text: &str) -> Map {
+        ascii::parse(text).unwrap()
+    }
+
+    // The shipped maps as they are, with what is known to be wrong in them
+    #[test]
+    fn test_shipped_maps() {
+        let dir = env!("CARGO_MANIFEST_DIR");
+
+        // The chambers in the middle of room2 and room3 are walled in on purpose
+        let default = Map::from_json(&format!("{}/assets/default_map.json", dir)).unwrap();
+        assert_eq!(
+            check(&default),
+            vec![
+                Problem::Unreachable {
+                    room: "room2".to_string(),
+                    x: 4,
+                    y: 4,
+                    cells: 13
+                },
+                Problem::Unreachable {
+                    room: "room3".to_string(),
+                    x: 5,
+                    y: 5,
+                    cells: 4
+                },
+            ]
+        );
+
+        // Its generator marks the skulls with the number that means a door
+        let large = Map::from_json(&format!("{}/assets/large_room_map.json", dir)).unwrap();
+        let skulls = [(5, 5), (25, 5), (15, 15), (5, 25), (25, 25)];
+        assert_eq!(
+            check(&large),
+            skulls
+                .map(|(x, y)| Problem::UnlinkedDoor {
+                    room: "room0".to_string(),
+                    x,
+                    y
+                })
+                .to_vec()
+        );
+
+        assert_valid(&Map::from_ascii(&format!("{}/../map.sample", dir)).unwrap());
+    }
+
+    #[test]
+    fn test_linked_rooms_are_fine() {
+        assert_eq!(check(&parse(PAIR)), vec![]);
+    }
+
+    #[test]
+    fn test_doors() {
+        let lonely = parse("# = Wall\nX = Door\n\n####\n#  #\n##X#\n");
+        assert_eq!(
+            check(&lonely),
+            vec![Problem::UnlinkedDoor {
+                room: "room0".to_string(),
+                x: 2,
+                y: 2
+            }]
+        );
+
+        let own = parse("# = Wall\nX = Door -> \"room0\"\n\n####\n#  #\n##X#\n");
+        assert_eq!(
+            check(&own),
+            vec![Problem::DoorToOwnRoom {
+                room: "room0".to_string(),
+                x: 2,
+                y: 2
+            }]
+        );
+        // Doors out of the map lead wherever the server says
+        let exit = parse("# = Wall\nX = Door -> \"hub\"\n\n####\n#  #\n##X#\n");
+        assert_eq!(check(&exit), vec![]);
+
+        // The way back is a wall
+        let one_way = parse(&PAIR.replace("##Y#", "####"));
+        assert_eq!(
+            check(&one_way),
+            vec![Problem::UnpairedDoor {
+                room: "room0".to_string(),
+                x: 2,
+                y: 2,
+                to: "room1".to_string()
+            }]
+        );
+
+        let mut dangling = parse(PAIR);
+        dangling.rooms[0].objects[0] = MapObject::Door {
+            x: 2,
+            y: 2,
+            to: Some(7),
+        };
+        assert!(check(&dangling).contains(&Problem::DanglingDoor {
+            room: "room0".to_string(),
+            x: 2,
+            y: 2,
+            to: 7
+        }));
+    }
+
+    #[test]
+    fn test_objects_and_shape() {
+        let mut map = parse(PAIR);
+        let room = &mut map.rooms[1];
+        room.objects.push(MapObject::Spawn { x: 0, y: 2 });
+        room.objects.push(MapObject::Spawn { x: 4, y: 1 });
+        room.decorations.push((1, 3, TileType::Skull));
+        room.grid[2].pop();
+        assert_eq!(
+            check(&map),
+            vec![
+                Problem::NotRectangular {
+                    room: "room1".to_string(),
+                    row: 2,
+                    width: 3,
+                    expected: 4
+                },
+                Problem::OutOfRange {
+                    room: "room1".to_string(),
+                    what: "decoration",
+                    x: 1,
+                    y: 3
+                },
+                Problem::SpawnInWall {
+                    room: "room1".to_string(),
+                    x: 0,
+                    y: 2
+                },
+                Problem::OutOfRange {
+                    room: "room1".to_string(),
+                    what: "spawn point",
+                    x: 4,
+                    y: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sealed_floor_is_unreachable() {
+        let map = parse(&PAIR.replace("####\n#  #\n##X#", "#####\n#  ##\n#####\n#   #\n##X##"));
+        assert_eq!(
+            check(&map),
+            vec![Problem::Unreachable {
+                room: "room0".to_string(),
+                x: 1,
+                y: 1,
+                cells: 2
+            }]
+        );
+
+        // Without doors or spawn points the largest area is the room
+        let map = parse("# = Wall\n\n#####\n# # #\n#   #\n#####\n");
+        assert_eq!(check(&map), vec![]);
+        let map = parse("# = Wall\n\n######\n#  # #\n#  ###\n######\n");
+        assert_eq!(
+            check(&map),
+            vec![Problem::Unreachable {
+                room: "room0".to_string(),
+                x: 4,
+                y: 1,
+                cells: 1
+            }]
+        );
+    }
+}
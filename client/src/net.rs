@@ -1,10 +1,22 @@
 use std::{
-    io::{Read, Write},
-    net::TcpStream,
-    str::FromStr,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+    thread,
+    time::{Duration, Instant},
 };
 
-use protocol::ClientToServer;
+use protocol::{
+    ClientToServer, ServerToClient,
+    auth::{self, Challenge},
+    binary::{self, FrameDecoder},
+    framing::LineDecoder,
+    handshake::{self, Codec, ServerInfo},
+    heartbeat::Heartbeat,
+};
+
+use crate::{
+    netsim::{Conditions, SimulatedTransport},
+    transport::{self, Connector, TcpConnector, Transport},
+};
 
 /// Network client errors that can occur during communication
 #[derive(Debug)]
@@ -17,10 +29,57 @@ pub enum NCError {
     ConnectionError(String),
 }
 
+// Wait before the first reconnect attempt, doubled after every failure
+const RECONNECT_BASE_DELAY: Duration = Duration::from_millis(500);
+// Longest wait between reconnect attempts
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(30);
+// Longest the network thread waits for something to send before reading the socket again
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// Where the connection to the server stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connected,
+    /// The connection is down; attempt number `attempt` is made at `retry_at`
+    Reconnecting {
+        attempt: u32,
+        retry_at: Instant,
+    },
+}
+
+// What the game loop asks of the network thread
+enum Command {
+    Send(ClientToServer),
+    SendBatch(Vec<ClientToServer>),
+    SetSession(Option<(String, String)>),
+    SetMaxMissedPongs(u32),
+    Simulate(Conditions),
+}
+
+// What the network thread reports back, in the order it happened
+enum Event {
+    Message(ServerToClient),
+    State(ConnectionState),
+    Handshake(ServerInfo),
+    Latency(Duration, Duration),
+    Lost(String),
+}
+
 /// Network client that handles communication with the game server
+///
+/// The transport lives on a background thread, so sending only queues a message
+/// and receiving only drains what the thread has already decoded
 pub struct NetClient {
-    tcp: Option<TcpStream>,
-    offline_mode: bool,
+    // Queue to and from the network thread, gone once the thread stopped
+    channel: Option<(Sender<Command>, Receiver<Event>)>,
+    // Mirrors of what the network thread last reported
+    state: ConnectionState,
+    server: Option<ServerInfo>,
+    latency: Option<(Duration, Duration)>,
+    // Whether a session token was handed to the network thread to resume with
+    session: bool,
+    // A lost connection reported on the next `recv`, after the messages that preceded it
+    lost: Option<String>,
 }
 
 impl Default for NetClient {
@@ -30,80 +89,399 @@ impl Default for NetClient {
 }
 
 impl NetClient {
-    /// Creates a new network client in online mode
+    /// Creates a new network client connected to the game server over TCP
     pub fn new() -> Self {
-        Self::new_with_mode(false)
+        Self::with_connector(TcpConnector::from_env())
     }
 
-    /// Creates a new network client in offline mode
+    /// Creates a new network client talking to a local stand-in server
     pub fn new_offline() -> Self {
-        Self::new_with_mode(true)
+        log::info!("Starting in offline mode");
+        Self::with_connector(transport::offline())
     }
 
-    fn new_with_mode(offline_mode: bool) -> Self {
-        if offline_mode {
-            log::info!("Starting in offline mode");
-            return Self {
-                tcp: None,
-                offline_mode: true,
-            };
+    /// Creates a new network client that opens its connections with `connector`
+    pub fn with_connector(connector: impl Connector + 'static) -> Self {
+        let (command_tx, command_rx) = mpsc::channel();
+        let (event_tx, event_rx) = mpsc::channel();
+        let connector = Box::new(connector);
+        thread::Builder::new()
+            .name("network".to_string())
+            .spawn(move || Connection::new(connector, event_tx).run(command_rx))
+            .expect("Failed to start the network thread");
+
+        Self {
+            channel: Some((command_tx, event_rx)),
+            state: ConnectionState::Connected,
+            server: None,
+            latency: None,
+            session: false,
+            lost: None,
+        }
+    }
+
+    /// Returns whether we are connected or waiting to reconnect
+    pub fn connection_state(&self) -> ConnectionState {
+        self.state
+    }
+
+    /// Remembers the session token from a login, so a dropped connection can
+    /// be resumed without asking for the password again
+    pub fn set_session(&mut self, username: String, token: String) {
+        self.session = self
+            .command(Command::SetSession(Some((username, token))))
+            .is_ok();
+    }
+
+    /// Returns whether a dropped connection logs back in by itself
+    pub fn has_session(&self) -> bool {
+        self.session
+    }
+
+    /// Sets how many pings in a row may go unanswered before the connection is dropped
+    pub fn set_max_missed_pongs(&mut self, max_missed: u32) {
+        let _ = self.command(Command::SetMaxMissedPongs(max_missed));
+    }
+
+    /// Degrades the connection as described by `conditions`, from now on and
+    /// for every reconnect
+    pub fn simulate(&mut self, conditions: Conditions) {
+        let _ = self.command(Command::Simulate(conditions));
+    }
+
+    /// Returns the smoothed round trip time and its jitter, once measured
+    pub fn latency(&self) -> Option<(Duration, Duration)> {
+        self.latency
+    }
+
+    /// Forgets the session, e.g. after being kicked or when the server refuses it
+    pub fn clear_session(&mut self) {
+        self.session = false;
+        let _ = self.command(Command::SetSession(None));
+    }
+
+    /// Returns what the server announced in the handshake, once it has answered
+    pub fn server_info(&self) -> Option<&ServerInfo> {
+        self.server.as_ref()
+    }
+
+    /// Queues a client-to-server message for the network thread
+    pub fn send(&mut self, cts: ClientToServer) -> Result<(), NCError> {
+        if let ConnectionState::Reconnecting { .. } = self.state {
+            return Err(NCError::ConnectionError(
+                "Server connection lost".to_string(),
+            ));
+        }
+        self.command(Command::Send(cts))
+    }
+
+    /// Queues several messages to go out together, in a single write where the
+    /// codec allows it. They are taken out of `batch`, unless the connection is
+    /// down and they stay there for the next try.
+    pub fn send_batch(&mut self, batch: &mut Vec<ClientToServer>) -> Result<(), NCError> {
+        if let ConnectionState::Reconnecting { .. } = self.state {
+            return Err(NCError::ConnectionError(
+                "Server connection lost".to_string(),
+            ));
+        }
+        self.command(Command::SendBatch(std::mem::take(batch)))
+    }
+
+    /// Answers a login challenge with `AuthProof` once a worker thread derived
+    /// the key, which takes too long for the game loop. The challenge should
+    /// have passed `auth::check_challenge` already.
+    pub fn answer_challenge(
+        &mut self,
+        username: String,
+        password: String,
+        challenge: Challenge,
+    ) -> Result<(), NCError> {
+        let Some((commands, _)) = &self.channel else {
+            return Err(NCError::ConnectionError(
+                "Network thread stopped".to_string(),
+            ));
+        };
+        let commands = commands.clone();
+        thread::Builder::new()
+            .name("login".to_string())
+            .spawn(move || {
+                let proof = match auth::client_proof(&username, &password, &challenge) {
+                    Ok(proof) => proof,
+                    Err(e) => {
+                        log::warn!("Refusing the login challenge: {}", e);
+                        return;
+                    }
+                };
+                let _ = commands.send(Command::Send(ClientToServer::AuthProof(proof)));
+            })
+            .map_err(|e| NCError::ConnectionError(format!("Could not start the login: {}", e)))?;
+        Ok(())
+    }
+
+    fn command(&self, command: Command) -> Result<(), NCError> {
+        match &self.channel {
+            Some((commands, _)) if commands.send(command).is_ok() => Ok(()),
+            _ => Err(NCError::ConnectionError(
+                "Network thread stopped".to_string(),
+            )),
+        }
+    }
+
+    /// Receives every message the network thread has decoded since the last call
+    pub fn recv(&mut self) -> Result<Vec<ServerToClient>, NCError> {
+        if let Some(reason) = self.lost.take() {
+            return Err(NCError::ConnectionError(reason));
+        }
+        let Some((_, events)) = &self.channel else {
+            return Err(NCError::NoNewData);
+        };
+
+        let mut messages = Vec::new();
+        let mut stopped = false;
+        loop {
+            match events.try_recv() {
+                Ok(Event::Message(message)) => messages.push(message),
+                Ok(Event::State(state)) => self.state = state,
+                Ok(Event::Handshake(info)) => self.server = Some(info),
+                Ok(Event::Latency(rtt, jitter)) => self.latency = Some((rtt, jitter)),
+                Ok(Event::Lost(reason)) => {
+                    // The next server may not be the same version
+                    self.server = None;
+                    self.latency = None;
+                    self.lost = Some(reason);
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    stopped = true;
+                    break;
+                }
+            }
+        }
+        if stopped {
+            log::error!("The network thread stopped");
+            self.channel = None;
+            self.lost = Some("Network thread stopped".to_string());
+        }
+
+        // Hand over whatever arrived before the connection was lost; the next call reports it
+        match self.lost.take() {
+            Some(reason) if messages.is_empty() => Err(NCError::ConnectionError(reason)),
+            lost => {
+                self.lost = lost;
+                if messages.is_empty() {
+                    Err(NCError::NoNewData)
+                } else {
+                    Ok(messages)
+                }
+            }
+        }
+    }
+}
+
+// The transport end of `NetClient`, running on the network thread. It connects
+// and reconnects, runs the handshake and heartbeat, and decodes everything the
+// server sends into events for the game loop.
+struct Connection {
+    transport: Option<Box<dyn Transport>>,
+    connector: Box<dyn Connector>,
+    state: ConnectionState,
+    events: Sender<Event>,
+    // Username and token from our last login, used to log in again after reconnecting
+    session: Option<(String, String)>,
+    decoder: LineDecoder,
+    // Takes over from `decoder` once the server switches to binary frames
+    frames: Option<FrameDecoder>,
+    // Codec for what we send
+    codec: Codec,
+    // Filled in once the server has answered our hello
+    server: Option<ServerInfo>,
+    // Keepalive and latency, for servers that answer pings
+    heartbeat: Heartbeat,
+    // Bad network to put every transport through, see `netsim`
+    simulation: Option<Conditions>,
+}
+
+impl Connection {
+    fn new(connector: Box<dyn Connector>, events: Sender<Event>) -> Self {
+        Self {
+            transport: None,
+            connector,
+            state: ConnectionState::Connected,
+            events,
+            session: None,
+            decoder: LineDecoder::new(),
+            frames: None,
+            codec: Codec::Text,
+            server: None,
+            heartbeat: Heartbeat::default(),
+            simulation: None,
         }
+    }
+
+    // Runs until the `NetClient` is dropped
+    fn run(mut self, commands: Receiver<Command>) {
+        self.connect();
+        loop {
+            // Wait briefly for something to send, then see what the server said
+            match commands.recv_timeout(POLL_INTERVAL) {
+                Ok(command) => self.apply(command),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            while let Ok(command) = commands.try_recv() {
+                self.apply(command);
+            }
+            self.poll();
+        }
+        log::debug!("Network thread stopped");
+    }
 
-        let addr = std::env::var("GAME_HOSTNAME_PORT").unwrap_or("game.ablecorp.us:45250".to_owned());
-        let stream = match TcpStream::connect(&addr) {
-            Ok(stream) => {
-                log::info!("Connected to server at {}", &addr);
-                if let Err(err) = stream.set_nonblocking(true) {
-                    log::error!("Failed to set nonblocking mode: {}", err);
+    fn apply(&mut self, command: Command) {
+        match command {
+            Command::Send(cts) => {
+                if let Err(e) = self.send(cts) {
+                    log::debug!("Dropping message: {:?}", e);
                 }
-                Some(stream)
             }
+            Command::SendBatch(batch) => {
+                if let Err(e) = self.send_batch(&batch) {
+                    log::debug!("Dropping {} messages: {:?}", batch.len(), e);
+                }
+            }
+            Command::SetSession(session) => self.session = session,
+            Command::SetMaxMissedPongs(max_missed) => self.heartbeat.set_max_missed(max_missed),
+            Command::Simulate(conditions) => {
+                log::info!("Simulating {}", conditions);
+                self.simulation = Some(conditions);
+                // The connection may already be up
+                if let Some(transport) = self.transport.take() {
+                    self.transport = Some(self.simulate(transport));
+                }
+            }
+        }
+    }
+
+    fn emit(&self, event: Event) {
+        // Nobody is listening once the game is shutting down
+        let _ = self.events.send(event);
+    }
+
+    fn set_state(&mut self, state: ConnectionState) {
+        self.state = state;
+        self.emit(Event::State(state));
+    }
+
+    // Opens a fresh connection and starts the handshake, or schedules the next attempt
+    fn connect(&mut self) {
+        let attempt = match self.state {
+            ConnectionState::Connected => 0,
+            ConnectionState::Reconnecting { attempt, .. } => attempt,
+        };
+
+        let transport = match self.connector.connect() {
+            Ok(transport) => transport,
             Err(e) => {
-                log::warn!("Failed to connect to server: {}. Switching to offline mode.", e);
-                return Self {
-                    tcp: None,
-                    offline_mode: true,
-                };
+                let delay = reconnect_delay(attempt);
+                log::warn!(
+                    "Failed to connect to server at {}: {}. Retrying in {:.1}s",
+                    self.connector,
+                    e,
+                    delay.as_secs_f32()
+                );
+                self.set_state(ConnectionState::Reconnecting {
+                    attempt: attempt + 1,
+                    retry_at: Instant::now() + delay,
+                });
+                return;
             }
         };
 
-        Self {
-            tcp: stream,
-            offline_mode: false,
+        log::info!("Connected to server at {}", self.connector);
+        self.transport = Some(self.simulate(transport));
+        self.set_state(ConnectionState::Connected);
+        // Nothing said on the old connection carries over
+        self.decoder = LineDecoder::new();
+        self.frames = None;
+        self.codec = Codec::Text;
+        self.server = None;
+        self.heartbeat.reset();
+
+        // Open the handshake before anything else is said
+        let hello = ClientToServer::Hello(
+            handshake::PROTOCOL_VERSION,
+            handshake::CLIENT_FEATURES
+                .iter()
+                .map(|f| f.to_string())
+                .collect(),
+        );
+        if let Err(e) = self.send(hello) {
+            log::error!("Failed to send hello: {:?}", e);
         }
     }
 
-    /// Returns whether the client is in offline mode
-    pub fn is_offline(&self) -> bool {
-        self.offline_mode
+    // Wraps a transport in the simulated network, if there is one
+    fn simulate(&mut self, transport: Box<dyn Transport>) -> Box<dyn Transport> {
+        match self.simulation.as_mut() {
+            Some(conditions) => {
+                let simulated = SimulatedTransport::new(transport, conditions.clone());
+                // Every connection gets its own random choices
+                conditions.seed = conditions.seed.wrapping_add(1);
+                Box::new(simulated)
+            }
+            None => transport,
+        }
     }
 
-    /// Sends a client-to-server message
-    pub fn send(&mut self, cts: ClientToServer) -> Result<(), NCError> {
-        if self.offline_mode {
-            log::debug!("Offline mode: Would send {:?}", cts);
-            return Ok(());
-        }
+    // Drops the connection and schedules the first reconnect attempt
+    fn disconnect(&mut self, reason: &str) {
+        log::warn!("Lost connection to server: {}", reason);
+        self.transport = None;
+        self.server = None;
+        self.emit(Event::Lost(reason.to_string()));
+        self.set_state(ConnectionState::Reconnecting {
+            attempt: 0,
+            retry_at: Instant::now() + RECONNECT_BASE_DELAY,
+        });
+    }
 
-        self.send_str(cts.as_line())
+    // Encodes and writes a message with the codec in use
+    fn send(&mut self, cts: ClientToServer) -> Result<(), NCError> {
+        log::trace!("Sending: {:?}", cts);
+        let bytes = self.encode(&cts);
+        self.send_bytes(&bytes)
     }
 
-    /// Sends a raw string message to the server
-    fn send_str(&mut self, string: String) -> Result<(), NCError> {
-        if self.offline_mode {
-            log::debug!("Offline mode: Would send {}", string.trim());
-            return Ok(());
+    // Writes several messages, at once when frames say where each one ends. Text
+    // servers only read the first line of every read, so lines go out one by one.
+    fn send_batch(&mut self, batch: &[ClientToServer]) -> Result<(), NCError> {
+        log::trace!("Sending batch: {:?}", batch);
+        match self.codec {
+            Codec::Text => batch.iter().try_for_each(|cts| self.send(cts.clone())),
+            Codec::Binary => {
+                let bytes: Vec<u8> = batch.iter().flat_map(|cts| self.encode(cts)).collect();
+                self.send_bytes(&bytes)
+            }
         }
+    }
+
+    fn encode(&self, cts: &ClientToServer) -> Vec<u8> {
+        match self.codec {
+            Codec::Text => cts.as_line().into_bytes(),
+            Codec::Binary => binary::encode_frame(cts),
+        }
+    }
 
-        log::trace!("Sending: {}", string.trim());
-        match self.tcp.as_mut() {
-            Some(stream) => match stream.write(string.as_bytes()) {
-                Ok(bytes) => {
-                    log::trace!("Sent {} bytes to server", bytes);
+    // Writes already encoded bytes to the server
+    fn send_bytes(&mut self, bytes: &[u8]) -> Result<(), NCError> {
+        match self.transport.as_mut() {
+            Some(transport) => match transport.write(bytes) {
+                Ok(()) => {
+                    log::trace!("Sent {} bytes to server", bytes.len());
                     Ok(())
                 }
                 Err(e) => {
                     log::error!("Error sending data: {}", e);
+                    self.disconnect(&e.to_string());
                     Err(NCError::SendError)
                 }
             },
@@ -111,211 +489,416 @@ impl NetClient {
         }
     }
 
-    /// Receives data from the server
-    pub fn recv(&mut self) -> Result<String, NCError> {
-        if self.offline_mode {
-            return Err(NCError::NoNewData);
+    // Reads whatever the server sent, or retries the connection once it is due
+    fn poll(&mut self) {
+        if let ConnectionState::Reconnecting { retry_at, .. } = self.state {
+            if Instant::now() < retry_at {
+                return;
+            }
+            self.connect();
         }
 
+        let Some(transport) = self.transport.as_mut() else {
+            return;
+        };
+
+        // Drain the transport so lines split or coalesced across reads are framed correctly
         let mut buffer = [0; 1024];
-        match self.tcp.as_mut() {
-            Some(stream) => {
-                stream.set_nonblocking(true).unwrap();
-
-                match stream.read(&mut buffer) {
-                    Ok(0) => Err(NCError::ConnectionError("Server closed connection".to_string())),
-                    Ok(n) => Ok(String::from_utf8_lossy(&buffer[0..n]).to_string()),
-                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                        Err(NCError::NoNewData)
-                    }
-                    Err(e) => Err(NCError::ConnectionError(e.to_string())),
+        let mut closed = None;
+        loop {
+            match transport.read(&mut buffer) {
+                Ok(0) => {
+                    closed = Some("Server closed connection".to_string());
+                    break;
+                }
+                Ok(n) => match self.frames.as_mut() {
+                    Some(frames) => frames.feed(&buffer[0..n]),
+                    None => self.decoder.feed(&buffer[0..n]),
+                },
+                Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    closed = Some(e.to_string());
+                    break;
                 }
             }
-            None => Err(NCError::ConnectionError("Server connection lost".to_string())),
         }
-    }
 
-    /// Parses a server message into a ServerToClient enum
-    pub fn parse_server_message(&self, message: &str) -> Option<protocol::ServerToClient> {
-        log::trace!("Parsing server message: {}", message);
+        while self.frames.is_none() {
+            let Some(line) = self.decoder.next_line() else {
+                break;
+            };
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    log::warn!("Dropping malformed server line: {}", e);
+                    continue;
+                }
+            };
 
-        if message.contains("player_moved") {
-            let parts: Vec<&str> = message.split_whitespace().collect();
-            log::trace!("Message parts: {:?}", parts);
-
-            // Find the position of "player_moved" in the message
-            if let Some(pos_index) = parts.iter().position(|&part| part == "player_moved") {
-                log::trace!("Found player_moved at position {}", pos_index);
-
-                // Check if we have enough parts after "player_moved"
-                if pos_index + 4 < parts.len() {
-                    // The format is now "player_moved username x y facing"
-                    let username = parts[pos_index + 1].to_string();
-                    log::trace!("Username from message: {}", username);
-
-                    if let (Ok(x), Ok(y)) = (
-                        parts[pos_index + 2].parse::<i32>(),
-                        parts[pos_index + 3].parse::<i32>(),
-                    ) {
-                        let facing_str = parts[pos_index + 4];
-                        let facing =
-                            protocol::Facing::from_str(facing_str).expect("invalid direction");
-
-                        log::trace!(
-                            "Successfully parsed player_moved message for {}: ({}, {}) facing {:?}",
-                            username,
-                            x,
-                            y,
-                            facing
-                        );
-
-                        return Some(protocol::ServerToClient::PlayerMoved(
-                            username,
-                            protocol::Position::new(x, y),
-                            facing,
-                        ));
-                    } else {
-                        log::warn!("Failed to parse x/y coordinates from player_moved message");
-                    }
-                } else {
-                    log::warn!("Not enough parts after player_moved in message");
+            // The first reply always answers our hello
+            if self.server.is_none() {
+                let info = Self::read_hello_reply(&line);
+                if info.codec() == Codec::Binary {
+                    self.switch_to_binary();
                 }
-            } else {
-                log::warn!("Could not find player_moved in message parts");
+                self.emit(Event::Handshake(info.clone()));
+                self.server = Some(info);
+                self.resume_session();
+                continue;
+            }
+
+            if let Some(message) = self.parse_server_message(&line) {
+                self.receive(message);
+            }
+        }
+
+        while let Some(message) = self.frames.as_mut().and_then(|f| f.next_message()) {
+            match message {
+                Ok(message) => self.receive(message),
+                Err(e) => log::warn!("Dropping malformed server frame: {}", e),
             }
-        } else if message.contains("player_joined") {
-            // Similar logging for player_joined
-            let parts: Vec<&str> = message.split_whitespace().collect();
-            log::trace!("Message parts: {:?}", parts);
-
-            // Find the position of "player_joined" in the message
-            if let Some(pos_index) = parts.iter().position(|&part| part == "player_joined") {
-                log::trace!("Found player_joined at position {}", pos_index);
-
-                // Check if we have enough parts after "player_joined"
-                if pos_index + 4 < parts.len() {
-                    // The format is now "player_joined username x y facing"
-                    let username = parts[pos_index + 1].to_string();
-                    log::trace!("Username from message: {}", username);
-
-                    if let (Ok(x), Ok(y)) = (
-                        parts[pos_index + 2].parse::<i32>(),
-                        parts[pos_index + 3].parse::<i32>(),
-                    ) {
-                        let facing_str = parts[pos_index + 4];
-                        let facing =
-                            protocol::Facing::from_str(facing_str).expect("invalid direction");
-
-                        log::trace!(
-                            "Successfully parsed player_joined message for {}: ({}, {}) facing {:?}",
-                            username,
-                            x,
-                            y,
-                            facing
-                        );
-
-                        return Some(protocol::ServerToClient::PlayerJoined(
-                            username,
-                            protocol::Position::new(x, y),
-                            facing,
-                        ));
-                    } else {
-                        log::warn!("Failed to parse x/y coordinates from player_joined message");
+        }
+
+        // Whatever arrived before the connection closed has been handed over
+        if let Some(reason) = closed {
+            self.disconnect(&reason);
+        } else if let Err(reason) = self.keep_alive() {
+            self.disconnect(&reason);
+        }
+    }
+
+    fn receive(&mut self, message: ServerToClient) {
+        match message {
+            // Pongs only feed the latency figures
+            ServerToClient::Pong(nonce, timestamp) => {
+                if let Some(rtt) = self.heartbeat.on_pong(nonce, timestamp, Instant::now()) {
+                    log::trace!("Pong {} after {}ms", nonce, rtt.as_millis());
+                    if let Some(smoothed) = self.heartbeat.rtt() {
+                        self.emit(Event::Latency(smoothed, self.heartbeat.jitter()));
                     }
-                } else {
-                    log::warn!("Not enough parts after player_joined in message");
                 }
-            } else {
-                log::warn!("Could not find player_joined in message parts");
             }
-        } else if message.contains("player_left") {
-            // Similar logging for player_left
-            let parts: Vec<&str> = message.split_whitespace().collect();
-            log::trace!("Message parts: {:?}", parts);
-
-            // Find the position of "player_left" in the message
-            if let Some(pos_index) = parts.iter().position(|&part| part == "player_left") {
-                log::trace!("Found player_left at position {}", pos_index);
-
-                // Check if we have enough parts after "player_left"
-                if pos_index + 1 < parts.len() {
-                    // The format is "player_left username"
-                    let username = parts[pos_index + 1].to_string();
-                    log::trace!("Username from message: {}", username);
-
-                    log::trace!("Successfully parsed player_left message for {}", username);
-                    return Some(protocol::ServerToClient::PlayerLeft(username));
-                } else {
-                    log::warn!("Not enough parts after player_left in message");
-                }
-            } else {
-                log::warn!("Could not find player_left in message parts");
+            message => self.emit(Event::Message(message)),
+        }
+    }
+
+    // Pings servers that support it and gives up on them once too many pings went unanswered
+    fn keep_alive(&mut self) -> Result<(), String> {
+        if !self
+            .server
+            .as_ref()
+            .is_some_and(|info| info.has_capability(handshake::HEARTBEAT_CAPABILITY))
+        {
+            return Ok(());
+        }
+
+        if let Some((nonce, timestamp)) = self.heartbeat.poll(Instant::now()) {
+            let _ = self.send(ClientToServer::Ping(nonce, timestamp));
+        }
+        if self.heartbeat.timed_out() {
+            return Err("Server stopped answering pings".to_string());
+        }
+        Ok(())
+    }
+
+    // The server sends binary frames straight after its hello, and expects them
+    // from us once we have said `codec binary`.
+    fn switch_to_binary(&mut self) {
+        log::info!("Switching to the binary codec");
+        let mut frames = FrameDecoder::new();
+        frames.feed(&self.decoder.take_buffered());
+        self.frames = Some(frames);
+
+        if let Err(e) = self.send(ClientToServer::UseCodec(Codec::Binary)) {
+            log::error!("Failed to switch codec: {:?}", e);
+        }
+        self.codec = Codec::Binary;
+    }
+
+    // After a reconnect, logs straight back in with the token from the last login.
+    // The server answers with `LoginOk`, or `LoginFailed` once the token expired.
+    fn resume_session(&mut self) {
+        if let Some((username, token)) = self.session.clone() {
+            log::info!("Resuming the session of '{}'", username);
+            if let Err(e) = self.send(ClientToServer::Resume(username, token)) {
+                log::error!("Failed to resume session: {:?}", e);
+            }
+        }
+    }
+
+    // Servers from before the handshake answer `hello` with `Invalid protocol.`
+    fn read_hello_reply(reply: &str) -> ServerInfo {
+        match reply.parse::<ServerToClient>() {
+            Ok(ServerToClient::Hello(version, capabilities)) => {
+                log::info!(
+                    "Server speaks protocol v{} with capabilities {:?}",
+                    version,
+                    capabilities
+                );
+                ServerInfo::new(version, capabilities)
             }
-        } else if message.contains("Facing") {
-            // Extract the username from the message
-            let parts: Vec<&str> = message.split_whitespace().collect();
+            _ => {
+                log::warn!(
+                    "Server did not answer the handshake ({}), assuming a legacy server",
+                    reply.trim()
+                );
+                ServerInfo::legacy()
+            }
+        }
+    }
 
-            // Find the username part
-            let username_part = parts
-                .iter()
-                .find(|&&part| part.starts_with("USR-(") && part.ends_with("):"));
-            if let Some(username_part) = username_part {
-                // Extract username from "USR-(username):"
-                let username = username_part
-                    .trim_start_matches("USR-(")
-                    .trim_end_matches("):")
-                    .to_string();
-
-                // Find the facing direction
-                if let Some(facing_index) = parts.iter().position(|&part| part == "Facing") {
-                    if facing_index + 1 < parts.len() {
-                        let facing_str = parts[facing_index + 1];
-                        let facing =
-                            protocol::Facing::from_str(facing_str).expect("invalid direction");
-
-                        // We don't have a position, so use a dummy position
-                        // This is just to update the facing direction
-                        let position = protocol::Position::new(0, 0);
-
-                        return Some(protocol::ServerToClient::PlayerMoved(
-                            username, position, facing,
-                        ));
-                    }
+    // Parses a text server line into a ServerToClient message
+    fn parse_server_message(&self, message: &str) -> Option<ServerToClient> {
+        log::trace!("Parsing server message: {}", message);
+
+        match message.parse::<ServerToClient>() {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                log::trace!("Ignoring server line {:?}: {}", message.trim(), e);
+                None
+            }
+        }
+    }
+}
+
+// Exponential backoff: the base delay doubled per failed attempt, capped
+fn reconnect_delay(attempt: u32) -> Duration {
+    RECONNECT_BASE_DELAY
+        .saturating_mul(1 << attempt.min(16))
+        .min(RECONNECT_MAX_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use protocol::{Facing, Position};
+
+    use super::*;
+    use crate::transport::{LoopbackListener, LoopbackTransport};
+
+    // Longest any test waits for the network thread
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    // The server end of a loopback connection, reading what the client says
+    struct Server {
+        transport: LoopbackTransport,
+        decoder: LineDecoder,
+    }
+
+    impl Server {
+        fn accept(listener: &LoopbackListener) -> Self {
+            Self {
+                transport: listener.accept().expect("the client should connect"),
+                decoder: LineDecoder::new(),
+            }
+        }
+
+        // The next line from the client, `None` if none comes within `wait`
+        fn try_line(&mut self, wait: Duration) -> Option<String> {
+            let deadline = Instant::now() + wait;
+            let mut buffer = [0; 1024];
+            loop {
+                if let Some(line) = self.decoder.next_line() {
+                    return Some(line.unwrap());
+                }
+                match self.transport.read(&mut buffer) {
+                    Ok(0) => panic!("the client hung up"),
+                    Ok(n) => self.decoder.feed(&buffer[..n]),
+                    Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(1)),
+                    Err(_) => return None,
                 }
             }
-        } else if message.contains("chat_message") {
-            let parts: Vec<&str> = message.split_whitespace().collect();
-            log::info!("Chat message parts: {:?}", parts);
+        }
 
-            // Find the position of "chat_message" in the message
-            if let Some(pos_index) = parts.iter().position(|&part| part == "chat_message") {
-                log::info!("Found chat_message at position {}", pos_index);
+        fn line(&mut self) -> String {
+            self.try_line(TIMEOUT)
+                .expect("the client should say something")
+        }
 
-                // Check if we have enough parts after "chat_message"
-                if pos_index + 2 < parts.len() {
-                    // The format is "chat_message username message_content..."
-                    let username = parts[pos_index + 1].to_string();
+        fn write(&mut self, text: &str) {
+            self.transport.write(text.as_bytes()).unwrap();
+        }
 
-                    // The rest of the message is the chat content
-                    let chat_content = parts[pos_index + 2..].join(" ");
+        // Answers the client's hello without offering any capabilities
+        fn handshake(&mut self) {
+            assert!(self.line().starts_with("hello "));
+            self.write(&format!("SRV: hello {}\r\n", handshake::PROTOCOL_VERSION));
+        }
+    }
 
-                    log::info!("Chat message from {}: {}", username, chat_content);
+    fn line(cts: ClientToServer) -> String {
+        cts.as_line().trim_end().to_string()
+    }
 
-                    return Some(protocol::ServerToClient::ChatMessage(
-                        username,
-                        chat_content,
-                    ));
-                } else {
-                    log::warn!("Not enough parts after chat_message in message");
+    // Receives until `done` says so, keeping the messages and the first error
+    fn recv_until(
+        nc: &mut NetClient,
+        mut done: impl FnMut(&NetClient, &[ServerToClient], Option<&String>) -> bool,
+    ) -> (Vec<ServerToClient>, Option<String>) {
+        let deadline = Instant::now() + TIMEOUT;
+        let (mut messages, mut lost) = (Vec::new(), None);
+        while !done(nc, &messages, lost.as_ref()) {
+            assert!(
+                Instant::now() < deadline,
+                "gave up waiting on the network thread"
+            );
+            match nc.recv() {
+                Ok(received) => messages.extend(received),
+                Err(NCError::ConnectionError(reason)) => {
+                    lost.get_or_insert(reason);
                 }
-            } else {
-                log::warn!("Could not find chat_message in message parts");
+                Err(_) => thread::sleep(Duration::from_millis(1)),
             }
-        } else {
-            log::info!("Message does not contain player_moved, player_joined, or player_left");
         }
+        (messages, lost)
+    }
+
+    fn retry(nc: &NetClient) -> Option<(u32, Instant)> {
+        match nc.connection_state() {
+            ConnectionState::Reconnecting { attempt, retry_at } => Some((attempt, retry_at)),
+            ConnectionState::Connected => None,
+        }
+    }
+
+    #[test]
+    fn test_server_lines_become_messages() {
+        let (connector, listener) = transport::loopback();
+        let mut nc = NetClient::with_connector(connector);
+        let mut server = Server::accept(&listener);
+        server.handshake();
+
+        // Lines arrive whole, several to a read and split across reads
+        server.write("SRV: player_joined alice 32 48 East\r\nSRV: player_le");
+        server.write("ft bob\r\nnot a server line\r\n");
+        server.write("SRV: chat_message alice hi\r\n");
+        let (messages, lost) = recv_until(&mut nc, |_, messages, _| messages.len() == 3);
+        assert_eq!(lost, None);
+        let expected: Vec<ServerToClient> = [
+            "SRV: player_joined alice 32 48 East",
+            "SRV: player_left bob",
+            "SRV: chat_message alice hi",
+        ]
+        .iter()
+        .map(|line| line.parse().unwrap())
+        .collect();
+        assert_eq!(messages, expected);
+        assert!(nc.server_info().is_some());
+    }
+
+    #[test]
+    fn test_commands_are_written_in_order() {
+        let (connector, listener) = transport::loopback();
+        let mut nc = NetClient::with_connector(connector);
+        let sent = [
+            ClientToServer::AttemptPlayerFacingChange(Facing::North),
+            ClientToServer::SetPosition(1, 2),
+            ClientToServer::AttemptPlayerMove(Position::new(3, 4)),
+            ClientToServer::AttemptPlayerFacingChange(Facing::West),
+            ClientToServer::ChatMessage("hi all".to_string()),
+        ];
+
+        // Queued before the connection is even up, and still after the hello
+        nc.send(sent[0].clone()).unwrap();
+        nc.send(sent[1].clone()).unwrap();
+        let mut batch = sent[2..4].to_vec();
+        nc.send_batch(&mut batch).unwrap();
+        assert!(batch.is_empty());
+        nc.send(sent[4].clone()).unwrap();
+
+        let mut server = Server::accept(&listener);
+        assert!(server.line().starts_with("hello "));
+        for cts in sent {
+            assert_eq!(server.line(), line(cts));
+        }
+    }
+
+    #[test]
+    fn test_reconnect_delay_doubles_up_to_a_cap() {
+        let delays: Vec<_> = (0..8).map(reconnect_delay).collect();
+        let ms = Duration::from_millis;
+        assert_eq!(
+            delays,
+            vec![
+                ms(500),
+                ms(1000),
+                ms(2000),
+                ms(4000),
+                ms(8000),
+                ms(16000),
+                RECONNECT_MAX_DELAY,
+                RECONNECT_MAX_DELAY
+            ]
+        );
+        assert_eq!(reconnect_delay(u32::MAX), RECONNECT_MAX_DELAY);
+    }
 
-        None
+    #[test]
+    fn test_reconnects_with_backoff() {
+        let (connector, listener) = transport::loopback();
+        let mut nc = NetClient::with_connector(connector);
+        Server::accept(&listener).handshake();
+
+        // The server hung up, which is reported once, and the first retry
+        // waits the base delay
+        let hung_up = Instant::now();
+        let (_, lost) = recv_until(&mut nc, |nc, _, lost| lost.is_some() && retry(nc).is_some());
+        assert_eq!(lost.as_deref(), Some("Server closed connection"));
+        let (attempt, retry_at) = retry(&nc).unwrap();
+        assert_eq!(attempt, 0);
+        assert!(retry_at >= hung_up + RECONNECT_BASE_DELAY);
+        let server = Server::accept(&listener);
+        assert!(hung_up.elapsed() >= RECONNECT_BASE_DELAY);
+        recv_until(&mut nc, |nc, _, _| retry(nc).is_none());
+
+        // With nobody listening every failed attempt waits twice as long
+        drop(listener);
+        drop(server);
+        recv_until(&mut nc, |nc, _, _| {
+            retry(nc).is_some_and(|(attempt, _)| attempt == 1)
+        });
+        let (_, first) = retry(&nc).unwrap();
+        recv_until(&mut nc, |nc, _, _| {
+            retry(nc).is_some_and(|(attempt, _)| attempt == 2)
+        });
+        let (_, second) = retry(&nc).unwrap();
+        assert!(second >= first + reconnect_delay(1));
+    }
+
+    #[test]
+    fn test_reconnect_resumes_the_session() {
+        let (connector, listener) = transport::loopback();
+        let mut nc = NetClient::with_connector(connector);
+        nc.set_session("alice".to_string(), "secret".to_string());
+        assert!(nc.has_session());
+        let resume = line(ClientToServer::Resume(
+            "alice".to_string(),
+            "secret".to_string(),
+        ));
+
+        let mut server = Server::accept(&listener);
+        server.handshake();
+        assert_eq!(server.line(), resume);
+        drop(server);
+
+        // Straight after the hello on the new connection
+        let mut server = Server::accept(&listener);
+        server.handshake();
+        assert_eq!(server.line(), resume);
+        recv_until(&mut nc, |_, _, lost| lost.is_some());
+        assert!(nc.has_session());
+    }
 
+    #[test]
+    fn test_reconnect_without_session_is_not_logged_in() {
+        let (connector, listener) = transport::loopback();
+        let mut nc = NetClient::with_connector(connector);
+        Server::accept(&listener).handshake();
+        recv_until(&mut nc, |_, _, lost| lost.is_some());
+        assert!(!nc.has_session());
+
+        // The new connection only says hello, and the game asks for the password
+        let mut server = Server::accept(&listener);
+        server.handshake();
+        assert_eq!(server.try_line(Duration::from_millis(100)), None);
+        assert!(!nc.has_session());
     }
 }
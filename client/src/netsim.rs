@@ -0,0 +1,412 @@
+// Simulated bad networks, for reproducing lag bugs on a good connection.
+//
+// `SimulatedTransport` sits between the network thread and the real transport
+// and holds every chunk of bytes back until its delivery time, in both
+// directions. The delivery time is made up of:
+//
+// - the fixed one-way `delay`
+// - a random share of the `jitter`
+// - the time the chunk takes to cross a link of `bandwidth` bytes per second,
+//   queued behind the chunks before it
+// - a retransmission timeout for the `loss` share of chunks, which is how a
+//   lost packet shows up on TCP
+//
+// Chunks normally keep their order, as on TCP. With `reorder` set each one is
+// released as soon as it is due, so jitter shuffles them. Whole reads and writes
+// get shuffled, which usually means whole messages but can split one apart.
+// With `disconnect` set the connection is cut at random, on average that often.
+
+use std::{
+    collections::VecDeque,
+    fmt, io,
+    time::{Duration, Instant},
+};
+
+use crate::transport::Transport;
+
+// Shortest time TCP waits before resending a lost packet
+const MIN_RETRANSMIT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// How bad the simulated network is
+#[derive(Debug, Clone, Default)]
+pub struct Conditions {
+    /// Added to every chunk, in each direction
+    pub delay: Duration,
+    /// Up to this much more is added at random
+    pub jitter: Duration,
+    /// Deliver chunks as soon as they are due instead of in order
+    pub reorder: bool,
+    /// Share of chunks, from 0 to 1, that need a retransmission
+    pub loss: f32,
+    /// Bytes per second in each direction, unlimited if not set
+    pub bandwidth: Option<u32>,
+    /// Mean time between dropped connections
+    pub disconnect: Option<Duration>,
+    /// Seed for the random choices, so a run can be repeated
+    pub seed: u64,
+}
+
+impl fmt::Display for Conditions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}ms delay, {}ms jitter, {:.0}% loss",
+            self.delay.as_millis(),
+            self.jitter.as_millis(),
+            self.loss * 100.0
+        )?;
+        if self.reorder {
+            write!(f, ", reordering")?;
+        }
+        if let Some(bandwidth) = self.bandwidth {
+            write!(f, ", {} bytes/s", bandwidth)?;
+        }
+        if let Some(disconnect) = self.disconnect {
+            write!(f, ", disconnect every ~{:.0}s", disconnect.as_secs_f32())?;
+        }
+        write!(f, ", seed {}", self.seed)
+    }
+}
+
+/// A transport whose traffic is held back according to `Conditions`
+pub struct SimulatedTransport {
+    inner: Box<dyn Transport>,
+    conditions: Conditions,
+    rng: Rng,
+    incoming: Link,
+    outgoing: Link,
+    // What has been delivered but not read yet
+    readable: VecDeque<u8>,
+    // Set once the real transport closed, reported after what was in flight
+    closed: Option<io::Error>,
+    // When the connection gets cut
+    disconnect_at: Option<Instant>,
+}
+
+impl SimulatedTransport {
+    pub fn new(inner: Box<dyn Transport>, conditions: Conditions) -> Self {
+        let mut rng = Rng::new(conditions.seed);
+        let disconnect_at = conditions
+            .disconnect
+            .map(|mean| Instant::now() + rng.exponential(mean));
+        Self {
+            inner,
+            conditions,
+            rng,
+            incoming: Link::default(),
+            outgoing: Link::default(),
+            readable: VecDeque::new(),
+            closed: None,
+            disconnect_at,
+        }
+    }
+
+    // Sends whatever outgoing chunks are due
+    fn flush(&mut self, now: Instant) -> io::Result<()> {
+        while let Some(chunk) = self.outgoing.pop_due(now) {
+            self.inner.write(&chunk)?;
+        }
+        Ok(())
+    }
+
+    // Pulls everything the real transport has into the incoming link
+    fn fill(&mut self, now: Instant) {
+        let mut buffer = [0; 1024];
+        while self.closed.is_none() {
+            match self.inner.read(&mut buffer) {
+                Ok(0) => self.closed = Some(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => {
+                    let chunk = buffer[..n].to_vec();
+                    self.incoming
+                        .push(chunk, now, &self.conditions, &mut self.rng);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => self.closed = Some(e),
+            }
+        }
+    }
+
+    fn check_disconnect(&self, now: Instant) -> io::Result<()> {
+        match self.disconnect_at {
+            Some(at) if now >= at => Err(io::Error::new(
+                io::ErrorKind::ConnectionReset,
+                "Simulated disconnect",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Transport for SimulatedTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let now = Instant::now();
+        self.check_disconnect(now)?;
+        // Reads are polled constantly, so this is where delayed writes go out
+        self.flush(now)?;
+        self.fill(now);
+
+        while let Some(chunk) = self.incoming.pop_due(now) {
+            self.readable.extend(chunk);
+        }
+        if self.readable.is_empty() {
+            return match self.closed.take() {
+                Some(e) if e.kind() == io::ErrorKind::UnexpectedEof && self.incoming.is_empty() => {
+                    Ok(0)
+                }
+                Some(e) if self.incoming.is_empty() => Err(e),
+                closed => {
+                    self.closed = closed;
+                    Err(io::ErrorKind::WouldBlock.into())
+                }
+            };
+        }
+
+        let n = buf.len().min(self.readable.len());
+        for (byte, slot) in self.readable.drain(..n).zip(buf.iter_mut()) {
+            *slot = byte;
+        }
+        Ok(n)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let now = Instant::now();
+        self.check_disconnect(now)?;
+        self.outgoing
+            .push(bytes.to_vec(), now, &self.conditions, &mut self.rng);
+        self.flush(now)
+    }
+}
+
+// One direction of the simulated network
+#[derive(Default)]
+struct Link {
+    // Chunks with their delivery time, in the order they were sent
+    in_flight: VecDeque<(Instant, Vec<u8>)>,
+    // When the last chunk finished crossing the bandwidth cap
+    busy_until: Option<Instant>,
+    // Delivery time of the last chunk, which in-order delivery may not undercut
+    last_delivery: Option<Instant>,
+}
+
+impl Link {
+    fn push(&mut self, chunk: Vec<u8>, now: Instant, conditions: &Conditions, rng: &mut Rng) {
+        let mut sent = now;
+        if let Some(bandwidth) = conditions.bandwidth {
+            let start = self.busy_until.map_or(now, |busy| busy.max(now));
+            sent = start + Duration::from_secs_f64(chunk.len() as f64 / bandwidth.max(1) as f64);
+            self.busy_until = Some(sent);
+        }
+
+        let mut delivery = sent + conditions.delay + conditions.jitter.mul_f64(rng.unit());
+        if rng.unit() < conditions.loss as f64 {
+            delivery += MIN_RETRANSMIT_TIMEOUT.max(conditions.delay * 2);
+        }
+        if !conditions.reorder {
+            delivery = self
+                .last_delivery
+                .map_or(delivery, |last| last.max(delivery));
+        }
+        self.last_delivery = Some(delivery);
+        self.in_flight.push_back((delivery, chunk));
+    }
+
+    // The earliest sent chunk that is due; with reordering that need not be the first
+    fn pop_due(&mut self, now: Instant) -> Option<Vec<u8>> {
+        let index = self.in_flight.iter().position(|(due, _)| *due <= now)?;
+        self.in_flight.remove(index).map(|(_, chunk)| chunk)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.in_flight.is_empty()
+    }
+}
+
+// xorshift64*, plenty for picking delays
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // Zero would get stuck at zero
+        Self(seed.max(1))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    // Uniform in [0, 1)
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Exponentially distributed around `mean`, the gaps between random events
+    fn exponential(&mut self, mean: Duration) -> Duration {
+        mean.mul_f64(-(1.0 - self.unit()).ln())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::transport::LoopbackTransport;
+
+    // A simulated client end and the server end it talks to
+    fn pair(conditions: Conditions) -> (SimulatedTransport, LoopbackTransport) {
+        let (client, server) = LoopbackTransport::pair();
+        (
+            SimulatedTransport::new(Box::new(client), conditions),
+            server,
+        )
+    }
+
+    // Keeps the simulated end busy, as the network thread does, until `count`
+    // chunks reached the server, and returns them with when they arrived
+    fn deliver(
+        client: &mut SimulatedTransport,
+        server: &mut LoopbackTransport,
+        count: usize,
+    ) -> Vec<(Instant, Vec<u8>)> {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut received = Vec::new();
+        let mut buffer = [0; 1024];
+        while received.len() < count {
+            assert!(
+                Instant::now() < deadline,
+                "only {} chunks arrived",
+                received.len()
+            );
+            let _ = client.read(&mut buffer);
+            while let Ok(n) = server.read(&mut buffer) {
+                received.push((Instant::now(), buffer[..n].to_vec()));
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+        received
+    }
+
+    #[test]
+    fn test_delay_keeps_order() {
+        let delay = Duration::from_millis(50);
+        let (mut client, mut server) = pair(Conditions {
+            delay,
+            jitter: Duration::from_millis(30),
+            seed: 1,
+            ..Conditions::default()
+        });
+        let start = Instant::now();
+        for chunk in [b"a", b"b", b"c"] {
+            client.write(chunk).unwrap();
+        }
+        let mut buffer = [0; 8];
+        assert_eq!(
+            server.read(&mut buffer).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        let received = deliver(&mut client, &mut server, 3);
+        assert!(received.iter().all(|(at, _)| *at >= start + delay));
+        let chunks: Vec<_> = received.into_iter().map(|(_, chunk)| chunk).collect();
+        assert_eq!(chunks, vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]);
+
+        // The other way is held back just the same
+        let start = Instant::now();
+        server.write(b"hi").unwrap();
+        let arrived = loop {
+            match client.read(&mut buffer) {
+                Ok(n) => break n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(1))
+                }
+                Err(e) => panic!("read failed: {}", e),
+            }
+        };
+        assert_eq!(&buffer[..arrived], b"hi");
+        assert!(start.elapsed() >= delay);
+    }
+
+    #[test]
+    fn test_reorder_within_jitter() {
+        let conditions = Conditions {
+            jitter: Duration::from_millis(50),
+            reorder: true,
+            seed: 7,
+            ..Conditions::default()
+        };
+        let sent: Vec<Vec<u8>> = (0..20u8).map(|byte| vec![byte]).collect();
+        let run = |conditions: Conditions| {
+            let (mut client, mut server) = pair(conditions);
+            for chunk in &sent {
+                client.write(chunk).unwrap();
+            }
+            deliver(&mut client, &mut server, sent.len())
+                .into_iter()
+                .map(|(_, chunk)| chunk)
+                .collect::<Vec<_>>()
+        };
+
+        // Everything arrives, just not in the order it was sent
+        let mut chunks = run(conditions.clone());
+        assert_ne!(chunks, sent);
+        chunks.sort();
+        assert_eq!(chunks, sent);
+
+        // The same jitter keeps the order without reordering
+        let in_order = run(Conditions {
+            reorder: false,
+            ..conditions
+        });
+        assert_eq!(in_order, sent);
+    }
+
+    #[test]
+    fn test_bandwidth_spreads_bytes_over_time() {
+        // 500 bytes take 50ms at 10 kB/s, so three chunks need 150ms
+        let (mut client, mut server) = pair(Conditions {
+            bandwidth: Some(10_000),
+            ..Conditions::default()
+        });
+        let start = Instant::now();
+        for _ in 0..3 {
+            client.write(&[0; 500]).unwrap();
+        }
+
+        let received = deliver(&mut client, &mut server, 3);
+        for (index, (at, chunk)) in received.iter().enumerate() {
+            assert_eq!(chunk.len(), 500);
+            assert!(*at >= start + Duration::from_millis(50) * (index as u32 + 1));
+        }
+    }
+
+    #[test]
+    fn test_disconnect_is_an_error() {
+        let (mut client, _server) = pair(Conditions {
+            disconnect: Some(Duration::from_millis(20)),
+            seed: 3,
+            ..Conditions::default()
+        });
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut buffer = [0; 8];
+        let error = loop {
+            match client.read(&mut buffer) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    assert!(Instant::now() < deadline, "the connection was never cut");
+                    thread::sleep(Duration::from_millis(1));
+                }
+                Err(e) => break e,
+                Ok(n) => panic!("read {} bytes nobody sent", n),
+            }
+        };
+        assert_eq!(error.kind(), io::ErrorKind::ConnectionReset);
+        // It stays cut
+        assert_eq!(
+            client.write(b"hello").unwrap_err().kind(),
+            io::ErrorKind::ConnectionReset
+        );
+    }
+}
@@ -0,0 +1,239 @@
+// Outgoing movement, sent in batches once per network tick.
+//
+// The simulation runs far more often than the server does, so rather than
+// telling it about every tick the game hands its movement to the `Outbox`,
+// which keeps only what the server needs:
+//
+// - numbered inputs for servers that predict, every one of them and in order
+// - otherwise the newest position and facing, and only when they differ from
+//   what the server was last told
+//
+// Whatever piled up goes out as one batch at most once per `SEND_INTERVAL`, and
+// counts as sent only once the network thread took it. A player standing still
+// sends nothing at all.
+
+use std::time::{Duration, Instant};
+
+use protocol::{ClientToServer, Facing, Position, zones::ZoneLink};
+
+use crate::net::{NCError, NetClient};
+
+/// Shortest time between two batches, the planned server tick
+pub const SEND_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Where batches go, the server connection outside of tests
+pub trait BatchSender {
+    /// Sends the messages, taking them out of `batch` once they are on their
+    /// way and leaving them there when they cannot go out
+    fn send_batch(&mut self, batch: &mut Vec<ClientToServer>) -> Result<(), NCError>;
+}
+
+impl BatchSender for NetClient {
+    fn send_batch(&mut self, batch: &mut Vec<ClientToServer>) -> Result<(), NCError> {
+        NetClient::send_batch(self, batch)
+    }
+}
+
+/// Movement waiting to go out to the server
+#[derive(Default)]
+pub struct Outbox {
+    // Messages that must all arrive, oldest first
+    queue: Vec<ClientToServer>,
+    // Newest position and facing, folded into the next batch
+    pos: Option<Position>,
+    facing: Option<Facing>,
+    // What the server was last told
+    sent_pos: Option<Position>,
+    sent_facing: Option<Facing>,
+    // No batch goes out before this
+    next_send: Option<Instant>,
+}
+
+impl Outbox {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Notes where we stand, for servers that take positions
+    pub fn move_to(&mut self, pos: Position, facing: Facing) {
+        self.pos = Some(pos);
+        self.facing = Some(facing);
+    }
+
+    /// Queues a numbered input, for servers that acknowledge them
+    pub fn input(&mut self, input: ClientToServer) {
+        self.queue.push(input);
+    }
+
+    /// Queues a move to another room, ahead of any movement made after it
+    pub fn goto(&mut self, link: ZoneLink) {
+        self.queue.push(ClientToServer::Goto(link));
+    }
+
+    /// Queues an absolute position, e.g. after walking through a door, ahead of
+    /// any movement made after it
+    pub fn teleport(&mut self, pos: Position, facing: Facing) {
+        self.queue
+            .push(ClientToServer::AttemptPlayerFacingChange(facing));
+        self.queue.push(ClientToServer::SetPosition(pos.x, pos.y));
+        self.pos = None;
+        self.facing = None;
+    }
+
+    /// Sends what changed since the last batch, unless one went out too recently
+    pub fn flush(&mut self, now: Instant, sender: &mut impl BatchSender) {
+        if self.next_send.is_some_and(|next| now < next) {
+            return;
+        }
+
+        let mut batch = std::mem::take(&mut self.queue);
+        let queued = batch.len();
+        let mut sent_pos = None;
+        let mut sent_facing = None;
+        for message in &batch {
+            match *message {
+                ClientToServer::AttemptPlayerFacingChange(facing) => sent_facing = Some(facing),
+                ClientToServer::SetPosition(x, y) => sent_pos = Some(Position::new(x, y)),
+                _ => {}
+            }
+        }
+        if let Some(facing) = self.facing
+            && self.sent_facing != Some(facing)
+        {
+            batch.push(ClientToServer::AttemptPlayerFacingChange(facing));
+            sent_facing = Some(facing);
+        }
+        if let Some(pos) = self.pos
+            && self.sent_pos != Some(pos)
+        {
+            batch.push(ClientToServer::AttemptPlayerMove(pos));
+            sent_pos = Some(pos);
+        }
+        if batch.is_empty() {
+            return;
+        }
+
+        log::trace!("Sending a batch of {} movement messages", batch.len());
+        // Keep everything for the next try while the connection is down
+        if sender.send_batch(&mut batch).is_err() {
+            batch.truncate(queued);
+            self.queue = batch;
+            return;
+        }
+        self.pos = None;
+        self.facing = None;
+        self.sent_pos = sent_pos.or(self.sent_pos);
+        self.sent_facing = sent_facing.or(self.sent_facing);
+        self.next_send = Some(now + SEND_INTERVAL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ClientToServer::*;
+
+    // Keeps every batch it takes, or refuses them while it is down
+    #[derive(Default)]
+    struct Server {
+        batches: Vec<Vec<ClientToServer>>,
+        down: bool,
+    }
+
+    impl BatchSender for Server {
+        fn send_batch(&mut self, batch: &mut Vec<ClientToServer>) -> Result<(), NCError> {
+            if self.down {
+                return Err(NCError::SendError);
+            }
+            self.batches.push(std::mem::take(batch));
+            Ok(())
+        }
+    }
+
+    const AFTER: Duration = SEND_INTERVAL;
+
+    #[test]
+    fn test_standing_still_sends_nothing() {
+        let (mut outbox, mut server, start) = (Outbox::new(), Server::default(), Instant::now());
+        outbox.flush(start, &mut server);
+        assert!(server.batches.is_empty());
+
+        let pos = Position::new(10, 20);
+        outbox.move_to(pos, Facing::South);
+        outbox.flush(start, &mut server);
+        assert_eq!(
+            server.batches,
+            vec![vec![
+                AttemptPlayerFacingChange(Facing::South),
+                AttemptPlayerMove(pos)
+            ]]
+        );
+
+        for tick in 1..5 {
+            outbox.move_to(pos, Facing::South);
+            outbox.flush(start + AFTER * tick, &mut server);
+        }
+        assert_eq!(server.batches.len(), 1);
+    }
+
+    #[test]
+    fn test_one_batch_per_interval() {
+        let (mut outbox, mut server, start) = (Outbox::new(), Server::default(), Instant::now());
+        outbox.move_to(Position::new(0, 0), Facing::East);
+        outbox.flush(start, &mut server);
+
+        // Everything within the interval waits, and only the newest position goes
+        for x in 1..=5 {
+            outbox.move_to(Position::new(x, 0), Facing::East);
+            outbox.flush(start + AFTER / 10 * x as u32, &mut server);
+        }
+        assert_eq!(server.batches.len(), 1);
+        outbox.flush(start + AFTER, &mut server);
+        assert_eq!(
+            server.batches[1],
+            vec![AttemptPlayerMove(Position::new(5, 0))]
+        );
+    }
+
+    #[test]
+    fn test_turning_in_place_is_sent() {
+        let (mut outbox, mut server, start) = (Outbox::new(), Server::default(), Instant::now());
+        let pos = Position::new(10, 20);
+        outbox.move_to(pos, Facing::South);
+        outbox.flush(start, &mut server);
+
+        outbox.move_to(pos, Facing::West);
+        outbox.flush(start + AFTER, &mut server);
+        assert_eq!(
+            server.batches[1],
+            vec![AttemptPlayerFacingChange(Facing::West)]
+        );
+    }
+
+    #[test]
+    fn test_retries_after_a_failed_send() {
+        let (mut outbox, mut server, start) = (Outbox::new(), Server::default(), Instant::now());
+        server.down = true;
+        outbox.input(Input(1, 1, 0, Facing::East));
+        outbox.move_to(Position::new(1, 0), Facing::East);
+        outbox.flush(start, &mut server);
+        outbox.input(Input(2, 1, 0, Facing::East));
+        outbox.flush(start, &mut server);
+        assert!(server.batches.is_empty());
+
+        // Nothing is lost or doubled, and the failures did not hold it back
+        server.down = false;
+        outbox.flush(start, &mut server);
+        assert_eq!(
+            server.batches,
+            vec![vec![
+                Input(1, 1, 0, Facing::East),
+                Input(2, 1, 0, Facing::East),
+                AttemptPlayerFacingChange(Facing::East),
+                AttemptPlayerMove(Position::new(1, 0)),
+            ]]
+        );
+        outbox.flush(start + AFTER, &mut server);
+        assert_eq!(server.batches.len(), 1);
+    }
+}
@@ -1,13 +1,19 @@
 use crate::{
     assets::AssetManager,
+    entity::Entity,
     input::{MovementState, PLAYER_SIZE},
+    interpolation::Snapshots,
     map::Map,
 };
 use ggez::{
     Context, GameResult,
     graphics::{self, Drawable},
 };
-use protocol::{Facing, Position};
+use protocol::{EntityId, EntityKind, Facing, Position};
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Instant,
+};
 // Animation constants
 // const ANIMATION_FRAME_TIME: f32 = 0.15; // Slightly slower animation for better visibility
 const MAX_FRAMES: usize = 4; // Knight has 4 animation frames
@@ -51,10 +57,36 @@ impl CharacterType {
     }
 }
 
+/// Where a player at `pos` ends up after moving by `dx`, `dy`, sliding along
+/// walls. `None` if the map blocks both directions.
+pub fn step(pos: Position, dx: i32, dy: i32, map: &Map, grid_size: i32) -> Option<Position> {
+    // Check if the new position is valid
+    if map.is_valid_position(pos.x + dx, pos.y + dy, grid_size) {
+        return Some(Position::new(pos.x + dx, pos.y + dy));
+    }
+
+    // If we can't move in both directions, try moving in just one
+    let mut moved = None;
+    let mut pos = pos;
+    if map.is_valid_position(pos.x + dx, pos.y, grid_size) {
+        pos.x += dx;
+        moved = Some(pos);
+    }
+    if map.is_valid_position(pos.x, pos.y + dy, grid_size) {
+        pos.y += dy;
+        moved = Some(pos);
+    }
+    moved
+}
+
 pub struct Player {
     #[allow(unused)]
     pub name: String,
     pub pos: Position,
+    // Where the last tick started, drawn blended with `pos`
+    pub prev_pos: Position,
+    // Zone path of the room the player is in, empty if the server never said
+    pub zone: Vec<String>,
     pub current_frame: usize,
     pub frame_timer: f32,
     pub direction: Facing,
@@ -71,6 +103,8 @@ impl Player {
         Self {
             name,
             pos,
+            prev_pos: pos,
+            zone: Vec::new(),
             current_frame: 0,
             frame_timer: 0.0,
             direction: Facing::South,
@@ -89,29 +123,10 @@ impl Player {
             self.direction = movement.direction;
         }
 
-        // Calculate new position based on movement
-        let new_x = self.pos.x + movement.dx;
-        let new_y = self.pos.y + movement.dy;
-
-        // Check if the new position is valid
-        if map.is_valid_position(new_x, new_y, grid_size) {
-            self.pos.x = new_x;
-            self.pos.y = new_y;
+        // Move unless the map blocks us in both directions
+        if let Some(pos) = step(self.pos, movement.dx, movement.dy, map, grid_size) {
+            self.pos = pos;
             self.is_moving = movement.is_moving;
-        } else {
-            // If we can't move in both directions, try moving in just one
-            let new_x_only = self.pos.x + movement.dx;
-            let new_y_only = self.pos.y + movement.dy;
-
-            if map.is_valid_position(new_x_only, self.pos.y, grid_size) {
-                self.pos.x = new_x_only;
-                self.is_moving = movement.is_moving;
-            }
-
-            if map.is_valid_position(self.pos.x, new_y_only, grid_size) {
-                self.pos.y = new_y_only;
-                self.is_moving = movement.is_moving;
-            }
         }
 
         // Update chat message timer
@@ -161,7 +176,10 @@ impl Player {
         ctx: &Context,
         canvas: &mut graphics::Canvas,
         asset_manager: &AssetManager,
+        alpha: f32,
     ) -> GameResult<()> {
+        let (x, y) = self.draw_pos(alpha);
+
         // Get the character folder name
         let character = self.character_type.folder_name();
         let _gender = "M"; // Using male characters for now
@@ -206,15 +224,15 @@ impl Player {
 
             // Draw the hero sprite at the correct position
             let mut draw_params =
-                graphics::DrawParam::default().dest([self.pos.x as f32, self.pos.y as f32]);
+                graphics::DrawParam::default().dest([x, y]);
 
             // Apply scaling
             if flip_x {
                 // For flipped sprites, we need to adjust the destination point
                 // Set the destination to account for the flipped sprite
                 draw_params = draw_params
-                    .dest([self.pos.x as f32 + scaled_width, self.pos.y as f32])
-                    .scale([scale_factor * -1.0, scale_factor]);
+                    .dest([x + scaled_width, y])
+                    .scale([-scale_factor, scale_factor]);
             } else {
                 draw_params = draw_params.scale([scale_factor, scale_factor]);
             }
@@ -226,19 +244,19 @@ impl Player {
 
                 // Get the current x position (which already accounts for flipping if needed)
                 let x_pos = if flip_x {
-                    self.pos.x as f32 + scaled_width
+                    x + scaled_width
                 } else {
-                    self.pos.x as f32
+                    x
                 };
 
                 // Apply the y offset while preserving the x position
-                draw_params = draw_params.dest([x_pos, self.pos.y as f32 - y_offset]);
+                draw_params = draw_params.dest([x_pos, y - y_offset]);
             }
 
             canvas.draw(&hero_asset.img, draw_params);
 
             // Calculate the center position for text elements
-            let center_x = self.pos.x as f32 + (hero_asset.img.width() as f32 * scale_factor / 2.0);
+            let center_x = x + (hero_asset.img.width() as f32 * scale_factor / 2.0);
 
             // Draw player name above the sprite
             let name_text = graphics::Text::new(&self.name);
@@ -246,7 +264,7 @@ impl Player {
             canvas.draw(
                 &name_text,
                 graphics::DrawParam::default()
-                    .dest([center_x - (name_width / 2.0), self.pos.y as f32 - 20.0])
+                    .dest([center_x - (name_width / 2.0), y - 20.0])
                     .color(graphics::Color::WHITE),
             );
 
@@ -261,7 +279,7 @@ impl Player {
                 let bubble_padding = 5.0;
                 let bubble_rect = graphics::Rect::new(
                     center_x - (chat_width / 2.0) - bubble_padding,
-                    self.pos.y as f32 - 45.0 - chat_height,
+                    y - 45.0 - chat_height,
                     chat_width + (bubble_padding * 2.0),
                     chat_height + (bubble_padding * 2.0),
                 );
@@ -282,7 +300,7 @@ impl Player {
                     graphics::DrawParam::default()
                         .dest([
                             center_x - (chat_width / 2.0),
-                            self.pos.y as f32 - 45.0 - chat_height + bubble_padding,
+                            y - 45.0 - chat_height + bubble_padding,
                         ])
                         .color(graphics::Color::WHITE),
                 );
@@ -296,7 +314,7 @@ impl Player {
                 let scale_factor = 0.75;
 
                 let draw_params = graphics::DrawParam::default()
-                    .dest([self.pos.x as f32, self.pos.y as f32])
+                    .dest([x, y])
                     .scale([scale_factor, scale_factor]);
 
                 canvas.draw(&player_asset.img, draw_params);
@@ -316,7 +334,7 @@ impl Player {
                 canvas.draw(
                     &graphics::Quad,
                     graphics::DrawParam::default()
-                        .dest([self.pos.x as f32, self.pos.y as f32])
+                        .dest([x, y])
                         .scale([rect_size, rect_size])
                         .color(color),
                 );
@@ -330,6 +348,27 @@ impl Player {
         self.character_type = self.character_type.next();
     }
 
+    /// Where to draw the player, `alpha` of the way from the last tick to the next
+    pub fn draw_pos(&self, alpha: f32) -> (f32, f32) {
+        let blend = |from: i32, to: i32| from as f32 + (to - from) as f32 * alpha;
+        (
+            blend(self.prev_pos.x, self.pos.x),
+            blend(self.prev_pos.y, self.pos.y),
+        )
+    }
+
+    /// Whether someone in `zone` is in the same room as this player. An unknown
+    /// zone on either side counts as the same, as servers before zones never say
+    pub fn shares_zone(&self, zone: &[String]) -> bool {
+        zone.is_empty() || self.zone.is_empty() || self.zone == zone
+    }
+
+    /// Moves the player without drawing them sliding there, e.g. through a door
+    pub fn teleport(&mut self, pos: Position) {
+        self.pos = pos;
+        self.prev_pos = pos;
+    }
+
     // Add a new method to set a chat message
     pub fn set_chat_message(&mut self, message: String) {
         self.chat_message = Some(message);
@@ -339,31 +378,45 @@ impl Player {
 
 pub struct Players {
     pub self_player: Player,
-    pub other_players: Vec<Player>,
+    // Everyone and everything else in the world, keyed by entity id
+    pub entities: BTreeMap<EntityId, Entity>,
+    // Where the server has put each entity lately, which is smoothed out for drawing
+    snapshots: HashMap<EntityId, Snapshots>,
+    // Chat and older servers still address players by username
+    ids_by_name: HashMap<String, EntityId>,
+    // Ids handed out for players that older servers only know by name
+    next_local_id: u32,
 }
 
 impl Players {
     pub fn new(player_name: String, start_pos: Position) -> Self {
         Self {
             self_player: Player::new(player_name, start_pos),
-            other_players: Vec::new(),
+            entities: BTreeMap::new(),
+            snapshots: HashMap::new(),
+            ids_by_name: HashMap::new(),
+            // Count down from the top so they stay clear of server assigned ids
+            next_local_id: u32::MAX,
         }
     }
 
     pub fn update(&mut self, movement: &MovementState, map: &Map, grid_size: i32, delta_time: f32) {
+        self.self_player.prev_pos = self.self_player.pos;
         self.self_player
             .update(movement, map, grid_size, delta_time);
 
-        // Also update other players with the same delta time
-        for player in &mut self.other_players {
-            // For other players, we don't apply movement from local input
-            let no_movement = MovementState {
-                is_moving: player.is_moving,
-                direction: player.direction,
-                dx: 0,
-                dy: 0,
-            };
-            player.update(&no_movement, map, grid_size, delta_time);
+        // Also update every other entity with the same delta time
+        let now = Instant::now();
+        for (id, entity) in self.entities.iter_mut() {
+            // Whoever is in another room is left alone until they are back
+            if !self.self_player.shares_zone(entity.zone()) {
+                continue;
+            }
+            if let Some(snapshots) = self.snapshots.get_mut(id) {
+                let (pos, moving) = snapshots.sample(now);
+                entity.place(pos, moving);
+            }
+            entity.update(map, grid_size, delta_time);
         }
     }
 
@@ -372,47 +425,119 @@ impl Players {
         self.self_player.switch_character();
     }
 
-    // New methods for multiplayer support
+    // Entity id based world updates
+
+    // Add an entity, replacing whatever had the same id before
+    pub fn spawn_entity(&mut self, id: EntityId, kind: EntityKind, pos: Position, facing: Facing) {
+        if let EntityKind::Player(name) = &kind {
+            self.ids_by_name.insert(name.clone(), id);
+        }
+        self.entities.insert(id, Entity::new(kind, pos, facing));
+        self.snapshots
+            .insert(id, Snapshots::new(pos, Instant::now()));
+    }
+
+    // Apply a position update from the server; the entity gets there over the next frames
+    pub fn move_entity(&mut self, id: EntityId, pos: Position, facing: Facing) {
+        match (self.entities.get_mut(&id), self.snapshots.get_mut(&id)) {
+            (Some(entity), Some(snapshots)) => {
+                entity.face(facing);
+                snapshots.push(pos, Instant::now());
+            }
+            _ => log::trace!("Ignoring move of unknown entity {}", id),
+        }
+    }
+
+    pub fn despawn_entity(&mut self, id: EntityId) {
+        self.snapshots.remove(&id);
+        if let Some(entity) = self.entities.remove(&id)
+            && self.ids_by_name.get(entity.name()) == Some(&id)
+        {
+            self.ids_by_name.remove(entity.name());
+        }
+    }
+
+    // Forget everyone else, e.g. after leaving the game
+    pub fn clear_others(&mut self) {
+        self.entities.clear();
+        self.snapshots.clear();
+        self.ids_by_name.clear();
+    }
+
+    // Username based updates, as sent by servers without entity ids
 
     // Add a new player or update an existing one
-    pub fn add_or_update_player(&mut self, name: String, pos: Position, facing: Facing) {
-        // Check if player already exists
-        for player in &mut self.other_players {
-            if player.name == name {
-                // Update existing player
-                player.pos = pos;
-                player.direction = facing;
-                player.is_moving = true; // Assume they're moving since we got an update
-                return;
+    pub fn add_or_update_player(
+        &mut self,
+        name: String,
+        pos: Position,
+        facing: Facing,
+        zone: Vec<String>,
+    ) {
+        if let Some(&id) = self.ids_by_name.get(&name) {
+            if let Some(player) = self
+                .entities
+                .get_mut(&id)
+                .and_then(Entity::as_character_mut)
+                && player.zone != zone
+            {
+                // They went to another room, so they appear there instead of sliding over
+                player.zone = zone;
+                player.teleport(pos);
+                self.snapshots
+                    .insert(id, Snapshots::new(pos, Instant::now()));
             }
+            self.move_entity(id, pos, facing);
+            return;
         }
 
-        // Player doesn't exist, add a new one
-        let mut new_player = Player::new(name, pos);
-        new_player.direction = facing;
-        self.other_players.push(new_player);
+        // Player doesn't exist, add a new one under a local id
+        let id = EntityId(self.next_local_id);
+        self.next_local_id -= 1;
+        self.spawn_entity(id, EntityKind::Player(name), pos, facing);
+        if let Some(player) = self
+            .entities
+            .get_mut(&id)
+            .and_then(Entity::as_character_mut)
+        {
+            player.zone = zone;
+        }
     }
 
     // Remove a player by name
     pub fn remove_player(&mut self, name: &str) {
-        self.other_players.retain(|player| player.name != name);
-    }
-
-    // Update a player's position and facing
-    pub fn update_player_position(&mut self, name: &str, pos: Position, facing: Facing) {
-        for player in &mut self.other_players {
-            if player.name == name {
-                player.pos = pos;
-                player.direction = facing;
-                player.is_moving = true; // They're moving since we got an update
-                // Reset the frame timer to animate movement
-                player.frame_timer = 0.0;
-                return;
-            }
+        if let Some(id) = self.ids_by_name.remove(name) {
+            self.entities.remove(&id);
+            self.snapshots.remove(&id);
         }
+    }
 
-        // If we didn't find the player, add them
-        self.add_or_update_player(name.to_string(), pos, facing);
+    // Update a player's position, facing and zone, adding them if we haven't seen them yet
+    pub fn update_player_position(
+        &mut self,
+        name: &str,
+        pos: Position,
+        facing: Facing,
+        zone: Vec<String>,
+    ) {
+        self.add_or_update_player(name.to_string(), pos, facing, zone);
+    }
+
+    // Update only a player's facing, e.g. when they turn on the spot
+    pub fn update_player_facing(&mut self, name: &str, facing: Facing) {
+        if let Some(entity) = self.player_entity_mut(name) {
+            entity.face(facing);
+        }
+    }
+
+    /// Looks up another player's character by username.
+    pub fn player_mut(&mut self, name: &str) -> Option<&mut Player> {
+        self.player_entity_mut(name)?.as_character_mut()
+    }
+
+    fn player_entity_mut(&mut self, name: &str) -> Option<&mut Entity> {
+        let id = self.ids_by_name.get(name)?;
+        self.entities.get_mut(id)
     }
 
     // Debug method to print all players
@@ -425,30 +550,40 @@ impl Players {
             self.self_player.pos.y
         );
 
-        for (i, player) in self.other_players.iter().enumerate() {
+        for (id, entity) in &self.entities {
+            let pos = entity.pos();
             log::info!(
-                "Other[{}]: {} at ({}, {})",
-                i,
-                player.name,
-                player.pos.x,
-                player.pos.y
+                "Entity[{}]: {} at ({}, {})",
+                id,
+                entity.name(),
+                pos.x,
+                pos.y
             );
         }
         log::info!("----------------------");
     }
 
+    // Everything else that is in the same room as us, see `Player::shares_zone`
+    fn visible_entities(&self) -> impl Iterator<Item = &Entity> {
+        self.entities
+            .values()
+            .filter(|entity| self.self_player.shares_zone(entity.zone()))
+    }
+
     pub fn draw(
         &self,
         ctx: &Context,
         canvas: &mut graphics::Canvas,
         asset_manager: &AssetManager,
+        alpha: f32,
     ) -> GameResult<()> {
         // Draw the main player
-        self.self_player.draw(ctx, canvas, asset_manager)?;
+        self.self_player
+            .draw(ctx, canvas, asset_manager, alpha)?;
 
-        // Draw other players
-        for player in &self.other_players {
-            player.draw(ctx, canvas, asset_manager)?;
+        // Draw everything else in the same room
+        for entity in self.visible_entities() {
+            entity.draw(ctx, canvas, asset_manager, alpha)?;
         }
 
         Ok(())
@@ -462,12 +597,109 @@ impl Players {
             return;
         }
 
-        // Otherwise, find the player in other_players
-        for player in &mut self.other_players {
-            if player.name == username {
-                player.set_chat_message(message);
-                break;
-            }
+        // Otherwise, find the player among the entities
+        if let Some(player) = self.player_mut(username) {
+            player.set_chat_message(message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::ascii;
+
+    fn zone(path: &[&str]) -> Vec<String> {
+        path.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn open_room() -> Map {
+        ascii::parse("# = Wall\n\n########\n#      #\n#      #\n#      #\n#      #\n########\n")
+            .unwrap()
+    }
+
+    fn players_in(path: &[&str]) -> Players {
+        let mut players = Players::new("me".to_string(), Position::new(40, 40));
+        players.self_player.zone = zone(path);
+        players
+    }
+
+    fn visible_names(players: &Players) -> Vec<&str> {
+        let mut names: Vec<_> = players.visible_entities().map(Entity::name).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_shares_zone() {
+        let mut player = Player::new("me".to_string(), Position::new(0, 0));
+        player.zone = zone(&["hub", "room0"]);
+
+        assert!(player.shares_zone(&zone(&["hub", "room0"])));
+        assert!(!player.shares_zone(&zone(&["hub", "room1"])));
+        assert!(!player.shares_zone(&zone(&["town", "room0"])));
+        assert!(!player.shares_zone(&zone(&["hub"])));
+
+        // Servers without zones leave the path empty, on either side
+        assert!(player.shares_zone(&[]));
+        player.zone.clear();
+        assert!(player.shares_zone(&zone(&["hub", "room1"])));
+        assert!(player.shares_zone(&[]));
+    }
+
+    #[test]
+    fn test_other_rooms_are_not_drawn() {
+        let mut players = players_in(&["hub", "room0"]);
+        let pos = Position::new(48, 48);
+        for (name, path) in [
+            ("near", zone(&["hub", "room0"])),
+            ("far", zone(&["hub", "room1"])),
+            ("old", Vec::new()),
+        ] {
+            players.add_or_update_player(name.to_string(), pos, Facing::South, path);
         }
+        players.spawn_entity(
+            EntityId(1),
+            EntityKind::Item("key".to_string()),
+            pos,
+            Facing::South,
+        );
+        assert_eq!(visible_names(&players), vec!["key", "near", "old"]);
+
+        // Once they walk over they show up again
+        players.add_or_update_player(
+            "far".to_string(),
+            pos,
+            Facing::South,
+            zone(&["hub", "room0"]),
+        );
+        assert_eq!(visible_names(&players), vec!["far", "key", "near", "old"]);
+    }
+
+    #[test]
+    fn test_other_rooms_are_not_updated() {
+        let map = open_room();
+        let mut players = players_in(&["hub", "room0"]);
+        let pos = Position::new(48, 48);
+        for (name, path) in [
+            ("near", zone(&["hub", "room0"])),
+            ("far", zone(&["hub", "room1"])),
+        ] {
+            players.add_or_update_player(name.to_string(), pos, Facing::South, path);
+            players.set_player_chat_message(name, "hi".to_string());
+        }
+
+        // Long enough for a chat bubble to run out, but only for whoever was updated
+        let movement = MovementState {
+            is_moving: false,
+            direction: Facing::South,
+            dx: 0,
+            dy: 0,
+        };
+        players.update(&movement, &map, 16, 10.0);
+        assert_eq!(players.player_mut("near").unwrap().chat_message, None);
+        let far = players.player_mut("far").unwrap();
+        assert_eq!(far.chat_message.as_deref(), Some("hi"));
+        assert_eq!(far.idle_timer, 0.0);
     }
 }
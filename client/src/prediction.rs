@@ -0,0 +1,250 @@
+// Client-side prediction for the local player.
+//
+// On servers announcing `input_ack` every tick of movement goes out as
+// `input <seq> <dx> <dy> <facing>` and is applied locally straight away, so
+// moving never waits for the server. Turning on the spot goes out the same way,
+// as an input that does not move. The server applies the same inputs in
+// order and answers with `position_ack <seq> <x> <y> <facing>`, where the
+// player stood after input `seq`.
+//
+// The inputs the server has not acknowledged yet wait in a buffer. On every ack
+// the acknowledged ones are dropped and the rest are replayed on top of the
+// server's position. If the server accepted everything that lands exactly where
+// we already are; if it rejected or corrected a move, we snap to where the
+// server says plus our own inputs since.
+
+use std::collections::VecDeque;
+
+use protocol::{ClientToServer, Facing, Position};
+
+use crate::{map::Map, player};
+
+// Most inputs kept waiting for an ack; older ones are forgotten past this
+const MAX_PENDING_INPUTS: usize = 1024;
+
+// One tick of movement we sent
+struct Input {
+    seq: u32,
+    dx: i32,
+    dy: i32,
+}
+
+/// Inputs sent but not yet acknowledged by the server
+pub struct Prediction {
+    next_seq: u32,
+    pending: VecDeque<Input>,
+    // Acks up to here were handled already, or predate a teleport
+    acked: u32,
+    // The facing of the last input sent
+    facing: Option<Facing>,
+}
+
+impl Default for Prediction {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Prediction {
+    pub fn new() -> Self {
+        Self {
+            next_seq: 1,
+            pending: VecDeque::new(),
+            acked: 0,
+            facing: None,
+        }
+    }
+
+    /// Numbers a tick that was just applied locally if the server needs to
+    /// hear of it, which is when we moved or turned on the spot
+    pub fn input(&mut self, dx: i32, dy: i32, facing: Facing) -> Option<ClientToServer> {
+        if dx == 0 && dy == 0 && self.facing == Some(facing) {
+            return None;
+        }
+        Some(self.record(dx, dy, facing))
+    }
+
+    /// Numbers a tick of movement that was just applied locally and returns
+    /// the message announcing it to the server
+    pub fn record(&mut self, dx: i32, dy: i32, facing: Facing) -> ClientToServer {
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.wrapping_add(1);
+
+        if self.pending.len() == MAX_PENDING_INPUTS {
+            log::warn!("The server is not acknowledging our inputs, forgetting the oldest");
+            self.pending.pop_front();
+        }
+        self.pending.push_back(Input { seq, dx, dy });
+        self.facing = Some(facing);
+        ClientToServer::Input(seq, dx, dy, facing)
+    }
+
+    /// Takes in the server's position after input `seq` and returns where we
+    /// are once the inputs it has not seen yet are replayed on top of it.
+    ///
+    /// `None` for acks that are out of date.
+    pub fn reconcile(
+        &mut self,
+        seq: u32,
+        server_pos: Position,
+        map: &Map,
+        grid_size: i32,
+    ) -> Option<Position> {
+        if !is_after(seq, self.acked) {
+            return None;
+        }
+        self.acked = seq;
+
+        while self
+            .pending
+            .front()
+            .is_some_and(|input| !is_after(input.seq, seq))
+        {
+            self.pending.pop_front();
+        }
+
+        let mut pos = server_pos;
+        for input in &self.pending {
+            pos = player::step(pos, input.dx, input.dy, map, grid_size).unwrap_or(pos);
+        }
+        Some(pos)
+    }
+
+    /// Forgets the pending inputs after the player was moved other than by
+    /// them, e.g. through a door
+    pub fn teleported(&mut self) {
+        self.pending.clear();
+        self.acked = self.next_seq.wrapping_sub(1);
+    }
+}
+
+// Whether sequence number `a` comes after `b`; they wrap, so this compares by distance
+fn is_after(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) > 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::ascii;
+
+    const GRID_SIZE: i32 = 16;
+
+    fn open_room() -> Map {
+        ascii::parse("# = Wall\n\n########\n#      #\n#      #\n#      #\n#      #\n########\n")
+            .unwrap()
+    }
+
+    fn seqs(prediction: &Prediction) -> Vec<u32> {
+        prediction.pending.iter().map(|input| input.seq).collect()
+    }
+
+    #[test]
+    fn test_unacked_inputs_are_replayed() {
+        let map = open_room();
+        let mut prediction = Prediction::new();
+        for _ in 0..3 {
+            prediction.record(1, 0, Facing::East);
+        }
+
+        // The server agrees with the first move, two more are on their way
+        let pos = prediction.reconcile(1, Position::new(41, 40), &map, GRID_SIZE);
+        assert_eq!(pos, Some(Position::new(43, 40)));
+        assert_eq!(seqs(&prediction), vec![2, 3]);
+
+        // It pushed us back instead, so the inputs since go on from there
+        let pos = prediction.reconcile(2, Position::new(36, 40), &map, GRID_SIZE);
+        assert_eq!(pos, Some(Position::new(37, 40)));
+    }
+
+    #[test]
+    fn test_acked_inputs_are_dropped() {
+        let map = open_room();
+        let mut prediction = Prediction::new();
+        for _ in 0..3 {
+            prediction.record(0, 1, Facing::South);
+        }
+
+        let pos = prediction.reconcile(3, Position::new(40, 43), &map, GRID_SIZE);
+        assert_eq!(pos, Some(Position::new(40, 43)));
+        assert!(prediction.pending.is_empty());
+
+        // Acks that arrive late change nothing
+        assert_eq!(
+            prediction.reconcile(2, Position::new(40, 42), &map, GRID_SIZE),
+            None
+        );
+        assert_eq!(
+            prediction.reconcile(3, Position::new(40, 43), &map, GRID_SIZE),
+            None
+        );
+
+        // Nor do acks from before a teleport
+        prediction.record(0, 1, Facing::South);
+        prediction.teleported();
+        assert!(prediction.pending.is_empty());
+        assert_eq!(
+            prediction.reconcile(4, Position::new(40, 44), &map, GRID_SIZE),
+            None
+        );
+    }
+
+    #[test]
+    fn test_turning_in_place_is_sent() {
+        let map = open_room();
+        let mut prediction = Prediction::new();
+        assert!(prediction.input(1, 0, Facing::East).is_some());
+        assert_eq!(prediction.input(0, 0, Facing::East), None);
+
+        assert_eq!(
+            prediction.input(0, 0, Facing::North),
+            Some(ClientToServer::Input(2, 0, 0, Facing::North))
+        );
+        assert_eq!(prediction.input(0, 0, Facing::North), None);
+        assert_eq!(
+            prediction.input(0, -1, Facing::North),
+            Some(ClientToServer::Input(3, 0, -1, Facing::North))
+        );
+
+        // Turning does not move us when replayed
+        let pos = prediction.reconcile(1, Position::new(41, 40), &map, GRID_SIZE);
+        assert_eq!(pos, Some(Position::new(41, 39)));
+    }
+
+    #[test]
+    fn test_sequence_numbers_wrap() {
+        let map = open_room();
+        let mut prediction = Prediction::new();
+        prediction.next_seq = u32::MAX - 1;
+        prediction.acked = u32::MAX - 2;
+
+        let sent: Vec<_> = (0..3)
+            .map(|_| prediction.record(1, 0, Facing::East))
+            .collect();
+        assert_eq!(
+            sent,
+            vec![
+                ClientToServer::Input(u32::MAX - 1, 1, 0, Facing::East),
+                ClientToServer::Input(u32::MAX, 1, 0, Facing::East),
+                ClientToServer::Input(0, 1, 0, Facing::East),
+            ]
+        );
+
+        let pos = prediction.reconcile(u32::MAX, Position::new(42, 40), &map, GRID_SIZE);
+        assert_eq!(pos, Some(Position::new(43, 40)));
+        assert_eq!(seqs(&prediction), vec![0]);
+
+        let pos = prediction.reconcile(0, Position::new(43, 40), &map, GRID_SIZE);
+        assert_eq!(pos, Some(Position::new(43, 40)));
+        assert!(prediction.pending.is_empty());
+        assert_eq!(
+            prediction.reconcile(u32::MAX, Position::new(42, 40), &map, GRID_SIZE),
+            None
+        );
+
+        assert!(is_after(0, u32::MAX));
+        assert!(is_after(5, u32::MAX - 5));
+        assert!(!is_after(u32::MAX, 0));
+        assert!(!is_after(7, 7));
+    }
+}
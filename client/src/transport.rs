@@ -0,0 +1,279 @@
+// Byte streams the network thread can talk to the server over.
+//
+// `NetClient` does not care what is on the other end: a TCP socket for real
+// servers, or an in-process loopback for offline mode, tests and scripted local
+// servers. A `Connector` opens a fresh `Transport` for the first connection and
+// again after every disconnect.
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+    thread,
+    time::Duration,
+};
+
+use protocol::handshake;
+
+// How long a single TCP connection attempt may take
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A connected byte stream to the server
+pub trait Transport: Send {
+    /// Reads without blocking: `WouldBlock` when nothing arrived yet and
+    /// `Ok(0)` once the other side closed the stream
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Writes all of `bytes`
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()>;
+}
+
+/// Opens transports to the server
+pub trait Connector: Send + fmt::Display {
+    fn connect(&mut self) -> io::Result<Box<dyn Transport>>;
+}
+
+/// Connects to a real server over TCP
+pub struct TcpConnector {
+    addr: String,
+}
+
+impl TcpConnector {
+    pub fn new(addr: String) -> Self {
+        Self { addr }
+    }
+
+    /// Connects to `GAME_HOSTNAME_PORT`, or the public server if it is not set
+    pub fn from_env() -> Self {
+        Self::new(
+            std::env::var("GAME_HOSTNAME_PORT").unwrap_or("game.ablecorp.us:45250".to_owned()),
+        )
+    }
+}
+
+impl fmt::Display for TcpConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.addr)
+    }
+}
+
+impl Connector for TcpConnector {
+    fn connect(&mut self) -> io::Result<Box<dyn Transport>> {
+        let mut last_error = None;
+        for addr in self.addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+                Ok(stream) => {
+                    stream.set_nonblocking(true)?;
+                    // Text servers read a line per read, so lines must not wait to be merged
+                    stream.set_nodelay(true)?;
+                    return Ok(Box::new(TcpTransport(stream)));
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.unwrap_or_else(|| io::ErrorKind::NotFound.into()))
+    }
+}
+
+struct TcpTransport(TcpStream);
+
+impl Transport for TcpTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.0.write_all(bytes)
+    }
+}
+
+/// One end of an in-process connection, see `loopback`
+pub struct LoopbackTransport {
+    incoming: Receiver<Vec<u8>>,
+    outgoing: Sender<Vec<u8>>,
+    // Part of a chunk that did not fit into the last read
+    pending: Vec<u8>,
+}
+
+impl LoopbackTransport {
+    /// Two transports wired to each other
+    pub fn pair() -> (Self, Self) {
+        let (a_tx, a_rx) = mpsc::channel();
+        let (b_tx, b_rx) = mpsc::channel();
+        let a = Self {
+            incoming: a_rx,
+            outgoing: b_tx,
+            pending: Vec::new(),
+        };
+        let b = Self {
+            incoming: b_rx,
+            outgoing: a_tx,
+            pending: Vec::new(),
+        };
+        (a, b)
+    }
+
+    /// Like `read`, but waits until something arrives or the other end is dropped
+    pub fn read_blocking(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            match self.incoming.recv() {
+                Ok(chunk) => self.pending = chunk,
+                Err(_) => return Ok(0),
+            }
+        }
+        self.read(buf)
+    }
+}
+
+impl Transport for LoopbackTransport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pending.is_empty() {
+            match self.incoming.try_recv() {
+                Ok(chunk) => self.pending = chunk,
+                Err(TryRecvError::Empty) => return Err(io::ErrorKind::WouldBlock.into()),
+                Err(TryRecvError::Disconnected) => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+        Ok(n)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.outgoing
+            .send(bytes.to_vec())
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+/// Hands out in-process connections to whoever holds the `LoopbackListener`
+pub struct LoopbackConnector {
+    accepted: Sender<LoopbackTransport>,
+}
+
+/// The server side of `loopback`, receiving one transport per connection
+pub struct LoopbackListener {
+    accepted: Receiver<LoopbackTransport>,
+}
+
+/// An in-process connector and the listener its connections arrive at
+pub fn loopback() -> (LoopbackConnector, LoopbackListener) {
+    let (accepted_tx, accepted_rx) = mpsc::channel();
+    (
+        LoopbackConnector {
+            accepted: accepted_tx,
+        },
+        LoopbackListener {
+            accepted: accepted_rx,
+        },
+    )
+}
+
+impl fmt::Display for LoopbackConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "loopback")
+    }
+}
+
+impl Connector for LoopbackConnector {
+    fn connect(&mut self) -> io::Result<Box<dyn Transport>> {
+        let (client, server) = LoopbackTransport::pair();
+        self.accepted
+            .send(server)
+            .map_err(|_| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+        Ok(Box::new(client))
+    }
+}
+
+impl LoopbackListener {
+    /// Waits for the next connection, `None` once the connector is gone
+    pub fn accept(&self) -> Option<LoopbackTransport> {
+        self.accepted.recv().ok()
+    }
+}
+
+/// A loopback to a stand-in server for offline mode.
+///
+/// It answers the hello without offering any capabilities and ignores
+/// everything else, so the client never waits on it.
+pub fn offline() -> LoopbackConnector {
+    let (connector, listener) = loopback();
+    thread::Builder::new()
+        .name("offline server".to_string())
+        .spawn(move || {
+            while let Some(mut transport) = listener.accept() {
+                let hello = format!("SRV: hello {}\r\n", handshake::PROTOCOL_VERSION);
+                let _ = transport.write(hello.as_bytes());
+                // Swallow what the client sends until it hangs up
+                let mut buffer = [0; 1024];
+                while let Ok(n) = transport.read_blocking(&mut buffer) {
+                    if n == 0 {
+                        break;
+                    }
+                }
+            }
+        })
+        .expect("Failed to start the offline server");
+    connector
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(transport: &mut dyn Transport) -> Vec<u8> {
+        let mut received = Vec::new();
+        let mut buffer = [0; 4];
+        loop {
+            match transport.read(&mut buffer) {
+                Ok(0) => panic!("the other end is still open"),
+                Ok(n) => received.extend_from_slice(&buffer[..n]),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return received,
+                Err(e) => panic!("read failed: {}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_loopback_both_ways() {
+        let (mut connector, listener) = loopback();
+        let mut client = connector.connect().unwrap();
+        let mut server = listener.accept().unwrap();
+
+        // Nothing there yet
+        assert!(read_all(&mut server).is_empty());
+
+        client.write(b"hello ").unwrap();
+        client.write(b"server").unwrap();
+        // Chunks longer than the buffer come out in pieces, in order
+        assert_eq!(read_all(&mut server), b"hello server");
+
+        server.write(b"SRV: hi\r\n").unwrap();
+        assert_eq!(read_all(client.as_mut()), b"SRV: hi\r\n");
+    }
+
+    #[test]
+    fn test_loopback_closed_end_reads_eof() {
+        let (mut client, mut server) = LoopbackTransport::pair();
+        client.write(b"bye").unwrap();
+        drop(client);
+
+        // What was sent before closing still arrives, then the end of the stream
+        let mut buffer = [0; 8];
+        assert_eq!(server.read(&mut buffer).unwrap(), 3);
+        assert_eq!(&buffer[..3], b"bye");
+        assert_eq!(server.read(&mut buffer).unwrap(), 0);
+        assert_eq!(server.read_blocking(&mut buffer).unwrap(), 0);
+        assert_eq!(
+            server.write(b"anyone?").unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+
+        // The connector refuses once nobody listens
+        let (mut connector, listener) = loopback();
+        drop(listener);
+        assert!(connector.connect().is_err());
+    }
+}
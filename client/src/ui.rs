@@ -3,9 +3,16 @@
 use ggez::input::keyboard::KeyCode;
 
 pub struct UI {}
+impl Default for UI {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl UI {
-    pub fn new() {
-        println!("UI")
+    pub fn new() -> Self {
+        println!("UI");
+        Self {}
     }
 
     pub fn feed_key_event(&mut self, key_event: &KeyCode) {
@@ -0,0 +1,255 @@
+// Salted challenge-response login, modelled on SCRAM-SHA-256.
+//
+// The server keeps a `Verifier` for every account instead of the password:
+//
+//   SaltedPassword  = PBKDF2-HMAC-SHA256(password, salt, iterations)
+//   ClientKey       = HMAC(SaltedPassword, "Client Key")
+//   StoredKey       = SHA256(ClientKey)
+//
+// Logging in goes `auth_start <username>`, answered with
+// `auth_challenge <salt> <iterations> <nonce>` carrying a fresh nonce, then
+// `auth_proof <proof>` where
+//
+//   ClientSignature = HMAC(StoredKey, username ":" nonce)
+//   ClientProof     = ClientKey XOR ClientSignature
+//
+// The server recovers ClientKey from the proof and checks that it hashes to
+// StoredKey. A recorded proof is useless against any other nonce, and the
+// password never crosses the wire.
+//
+// The StoredKey does, once: registering sends the whole verifier with
+// `register_verifier`. Whoever records it and any later proof of that account
+// can XOR ClientKey out of the proof and log in as that user for good, and so
+// can whoever steals the verifiers from the server. Registration therefore
+// needs a channel nobody else can read, such as TLS or a trusted network; only
+// logins are safe to send in the clear. The client refuses to register over its
+// plain TCP connection unless the player explicitly allows it. Binary values
+// travel as lowercase hex on the text protocol.
+//
+// After a successful login the server hands out a session token with
+// `session <token>`, and `resume <username> <token>` logs in again on a later
+// connection without asking for the password.
+
+use crate::ProtocolError;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Length of the keys and proofs, the SHA-256 output size.
+pub const KEY_LEN: usize = 32;
+
+/// KDF rounds used for new verifiers.
+pub const DEFAULT_ITERATIONS: u32 = 10_000;
+
+/// Fewest KDF rounds the client accepts in a challenge, so a server cannot
+/// talk it into a proof that is cheap to brute force.
+pub const MIN_ITERATIONS: u32 = 4096;
+
+/// Most KDF rounds the client accepts in a challenge, so a server cannot
+/// freeze it.
+pub const MAX_ITERATIONS: u32 = 1_000_000;
+
+/// What the server asks the client to prove, sent as `auth_challenge`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Challenge {
+    pub salt: Vec<u8>,
+    pub iterations: u32,
+    /// Fresh for every attempt, so proofs cannot be replayed.
+    pub nonce: Vec<u8>,
+}
+
+/// What the server stores per account in place of the password.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Verifier {
+    pub salt: Vec<u8>,
+    pub iterations: u32,
+    pub stored_key: Vec<u8>,
+}
+
+impl Verifier {
+    /// Derives the verifier of `password`, which is what registration sends.
+    pub fn new(password: &str, salt: &[u8], iterations: u32) -> Self {
+        let client_key = client_key(password, salt, iterations);
+        Self {
+            salt: salt.to_vec(),
+            iterations,
+            stored_key: Sha256::digest(client_key).to_vec(),
+        }
+    }
+
+    /// The challenge for a login attempt using the given fresh nonce.
+    pub fn challenge(&self, nonce: &[u8]) -> Challenge {
+        Challenge {
+            salt: self.salt.clone(),
+            iterations: self.iterations,
+            nonce: nonce.to_vec(),
+        }
+    }
+
+    /// Whether `proof` answers the challenge made with `nonce`.
+    pub fn verify(&self, username: &str, nonce: &[u8], proof: &[u8]) -> bool {
+        if proof.len() != KEY_LEN {
+            return false;
+        }
+        let signature = client_signature(&self.stored_key, username, nonce);
+        let client_key: Vec<u8> = proof.iter().zip(&signature).map(|(p, s)| p ^ s).collect();
+        constant_time_eq(&Sha256::digest(client_key), &self.stored_key)
+    }
+}
+
+/// Refuses challenges asking for fewer than `MIN_ITERATIONS` or more than
+/// `MAX_ITERATIONS` rounds, before any time goes into answering them.
+pub fn check_challenge(challenge: &Challenge) -> Result<(), ProtocolError> {
+    if !(MIN_ITERATIONS..=MAX_ITERATIONS).contains(&challenge.iterations) {
+        return Err(ProtocolError::InvalidIterations(
+            challenge.iterations.to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Answers a challenge, proving we know `password` without sending it.
+///
+/// This runs the whole key derivation, which can take a second at
+/// `MAX_ITERATIONS`. Challenges `check_challenge` refuses are refused here too.
+pub fn client_proof(
+    username: &str,
+    password: &str,
+    challenge: &Challenge,
+) -> Result<Vec<u8>, ProtocolError> {
+    check_challenge(challenge)?;
+
+    let client_key = client_key(password, &challenge.salt, challenge.iterations);
+    let stored_key = Sha256::digest(client_key);
+    let signature = client_signature(&stored_key, username, &challenge.nonce);
+    Ok(client_key
+        .iter()
+        .zip(&signature)
+        .map(|(k, s)| k ^ s)
+        .collect())
+}
+
+fn client_key(password: &str, salt: &[u8], iterations: u32) -> [u8; KEY_LEN] {
+    let mut salted_password = [0; KEY_LEN];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password.as_bytes(), salt, iterations, &mut salted_password);
+    hmac(&salted_password, &[b"Client Key"])
+}
+
+fn client_signature(stored_key: &[u8], username: &str, nonce: &[u8]) -> [u8; KEY_LEN] {
+    hmac(stored_key, &[username.as_bytes(), b":", nonce])
+}
+
+fn hmac(key: &[u8], parts: &[&[u8]]) -> [u8; KEY_LEN] {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC takes keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+// Compares without bailing out early, so timing does not leak how much matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Encodes bytes as lowercase hex for the text protocol.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn from_hex(hex: &str) -> Result<Vec<u8>, ProtocolError> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(ProtocolError::InvalidHex(hex.to_string()));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| ProtocolError::InvalidHex(hex.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn challenge(verifier: &Verifier) -> Challenge {
+        verifier.challenge(b"server nonce")
+    }
+
+    #[test]
+    fn test_proof_is_accepted() {
+        let verifier = Verifier::new("hunter2", b"salt", MIN_ITERATIONS);
+        let proof = client_proof("bob", "hunter2", &challenge(&verifier)).unwrap();
+        assert_eq!(proof.len(), KEY_LEN);
+        assert!(verifier.verify("bob", b"server nonce", &proof));
+    }
+
+    #[test]
+    fn test_wrong_password_user_or_nonce_is_refused() {
+        let verifier = Verifier::new("hunter2", b"salt", MIN_ITERATIONS);
+        let wrong = client_proof("bob", "hunter3", &challenge(&verifier)).unwrap();
+        assert!(!verifier.verify("bob", b"server nonce", &wrong));
+
+        let proof = client_proof("bob", "hunter2", &challenge(&verifier)).unwrap();
+        assert!(!verifier.verify("alice", b"server nonce", &proof));
+        // Replaying a proof against a new nonce fails
+        assert!(!verifier.verify("bob", b"other nonce", &proof));
+        assert!(!verifier.verify("bob", b"server nonce", &proof[1..]));
+    }
+
+    #[test]
+    fn test_proof_does_not_contain_password_material() {
+        let verifier = Verifier::new("hunter2", b"salt", MIN_ITERATIONS);
+        let proof = client_proof("bob", "hunter2", &challenge(&verifier)).unwrap();
+        assert_ne!(proof, verifier.stored_key);
+        assert!(!to_hex(&proof).contains(&to_hex(b"hunter2")));
+    }
+
+    #[test]
+    fn test_iteration_limits() {
+        let weak = Challenge {
+            salt: b"salt".to_vec(),
+            iterations: 1,
+            nonce: b"n".to_vec(),
+        };
+        assert_eq!(
+            client_proof("bob", "pw", &weak),
+            Err(ProtocolError::InvalidIterations("1".to_string()))
+        );
+        let huge = Challenge {
+            iterations: u32::MAX,
+            ..weak
+        };
+        assert!(client_proof("bob", "pw", &huge).is_err());
+        assert!(check_challenge(&huge).is_err());
+        let slowest = Challenge {
+            iterations: MAX_ITERATIONS,
+            ..huge
+        };
+        assert_eq!(check_challenge(&slowest), Ok(()));
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(to_hex(&[0, 0xab, 0x10]), "00ab10");
+        assert_eq!(from_hex("00AB10"), Ok(vec![0, 0xab, 0x10]));
+        assert_eq!(from_hex(""), Ok(vec![]));
+        assert_eq!(
+            from_hex("abc"),
+            Err(ProtocolError::InvalidHex("abc".to_string()))
+        );
+        assert_eq!(
+            from_hex("zz"),
+            Err(ProtocolError::InvalidHex("zz".to_string()))
+        );
+        assert_eq!(
+            from_hex("é0"),
+            Err(ProtocolError::InvalidHex("é0".to_string()))
+        );
+    }
+}
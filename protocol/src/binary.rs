@@ -0,0 +1,681 @@
+// Compact binary encoding of the protocol messages.
+//
+// Every message travels as a frame: a varint payload length followed by the
+// payload. The payload starts with a one byte tag naming the variant, followed
+// by its fields in declaration order:
+//
+//   integers   LEB128 varints, signed ones zigzag encoded first
+//   strings    varint byte length, then UTF-8
+//   bytes      varint length, then the raw bytes (salts, nonces, proofs)
+//   lists      varint count, then the items
+//   Position   x then y
+//   Facing     one byte, 0 North, 1 East, 2 South, 3 West
+//   ZoneLink   zone list, then the position
+//   EntityId   unsigned varint
+//   EntityKind one byte, 0 player, 1 npc, 2 item, 3 projectile, then the name
+//
+// The zone path closing `PlayerJoined` and `PlayerMoved` is a list of strings
+// that is left out entirely when empty, as servers from before zones send it.
+//
+// The codec is chosen per connection during the handshake, see
+// `handshake::Codec`. Tags are part of the wire format: append new variants
+// with fresh tags rather than renumbering. Server tag 0 is retired: it carried
+// `EntityMoved` as a bare position before entities had ids, and is refused now
+// rather than read as the newer layout.
+
+use crate::auth::{Challenge, Verifier};
+use crate::zones::ZoneLink;
+use crate::{
+    ClientToServer, EntityId, EntityKind, Facing, Position, ProtocolError, ServerToClient,
+    handshake::Codec,
+};
+
+/// Largest payload the decoder accepts before skipping a frame.
+pub const DEFAULT_MAX_FRAME_LEN: usize = 4096;
+
+/// A message that can be written to and read from a binary payload.
+pub trait BinaryMessage: Sized {
+    fn encode(&self, out: &mut Vec<u8>);
+    fn decode(payload: &[u8]) -> Result<Self, ProtocolError>;
+}
+
+/// Encodes a message as a complete length-prefixed frame.
+pub fn encode_frame<T: BinaryMessage>(message: &T) -> Vec<u8> {
+    let mut payload = Vec::new();
+    message.encode(&mut payload);
+
+    let mut frame = Vec::with_capacity(payload.len() + 2);
+    put_varint(&mut frame, payload.len() as u64);
+    frame.extend_from_slice(&payload);
+    frame
+}
+
+// Buffers raw socket bytes and hands them back out as whole frames, the binary
+// counterpart of `framing::LineDecoder`.
+#[derive(Debug)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+    max_frame_len: usize,
+    // Bytes still to drop from a frame that was too long
+    skipping: usize,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::with_max_frame_len(DEFAULT_MAX_FRAME_LEN)
+    }
+
+    pub fn with_max_frame_len(max_frame_len: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            max_frame_len,
+            skipping: 0,
+        }
+    }
+
+    /// Appends freshly read bytes to the buffer.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        let skipped = self.skipping.min(bytes.len());
+        self.skipping -= skipped;
+        self.buffer.extend_from_slice(&bytes[skipped..]);
+    }
+
+    /// Number of bytes waiting for the rest of their frame.
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Pops and decodes the next complete frame, if one is buffered.
+    ///
+    /// A frame over the length limit yields a single `FrameTooLong` error and
+    /// its payload is dropped as it arrives.
+    pub fn next_message<T: BinaryMessage>(&mut self) -> Option<Result<T, ProtocolError>> {
+        let mut reader = Reader::new(&self.buffer);
+        let len = match reader.varint() {
+            Ok(len) => len as usize,
+            // The length prefix itself has not fully arrived yet
+            Err(ProtocolError::MalformedFrame(_)) if self.buffer.len() < 10 => return None,
+            Err(e) => {
+                self.buffer.clear();
+                return Some(Err(e));
+            }
+        };
+        let start = reader.pos;
+
+        if len > self.max_frame_len {
+            let buffered = self.buffer.len() - start;
+            self.skipping = len.saturating_sub(buffered);
+            self.buffer.drain(..start + len.min(buffered));
+            return Some(Err(ProtocolError::FrameTooLong {
+                limit: self.max_frame_len,
+            }));
+        }
+        if self.buffer.len() < start + len {
+            return None;
+        }
+
+        let frame: Vec<u8> = self.buffer.drain(..start + len).skip(start).collect();
+        Some(T::decode(&frame))
+    }
+}
+
+fn put_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_signed(out: &mut Vec<u8>, value: i32) {
+    put_varint(out, ((value << 1) ^ (value >> 31)) as u32 as u64);
+}
+
+fn put_bytes(out: &mut Vec<u8>, value: &[u8]) {
+    put_varint(out, value.len() as u64);
+    out.extend_from_slice(value);
+}
+
+fn put_str(out: &mut Vec<u8>, value: &str) {
+    put_bytes(out, value.as_bytes());
+}
+
+fn put_strings(out: &mut Vec<u8>, values: &[String]) {
+    put_varint(out, values.len() as u64);
+    for value in values {
+        put_str(out, value);
+    }
+}
+
+fn put_position(out: &mut Vec<u8>, pos: &Position) {
+    put_signed(out, pos.x);
+    put_signed(out, pos.y);
+}
+
+fn put_facing(out: &mut Vec<u8>, facing: Facing) {
+    out.push(match facing {
+        Facing::North => 0,
+        Facing::East => 1,
+        Facing::South => 2,
+        Facing::West => 3,
+    });
+}
+
+fn put_zone_path(out: &mut Vec<u8>, zone: &[String]) {
+    if !zone.is_empty() {
+        put_strings(out, zone);
+    }
+}
+
+fn put_entity_kind(out: &mut Vec<u8>, kind: &EntityKind) {
+    out.push(match kind {
+        EntityKind::Player(_) => 0,
+        EntityKind::Npc(_) => 1,
+        EntityKind::Item(_) => 2,
+        EntityKind::Projectile(_) => 3,
+    });
+    put_str(out, kind.name());
+}
+
+// Walks a payload, failing on anything truncated or out of range.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn byte(&mut self) -> Result<u8, ProtocolError> {
+        let byte = *self
+            .bytes
+            .get(self.pos)
+            .ok_or(ProtocolError::MalformedFrame("truncated"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> Result<u64, ProtocolError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(ProtocolError::MalformedFrame("varint too long"))
+    }
+
+    fn unsigned(&mut self) -> Result<u32, ProtocolError> {
+        u32::try_from(self.varint()?)
+            .map_err(|_| ProtocolError::MalformedFrame("integer out of range"))
+    }
+
+    fn signed(&mut self) -> Result<i32, ProtocolError> {
+        let raw = self.unsigned()?;
+        Ok(((raw >> 1) as i32) ^ -((raw & 1) as i32))
+    }
+
+    fn len(&mut self) -> Result<usize, ProtocolError> {
+        let len = self.varint()? as usize;
+        if len > self.bytes.len() - self.pos {
+            return Err(ProtocolError::MalformedFrame("truncated"));
+        }
+        Ok(len)
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, ProtocolError> {
+        let len = self.len()?;
+        let bytes = self.bytes[self.pos..self.pos + len].to_vec();
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> Result<String, ProtocolError> {
+        String::from_utf8(self.bytes()?).map_err(|_| ProtocolError::InvalidUtf8)
+    }
+
+    fn strings(&mut self) -> Result<Vec<String>, ProtocolError> {
+        // Every string takes at least its length byte
+        let count = self.len()?;
+        (0..count).map(|_| self.string()).collect()
+    }
+
+    fn position(&mut self) -> Result<Position, ProtocolError> {
+        Ok(Position::new(self.signed()?, self.signed()?))
+    }
+
+    fn facing(&mut self) -> Result<Facing, ProtocolError> {
+        match self.byte()? {
+            0 => Ok(Facing::North),
+            1 => Ok(Facing::East),
+            2 => Ok(Facing::South),
+            3 => Ok(Facing::West),
+            other => Err(ProtocolError::InvalidFacingDirection(other.to_string())),
+        }
+    }
+
+    fn entity_id(&mut self) -> Result<EntityId, ProtocolError> {
+        Ok(EntityId(self.unsigned()?))
+    }
+
+    fn entity_kind(&mut self) -> Result<EntityKind, ProtocolError> {
+        let tag = self.byte()?;
+        let name = self.string()?;
+        match tag {
+            0 => Ok(EntityKind::Player(name)),
+            1 => Ok(EntityKind::Npc(name)),
+            2 => Ok(EntityKind::Item(name)),
+            3 => Ok(EntityKind::Projectile(name)),
+            other => Err(ProtocolError::InvalidEntityKind(other.to_string())),
+        }
+    }
+
+    fn zone_link(&mut self) -> Result<ZoneLink, ProtocolError> {
+        Ok(ZoneLink {
+            zones: self.strings()?,
+            pos: self.position()?,
+        })
+    }
+
+    // A trailing zone path, empty when the payload ends before it
+    fn zone_path(&mut self) -> Result<Vec<String>, ProtocolError> {
+        if self.pos == self.bytes.len() {
+            return Ok(Vec::new());
+        }
+        self.strings()
+    }
+
+    fn finish<T>(self, value: T) -> Result<T, ProtocolError> {
+        if self.pos != self.bytes.len() {
+            return Err(ProtocolError::MalformedFrame("trailing bytes"));
+        }
+        Ok(value)
+    }
+}
+
+impl BinaryMessage for ServerToClient {
+    fn encode(&self, out: &mut Vec<u8>) {
+        use ServerToClient::*;
+        match self {
+            EntityMoved(id, pos, facing) => {
+                out.push(18);
+                put_varint(out, u64::from(id.0));
+                put_position(out, pos);
+                put_facing(out, *facing);
+            }
+            PlayerJoined(username, pos, facing, zone) => {
+                out.push(1);
+                put_str(out, username);
+                put_position(out, pos);
+                put_facing(out, *facing);
+                put_zone_path(out, zone);
+            }
+            PlayerLeft(username) => {
+                out.push(2);
+                put_str(out, username);
+            }
+            PlayerMoved(username, pos, facing, zone) => {
+                out.push(3);
+                put_str(out, username);
+                put_position(out, pos);
+                put_facing(out, *facing);
+                put_zone_path(out, zone);
+            }
+            PlayerFaced(username, facing) => {
+                out.push(4);
+                put_str(out, username);
+                put_facing(out, *facing);
+            }
+            ChatMessage(username, message) => {
+                out.push(5);
+                put_str(out, username);
+                put_str(out, message);
+            }
+            LoginOk => out.push(6),
+            LoginFailed(reason) => {
+                out.push(7);
+                put_str(out, reason);
+            }
+            RegisterOk => out.push(11),
+            RegisterFailed(reason) => {
+                out.push(12);
+                put_str(out, reason);
+            }
+            Kicked(reason) => {
+                out.push(13);
+                put_str(out, reason);
+            }
+            Hello(version, capabilities) => {
+                out.push(8);
+                put_varint(out, u64::from(*version));
+                put_strings(out, capabilities);
+            }
+            EntitySpawned {
+                id,
+                kind,
+                pos,
+                facing,
+            } => {
+                out.push(9);
+                put_varint(out, u64::from(id.0));
+                put_entity_kind(out, kind);
+                put_position(out, pos);
+                put_facing(out, *facing);
+            }
+            EntityDespawned(id) => {
+                out.push(10);
+                put_varint(out, u64::from(id.0));
+            }
+            AuthChallenge(challenge) => {
+                out.push(14);
+                put_bytes(out, &challenge.salt);
+                put_varint(out, u64::from(challenge.iterations));
+                put_bytes(out, &challenge.nonce);
+            }
+            Session(token) => {
+                out.push(15);
+                put_str(out, token);
+            }
+            Pong(nonce, timestamp) => {
+                out.push(16);
+                put_varint(out, u64::from(*nonce));
+                put_varint(out, *timestamp);
+            }
+            PositionAck(seq, pos, facing) => {
+                out.push(17);
+                put_varint(out, u64::from(*seq));
+                put_position(out, pos);
+                put_facing(out, *facing);
+            }
+        }
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self, ProtocolError> {
+        use ServerToClient::*;
+        let mut r = Reader::new(payload);
+        let message = match r.byte()? {
+            1 => PlayerJoined(r.string()?, r.position()?, r.facing()?, r.zone_path()?),
+            2 => PlayerLeft(r.string()?),
+            3 => PlayerMoved(r.string()?, r.position()?, r.facing()?, r.zone_path()?),
+            4 => PlayerFaced(r.string()?, r.facing()?),
+            5 => ChatMessage(r.string()?, r.string()?),
+            6 => LoginOk,
+            7 => LoginFailed(r.string()?),
+            8 => Hello(r.unsigned()?, r.strings()?),
+            9 => EntitySpawned {
+                id: r.entity_id()?,
+                kind: r.entity_kind()?,
+                pos: r.position()?,
+                facing: r.facing()?,
+            },
+            10 => EntityDespawned(r.entity_id()?),
+            11 => RegisterOk,
+            12 => RegisterFailed(r.string()?),
+            13 => Kicked(r.string()?),
+            14 => AuthChallenge(Challenge {
+                salt: r.bytes()?,
+                iterations: r.unsigned()?,
+                nonce: r.bytes()?,
+            }),
+            15 => Session(r.string()?),
+            16 => Pong(r.unsigned()?, r.varint()?),
+            17 => PositionAck(r.unsigned()?, r.position()?, r.facing()?),
+            18 => EntityMoved(r.entity_id()?, r.position()?, r.facing()?),
+            tag => return Err(ProtocolError::UnknownTag(tag)),
+        };
+        r.finish(message)
+    }
+}
+
+impl BinaryMessage for ClientToServer {
+    fn encode(&self, out: &mut Vec<u8>) {
+        use ClientToServer::*;
+        match self {
+            AttemptPlayerMove(pos) => {
+                out.push(0);
+                put_position(out, pos);
+            }
+            AttemptPlayerFacingChange(facing) => {
+                out.push(1);
+                put_facing(out, *facing);
+            }
+            Register(username, password) => {
+                out.push(2);
+                put_str(out, username);
+                put_str(out, password);
+            }
+            Login(username, password) => {
+                out.push(3);
+                put_str(out, username);
+                put_str(out, password);
+            }
+            ChatMessage(message) => {
+                out.push(4);
+                put_str(out, message);
+            }
+            SetUsername(username) => {
+                out.push(5);
+                put_str(out, username);
+            }
+            SetPosition(x, y) => {
+                out.push(6);
+                put_signed(out, *x);
+                put_signed(out, *y);
+            }
+            Goto(link) => {
+                out.push(7);
+                put_strings(out, &link.zones);
+                put_position(out, &link.pos);
+            }
+            Hello(version, features) => {
+                out.push(8);
+                put_varint(out, u64::from(*version));
+                put_strings(out, features);
+            }
+            UseCodec(codec) => {
+                out.push(9);
+                out.push(match codec {
+                    Codec::Text => 0,
+                    Codec::Binary => 1,
+                });
+            }
+            AuthStart(username) => {
+                out.push(10);
+                put_str(out, username);
+            }
+            AuthProof(proof) => {
+                out.push(11);
+                put_bytes(out, proof);
+            }
+            RegisterVerifier(username, verifier) => {
+                out.push(12);
+                put_str(out, username);
+                put_bytes(out, &verifier.salt);
+                put_varint(out, u64::from(verifier.iterations));
+                put_bytes(out, &verifier.stored_key);
+            }
+            Resume(username, token) => {
+                out.push(13);
+                put_str(out, username);
+                put_str(out, token);
+            }
+            Ping(nonce, timestamp) => {
+                out.push(14);
+                put_varint(out, u64::from(*nonce));
+                put_varint(out, *timestamp);
+            }
+            Input(seq, dx, dy, facing) => {
+                out.push(15);
+                put_varint(out, u64::from(*seq));
+                put_signed(out, *dx);
+                put_signed(out, *dy);
+                put_facing(out, *facing);
+            }
+        }
+    }
+
+    fn decode(payload: &[u8]) -> Result<Self, ProtocolError> {
+        use ClientToServer::*;
+        let mut r = Reader::new(payload);
+        let message = match r.byte()? {
+            0 => AttemptPlayerMove(r.position()?),
+            1 => AttemptPlayerFacingChange(r.facing()?),
+            2 => Register(r.string()?, r.string()?),
+            3 => Login(r.string()?, r.string()?),
+            4 => ChatMessage(r.string()?),
+            5 => SetUsername(r.string()?),
+            6 => SetPosition(r.signed()?, r.signed()?),
+            7 => Goto(r.zone_link()?),
+            8 => Hello(r.unsigned()?, r.strings()?),
+            9 => UseCodec(match r.byte()? {
+                0 => Codec::Text,
+                1 => Codec::Binary,
+                _ => return Err(ProtocolError::MalformedFrame("unknown codec")),
+            }),
+            10 => AuthStart(r.string()?),
+            11 => AuthProof(r.bytes()?),
+            12 => RegisterVerifier(
+                r.string()?,
+                Verifier {
+                    salt: r.bytes()?,
+                    iterations: r.unsigned()?,
+                    stored_key: r.bytes()?,
+                },
+            ),
+            13 => Resume(r.string()?, r.string()?),
+            14 => Ping(r.unsigned()?, r.varint()?),
+            15 => Input(r.unsigned()?, r.signed()?, r.signed()?, r.facing()?),
+            tag => return Err(ProtocolError::UnknownTag(tag)),
+        };
+        r.finish(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_vectors::{client_to_server, server_to_client};
+
+    #[test]
+    fn test_client_to_server_vectors() {
+        for vector in client_to_server() {
+            assert_eq!(
+                encode_frame(&vector.message),
+                vector.frame,
+                "{:?}",
+                vector.message
+            );
+            let mut decoder = FrameDecoder::new();
+            decoder.feed(&vector.frame);
+            assert_eq!(decoder.next_message(), Some(Ok(vector.message)));
+        }
+    }
+
+    #[test]
+    fn test_server_to_client_vectors() {
+        for vector in server_to_client() {
+            assert_eq!(
+                encode_frame(&vector.message),
+                vector.frame,
+                "{:?}",
+                vector.message
+            );
+            let mut decoder = FrameDecoder::new();
+            decoder.feed(&vector.frame);
+            assert_eq!(decoder.next_message(), Some(Ok(vector.message)));
+        }
+    }
+
+    #[test]
+    fn test_varints() {
+        let mut out = Vec::new();
+        put_varint(&mut out, 300);
+        assert_eq!(out, [0xac, 0x02]);
+
+        for value in [0, 1, -1, 63, -64, 64, i32::MAX, i32::MIN] {
+            let mut out = Vec::new();
+            put_signed(&mut out, value);
+            assert_eq!(Reader::new(&out).signed(), Ok(value));
+        }
+    }
+
+    #[test]
+    fn test_frames_split_and_coalesced() {
+        let first = encode_frame(&ServerToClient::PlayerLeft("a".to_string()));
+        let second = encode_frame(&ServerToClient::LoginOk);
+        let mut bytes = first.clone();
+        bytes.extend_from_slice(&second);
+
+        let mut decoder = FrameDecoder::new();
+        decoder.feed(&bytes[..1]);
+        assert_eq!(decoder.next_message::<ServerToClient>(), None);
+        decoder.feed(&bytes[1..]);
+        assert_eq!(
+            decoder.next_message(),
+            Some(Ok(ServerToClient::PlayerLeft("a".to_string())))
+        );
+        assert_eq!(
+            decoder.next_message(),
+            Some(Ok(ServerToClient::LoginOk))
+        );
+        assert_eq!(decoder.next_message::<ServerToClient>(), None);
+        assert_eq!(decoder.buffered_len(), 0);
+    }
+
+    #[test]
+    fn test_malformed_payloads() {
+        assert_eq!(
+            ServerToClient::decode(&[42]),
+            Err(ProtocolError::UnknownTag(42))
+        );
+        // The position-only `EntityMoved` of the first binary servers
+        assert_eq!(
+            ServerToClient::decode(&[0, 2, 4]),
+            Err(ProtocolError::UnknownTag(0))
+        );
+        assert_eq!(
+            ServerToClient::decode(&[2, 5, b'a']),
+            Err(ProtocolError::MalformedFrame("truncated"))
+        );
+        assert_eq!(
+            ServerToClient::decode(&[6, 0]),
+            Err(ProtocolError::MalformedFrame("trailing bytes"))
+        );
+        assert_eq!(
+            ClientToServer::decode(&[1, 7]),
+            Err(ProtocolError::InvalidFacingDirection("7".to_string()))
+        );
+        assert_eq!(
+            ClientToServer::decode(&[5, 1, 0xff]),
+            Err(ProtocolError::InvalidUtf8)
+        );
+    }
+
+    #[test]
+    fn test_overlong_frame_is_skipped() {
+        let mut decoder = FrameDecoder::with_max_frame_len(4);
+        let big = encode_frame(&ServerToClient::PlayerLeft("abcdefgh".to_string()));
+        decoder.feed(&big[..5]);
+        assert_eq!(
+            decoder.next_message::<ServerToClient>(),
+            Some(Err(ProtocolError::FrameTooLong { limit: 4 }))
+        );
+        let mut rest = big[5..].to_vec();
+        rest.extend_from_slice(&encode_frame(&ServerToClient::LoginOk));
+        decoder.feed(&rest);
+        assert_eq!(
+            decoder.next_message(),
+            Some(Ok(ServerToClient::LoginOk))
+        );
+    }
+}
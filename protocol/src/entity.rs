@@ -0,0 +1,85 @@
+use crate::ProtocolError;
+use std::fmt;
+
+/// Server assigned identity of anything in the world, players included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
+pub struct EntityId(pub u32);
+
+impl fmt::Display for EntityId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// What an entity is, with the name shown above it.
+///
+/// On the text protocol this is two arguments: the kind keyword and the name,
+/// e.g. `npc "Old Man"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "type", content = "args", rename_all = "snake_case")
+)]
+pub enum EntityKind {
+    /// Another connected player, named by their username.
+    Player(String),
+    Npc(String),
+    Item(String),
+    Projectile(String),
+}
+
+impl EntityKind {
+    pub fn new(keyword: &str, name: String) -> Result<Self, ProtocolError> {
+        use EntityKind::*;
+        match keyword {
+            "player" => Ok(Player(name)),
+            "npc" => Ok(Npc(name)),
+            "item" => Ok(Item(name)),
+            "projectile" => Ok(Projectile(name)),
+            other => Err(ProtocolError::InvalidEntityKind(other.to_string())),
+        }
+    }
+
+    /// The keyword naming this kind on the text protocol.
+    pub fn keyword(&self) -> &'static str {
+        use EntityKind::*;
+        match self {
+            Player(_) => "player",
+            Npc(_) => "npc",
+            Item(_) => "item",
+            Projectile(_) => "projectile",
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        use EntityKind::*;
+        match self {
+            Player(name) | Npc(name) | Item(name) | Projectile(name) => name,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_keywords_round_trip() {
+        for kind in [
+            EntityKind::Player("alice".to_string()),
+            EntityKind::Npc("Old Man".to_string()),
+            EntityKind::Item("key".to_string()),
+            EntityKind::Projectile("arrow".to_string()),
+        ] {
+            let rebuilt = EntityKind::new(kind.keyword(), kind.name().to_string());
+            assert_eq!(rebuilt, Ok(kind));
+        }
+        assert_eq!(
+            EntityKind::new("dragon", String::new()),
+            Err(ProtocolError::InvalidEntityKind("dragon".to_string()))
+        );
+    }
+}
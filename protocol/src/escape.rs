@@ -0,0 +1,245 @@
+// Quoting for user supplied arguments on the line protocol.
+//
+// Arguments are separated by whitespace and lines end at `\n`, so a username
+// with a space or a chat message with an embedded `\r\n` would otherwise shift
+// the remaining arguments or smuggle in a second command. Arguments that are
+// plain words go out untouched; anything else is wrapped in double quotes with
+// these escapes:
+//
+//   \\  backslash        \"  double quote
+//   \n  line feed        \r  carriage return
+//   \t  tab              \u{1b}  any other control character, in hex
+//
+// Trailing free text such as a chat message stays unquoted as long as it has no
+// control characters and neither starts with a quote nor has whitespace at
+// either end, so ordinary chat reads the same as before.
+
+use crate::ProtocolError;
+use std::borrow::Cow;
+
+/// Quotes a single argument if it is not a plain word.
+pub fn quote_arg(arg: &str) -> Cow<'_, str> {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| !c.is_whitespace() && !c.is_control() && c != '"' && c != '\\');
+    if plain {
+        Cow::Borrowed(arg)
+    } else {
+        Cow::Owned(quote(arg))
+    }
+}
+
+/// Quotes trailing free text, leaving ordinary sentences as they are.
+pub fn quote_text(text: &str) -> Cow<'_, str> {
+    let plain = !text.is_empty()
+        && !text.starts_with('"')
+        && !text.starts_with(char::is_whitespace)
+        && !text.ends_with(char::is_whitespace)
+        && !text.chars().any(char::is_control);
+    if plain {
+        Cow::Borrowed(text)
+    } else {
+        Cow::Owned(quote(text))
+    }
+}
+
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Takes the first argument off `input`, returning it with the unparsed rest.
+///
+/// Returns `None` once only whitespace is left. Unquoted arguments are taken
+/// verbatim up to the next whitespace.
+pub fn take_arg(input: &str) -> Result<Option<(String, &str)>, ProtocolError> {
+    let input = input.trim_start();
+    if input.is_empty() {
+        return Ok(None);
+    }
+
+    let Some(quoted) = input.strip_prefix('"') else {
+        let end = input.find(char::is_whitespace).unwrap_or(input.len());
+        return Ok(Some((input[..end].to_string(), &input[end..])));
+    };
+
+    let mut arg = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let rest = &quoted[i + 1..];
+                if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+                    return Err(ProtocolError::InvalidEscape(rest.chars().take(8).collect()));
+                }
+                return Ok(Some((arg, rest)));
+            }
+            '\\' => arg.push(unescape(&mut chars)?),
+            c => arg.push(c),
+        }
+    }
+    Err(ProtocolError::UnterminatedQuote)
+}
+
+fn unescape(chars: &mut std::str::CharIndices<'_>) -> Result<char, ProtocolError> {
+    match chars.next().map(|(_, c)| c) {
+        Some('\\') => Ok('\\'),
+        Some('"') => Ok('"'),
+        Some('n') => Ok('\n'),
+        Some('r') => Ok('\r'),
+        Some('t') => Ok('\t'),
+        Some('u') => {
+            let mut hex = String::new();
+            if chars.next().map(|(_, c)| c) != Some('{') {
+                return Err(ProtocolError::InvalidEscape("\\u".to_string()));
+            }
+            loop {
+                match chars.next().map(|(_, c)| c) {
+                    Some('}') => break,
+                    Some(c) if c.is_ascii_hexdigit() && hex.len() < 6 => hex.push(c),
+                    _ => return Err(ProtocolError::InvalidEscape(format!("\\u{{{}", hex))),
+                }
+            }
+            u32::from_str_radix(&hex, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| ProtocolError::InvalidEscape(format!("\\u{{{}}}", hex)))
+        }
+        Some(other) => Err(ProtocolError::InvalidEscape(format!("\\{}", other))),
+        None => Err(ProtocolError::UnterminatedQuote),
+    }
+}
+
+/// Splits a whole argument list, decoding quoted arguments.
+pub fn split_args(input: &str) -> Result<Vec<String>, ProtocolError> {
+    let mut args = Vec::new();
+    let mut rest = input;
+    while let Some((arg, tail)) = take_arg(rest)? {
+        args.push(arg);
+        rest = tail;
+    }
+    Ok(args)
+}
+
+/// Decodes trailing free text written by [`quote_text`].
+///
+/// Text that is not a single quoted argument is returned trimmed as-is, which
+/// keeps messages relayed by servers that do not quote readable.
+pub fn unquote_text(input: &str) -> String {
+    let text = input.trim();
+    if text.starts_with('"')
+        && let Ok(Some((arg, rest))) = take_arg(text)
+        && rest.trim().is_empty()
+    {
+        return arg;
+    }
+    text.to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_plain_words_are_untouched() {
+        assert_eq!(quote_arg("player1"), "player1");
+        assert_eq!(quote_arg("zoë"), "zoë");
+        assert_eq!(quote_text("Hello everyone!"), "Hello everyone!");
+        assert!(matches!(quote_arg("player1"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_special_arguments_are_quoted() {
+        assert_eq!(quote_arg("bob smith"), "\"bob smith\"");
+        assert_eq!(quote_arg(""), "\"\"");
+        assert_eq!(quote_arg("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(quote_arg("a\u{1b}b"), "\"a\\u{1b}b\"");
+        assert_eq!(quote_text(" padded"), "\" padded\"");
+        assert_eq!(quote_text("\"quoted\" start"), "\"\\\"quoted\\\" start\"");
+    }
+
+    #[test]
+    fn test_line_breaks_cannot_inject_commands() {
+        let quoted = quote_text("hi\r\nlogin admin hunter2");
+        assert_eq!(quoted, "\"hi\\r\\nlogin admin hunter2\"");
+        assert!(!quoted.contains(['\r', '\n']));
+    }
+
+    #[test]
+    fn test_split_args() {
+        assert_eq!(
+            split_args("  alice \"bob smith\" \"\" 3 ").unwrap(),
+            vec!["alice", "bob smith", "", "3"]
+        );
+        assert_eq!(split_args("\"a\\tb\\u{41}\"").unwrap(), vec!["a\tbA"]);
+        assert!(split_args("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_malformed_quotes_are_rejected() {
+        assert_eq!(split_args("\"open"), Err(ProtocolError::UnterminatedQuote));
+        assert_eq!(
+            split_args("\"trailing\\"),
+            Err(ProtocolError::UnterminatedQuote)
+        );
+        assert_eq!(
+            split_args("\"bad\\q\""),
+            Err(ProtocolError::InvalidEscape("\\q".to_string()))
+        );
+        assert_eq!(
+            split_args("\"\\u{d800}\""),
+            Err(ProtocolError::InvalidEscape("\\u{d800}".to_string()))
+        );
+        assert_eq!(
+            split_args("\"glued\"on"),
+            Err(ProtocolError::InvalidEscape("on".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_unquoted_text_passes_through() {
+        assert_eq!(unquote_text(" hi   there "), "hi   there");
+        assert_eq!(
+            unquote_text("\"not\" closed properly"),
+            "\"not\" closed properly"
+        );
+        assert_eq!(unquote_text("\"line\\nbreak\""), "line\nbreak");
+    }
+
+    proptest! {
+        #[test]
+        fn prop_arg_round_trips(arg in any::<String>()) {
+            let quoted = quote_arg(&arg);
+            prop_assert!(!quoted.contains(['\r', '\n']));
+            prop_assert_eq!(split_args(&quoted).unwrap(), vec![arg.clone()]);
+        }
+
+        #[test]
+        fn prop_arg_lists_round_trip(args in proptest::collection::vec(any::<String>(), 0..6)) {
+            let line = args.iter().map(|a| quote_arg(a)).collect::<Vec<_>>().join(" ");
+            prop_assert_eq!(split_args(&line).unwrap(), args);
+        }
+
+        #[test]
+        fn prop_text_round_trips(text in any::<String>()) {
+            let quoted = quote_text(&text);
+            prop_assert!(!quoted.contains(['\r', '\n']));
+            prop_assert_eq!(unquote_text(&quoted), text);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use crate::ProtocolError;
+
+/// Longest line the decoder will buffer before giving up on it.
+pub const DEFAULT_MAX_LINE_LEN: usize = 4096;
+
+// Buffers raw socket bytes and hands them back out as whole lines.
+//
+// Lines are split on `\n` with any `\r` touching the terminator stripped, which
+// covers the normal `\r\n` ending as well as the `\n\r` the pre-auth server uses.
+// Splitting happens on bytes, so a UTF-8 character cut in half by a read simply
+// waits in the buffer until the rest of it arrives.
+#[derive(Debug)]
+pub struct LineDecoder {
+    buffer: Vec<u8>,
+    max_line_len: usize,
+    // Set while skipping the remainder of a line that was too long
+    discarding: bool,
+}
+
+impl Default for LineDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LineDecoder {
+    pub fn new() -> Self {
+        Self::with_max_line_len(DEFAULT_MAX_LINE_LEN)
+    }
+
+    pub fn with_max_line_len(max_line_len: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            max_line_len,
+            discarding: false,
+        }
+    }
+
+    /// Appends freshly read bytes to the buffer.
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Number of bytes waiting for a line terminator.
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Pops the next complete line, if one is buffered.
+    ///
+    /// Empty lines are skipped. A line over the length limit yields a single
+    /// `LineTooLong` error and its bytes are dropped up to the next terminator.
+    pub fn next_line(&mut self) -> Option<Result<String, ProtocolError>> {
+        loop {
+            let Some(end) = self.buffer.iter().position(|&b| b == b'\n') else {
+                if self.buffer.len() > self.max_line_len {
+                    self.buffer.clear();
+                    if !self.discarding {
+                        self.discarding = true;
+                        return Some(Err(self.too_long()));
+                    }
+                }
+                return None;
+            };
+
+            let raw: Vec<u8> = self.buffer.drain(..=end).collect();
+            if std::mem::take(&mut self.discarding) {
+                // This terminator ends the line we already reported
+                continue;
+            }
+
+            let line = trim_terminators(&raw);
+            if line.is_empty() {
+                continue;
+            }
+            if line.len() > self.max_line_len {
+                return Some(Err(self.too_long()));
+            }
+
+            return Some(String::from_utf8(line.to_vec()).map_err(|_| ProtocolError::InvalidUtf8));
+        }
+    }
+
+    /// Hands back whatever follows the last complete line, for when the stream
+    /// switches over to another codec.
+    pub fn take_buffered(&mut self) -> Vec<u8> {
+        self.discarding = false;
+        std::mem::take(&mut self.buffer)
+    }
+
+    /// Iterates over every complete line currently buffered.
+    pub fn lines(&mut self) -> Lines<'_> {
+        Lines { decoder: self }
+    }
+
+    fn too_long(&self) -> ProtocolError {
+        ProtocolError::LineTooLong {
+            limit: self.max_line_len,
+        }
+    }
+}
+
+fn trim_terminators(raw: &[u8]) -> &[u8] {
+    let start = raw.iter().position(|&b| b != b'\r' && b != b'\n');
+    let end = raw.iter().rposition(|&b| b != b'\r' && b != b'\n');
+    match (start, end) {
+        (Some(start), Some(end)) => &raw[start..=end],
+        _ => &[],
+    }
+}
+
+/// Draining iterator returned by [`LineDecoder::lines`].
+pub struct Lines<'a> {
+    decoder: &'a mut LineDecoder,
+}
+
+impl Iterator for Lines<'_> {
+    type Item = Result<String, ProtocolError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.decoder.next_line()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(decoder: &mut LineDecoder) -> Vec<Result<String, ProtocolError>> {
+        decoder.lines().collect()
+    }
+
+    #[test]
+    fn test_coalesced_lines_are_split() {
+        let mut decoder = LineDecoder::new();
+        decoder.feed(b"SRV: player_left a\r\nSRV: player_left b\r\n");
+        assert_eq!(
+            collect(&mut decoder),
+            vec![
+                Ok("SRV: player_left a".to_string()),
+                Ok("SRV: player_left b".to_string())
+            ]
+        );
+        assert_eq!(decoder.buffered_len(), 0);
+    }
+
+    #[test]
+    fn test_line_split_across_reads() {
+        let mut decoder = LineDecoder::new();
+        decoder.feed(b"SRV: player_mo");
+        assert!(collect(&mut decoder).is_empty());
+        decoder.feed(b"ved bob 1 2 North\r");
+        assert!(collect(&mut decoder).is_empty());
+        decoder.feed(b"\nSRV: ");
+        assert_eq!(
+            collect(&mut decoder),
+            vec![Ok("SRV: player_moved bob 1 2 North".to_string())]
+        );
+        assert_eq!(decoder.buffered_len(), 5);
+    }
+
+    #[test]
+    fn test_reversed_pre_auth_terminator() {
+        let mut decoder = LineDecoder::new();
+        decoder.feed(b"SRV: Login error.\n\rSRV: Password incorrect\n\r");
+        assert_eq!(
+            collect(&mut decoder),
+            vec![
+                Ok("SRV: Login error.".to_string()),
+                Ok("SRV: Password incorrect".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_partial_utf8_waits_for_rest() {
+        let message = "SRV: chat_message zoë héllo ✓\r\n".as_bytes();
+        // Split inside the multi-byte check mark
+        let split = message.len() - 4;
+
+        let mut decoder = LineDecoder::new();
+        decoder.feed(&message[..split]);
+        assert!(collect(&mut decoder).is_empty());
+        decoder.feed(&message[split..]);
+        assert_eq!(
+            collect(&mut decoder),
+            vec![Ok("SRV: chat_message zoë héllo ✓".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_invalid_utf8_is_reported() {
+        let mut decoder = LineDecoder::new();
+        decoder.feed(b"SRV: \xff\xfe\r\nSRV: ok\r\n");
+        assert_eq!(
+            collect(&mut decoder),
+            vec![Err(ProtocolError::InvalidUtf8), Ok("SRV: ok".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_overlong_line_is_dropped_once() {
+        let mut decoder = LineDecoder::with_max_line_len(8);
+        decoder.feed(b"0123456789");
+        assert_eq!(
+            collect(&mut decoder),
+            vec![Err(ProtocolError::LineTooLong { limit: 8 })]
+        );
+        decoder.feed(b"abcdef\r\nshort\r\n");
+        assert_eq!(collect(&mut decoder), vec![Ok("short".to_string())]);
+
+        // A complete line that is too long is reported as well
+        decoder.feed(b"0123456789\r\nok\r\n");
+        assert_eq!(
+            collect(&mut decoder),
+            vec![
+                Err(ProtocolError::LineTooLong { limit: 8 }),
+                Ok("ok".to_string())
+            ]
+        );
+    }
+}
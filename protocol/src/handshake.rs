@@ -0,0 +1,200 @@
+// The hello exchange that opens every connection.
+//
+// The client sends `hello <version> <feature>...` right after connecting and the
+// server answers `hello <version> <capability>...`. Servers from before the
+// exchange existed reply `Invalid protocol.` instead, which the client treats
+// as version 0 with no capabilities.
+//
+// When both sides support the binary codec the server switches its side of the
+// stream to binary frames right after its hello, and the client switches its
+// side right after sending `codec binary`.
+
+/// Revision of the line protocol spoken by this crate.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Features this client announces in its hello.
+pub const CLIENT_FEATURES: &[&str] = &[
+    "chat",
+    "facing",
+    ZONES_CAPABILITY,
+    CHALLENGE_AUTH_CAPABILITY,
+    HEARTBEAT_CAPABILITY,
+    INPUT_ACK_CAPABILITY,
+    #[cfg(feature = "binary")]
+    BINARY_CAPABILITY,
+];
+
+/// Capability announcing zone paths on player updates and `goto` between rooms.
+pub const ZONES_CAPABILITY: &str = "zones";
+
+/// Capability announcing support for the binary codec.
+pub const BINARY_CAPABILITY: &str = "binary";
+
+/// Capability announcing the challenge-response login of the `auth` module.
+pub const CHALLENGE_AUTH_CAPABILITY: &str = "challenge_auth";
+
+/// Capability announcing that the server answers pings, see the `heartbeat` module.
+pub const HEARTBEAT_CAPABILITY: &str = "heartbeat";
+
+/// Capability announcing that the server takes numbered `input` commands and
+/// answers them with `position_ack`.
+pub const INPUT_ACK_CAPABILITY: &str = "input_ack";
+
+// Capabilities advertising a game mode look like `gamemode:tag`
+const GAME_MODE_PREFIX: &str = "gamemode:";
+
+/// What the server told us about itself during the handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ServerInfo {
+    pub version: u32,
+    pub capabilities: Vec<String>,
+}
+
+/// How well the client can talk to a given server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "args", rename_all = "snake_case"))]
+pub enum Compatibility {
+    /// Both sides speak the same revision.
+    Full,
+    /// The server is older; the message explains what is missing.
+    Degraded(String),
+    /// The client must not log in; the message says why.
+    Incompatible(String),
+}
+
+/// Wire encoding used for a connection once the handshake is done.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Codec {
+    /// Readable `\r\n` terminated lines.
+    Text,
+    /// Length-prefixed frames, see the `binary` module.
+    Binary,
+}
+
+impl std::fmt::Display for Codec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Codec::Text => write!(f, "text"),
+            Codec::Binary => write!(f, "binary"),
+        }
+    }
+}
+
+impl ServerInfo {
+    pub fn new(version: u32, capabilities: Vec<String>) -> Self {
+        Self {
+            version,
+            capabilities,
+        }
+    }
+
+    /// A server that predates the hello exchange.
+    pub fn legacy() -> Self {
+        Self::new(0, Vec::new())
+    }
+
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// Game modes the server has enabled, e.g. `tag` or `rpg`.
+    pub fn game_modes(&self) -> impl Iterator<Item = &str> {
+        self.capabilities
+            .iter()
+            .filter_map(|c| c.strip_prefix(GAME_MODE_PREFIX))
+    }
+
+    /// Whether logging in can avoid sending the password in plaintext.
+    pub fn supports_challenge_auth(&self) -> bool {
+        self.has_capability(CHALLENGE_AUTH_CAPABILITY)
+    }
+
+    /// Codec to switch to after the handshake.
+    ///
+    /// Binary is only picked when this crate was built with it and the server
+    /// offers it; everything else stays on text.
+    pub fn codec(&self) -> Codec {
+        if cfg!(feature = "binary") && self.has_capability(BINARY_CAPABILITY) {
+            Codec::Binary
+        } else {
+            Codec::Text
+        }
+    }
+
+    pub fn compatibility(&self) -> Compatibility {
+        if self.version > PROTOCOL_VERSION {
+            Compatibility::Incompatible(format!(
+                "Server uses protocol v{}, this client only supports up to v{}. Please update the client.",
+                self.version, PROTOCOL_VERSION
+            ))
+        } else if self.version < PROTOCOL_VERSION {
+            Compatibility::Degraded(format!(
+                "Server uses older protocol v{}, some features are unavailable.",
+                self.version
+            ))
+        } else {
+            Compatibility::Full
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matching_version_is_fully_compatible() {
+        let info = ServerInfo::new(PROTOCOL_VERSION, vec![]);
+        assert_eq!(info.compatibility(), Compatibility::Full);
+    }
+
+    #[test]
+    fn test_legacy_server_degrades() {
+        assert!(matches!(
+            ServerInfo::legacy().compatibility(),
+            Compatibility::Degraded(_)
+        ));
+    }
+
+    #[test]
+    fn test_newer_server_is_refused() {
+        let info = ServerInfo::new(PROTOCOL_VERSION + 1, vec![]);
+        assert!(matches!(
+            info.compatibility(),
+            Compatibility::Incompatible(_)
+        ));
+    }
+
+    #[test]
+    fn test_game_modes_from_capabilities() {
+        let info = ServerInfo::new(
+            PROTOCOL_VERSION,
+            vec![
+                "chat".to_string(),
+                "gamemode:tag".to_string(),
+                "gamemode:rpg".to_string(),
+            ],
+        );
+        assert!(info.has_capability("chat"));
+        assert!(!info.has_capability("zones"));
+        assert!(!info.supports_challenge_auth());
+        assert_eq!(info.game_modes().collect::<Vec<_>>(), vec!["tag", "rpg"]);
+    }
+
+    #[test]
+    fn test_codec_negotiation() {
+        assert_eq!(ServerInfo::legacy().codec(), Codec::Text);
+
+        let info = ServerInfo::new(PROTOCOL_VERSION, vec![BINARY_CAPABILITY.to_string()]);
+        let expected = if cfg!(feature = "binary") {
+            Codec::Binary
+        } else {
+            Codec::Text
+        };
+        assert_eq!(info.codec(), expected);
+    }
+}
@@ -0,0 +1,208 @@
+// Keepalive pings and round-trip time measurement.
+//
+// The client sends `ping <nonce> <timestamp>` every interval to servers that
+// announce the `heartbeat` capability, and the server echoes both values back
+// as `pong <nonce> <timestamp>`. The timestamp is milliseconds on the client's
+// own clock, so the round trip is simply now minus the echoed timestamp and the
+// two clocks never need to agree.
+//
+// RTT is smoothed as in RFC 6298: the average moves an eighth of the way to
+// every sample and the jitter, the mean deviation, a quarter of the way. When
+// `max_missed` pings in a row go unanswered the connection is considered dead.
+
+use std::time::{Duration, Instant};
+
+/// How often a ping is sent.
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Unanswered pings in a row after which the connection is given up.
+pub const DEFAULT_MAX_MISSED_PONGS: u32 = 3;
+
+/// Sends pings on a timer and turns the pongs into latency figures.
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    interval: Duration,
+    max_missed: u32,
+    // Timestamps count milliseconds from here
+    epoch: Instant,
+    last_ping: Option<Instant>,
+    // Nonce of the last ping sent and of the last one answered
+    sent: u32,
+    answered: u32,
+    missed: u32,
+    rtt: Option<Duration>,
+    jitter: Duration,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new(DEFAULT_PING_INTERVAL, DEFAULT_MAX_MISSED_PONGS)
+    }
+}
+
+impl Heartbeat {
+    pub fn new(interval: Duration, max_missed: u32) -> Self {
+        Self {
+            interval,
+            max_missed,
+            epoch: Instant::now(),
+            last_ping: None,
+            sent: 0,
+            answered: 0,
+            missed: 0,
+            rtt: None,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    pub fn set_max_missed(&mut self, max_missed: u32) {
+        self.max_missed = max_missed;
+    }
+
+    /// Returns the nonce and timestamp of the next ping once one is due.
+    ///
+    /// Every ping sent while the previous one is still unanswered counts as a
+    /// missed pong.
+    pub fn poll(&mut self, now: Instant) -> Option<(u32, u64)> {
+        if self
+            .last_ping
+            .is_some_and(|last| now.duration_since(last) < self.interval)
+        {
+            return None;
+        }
+
+        if self.sent != self.answered {
+            self.missed += 1;
+        }
+        self.last_ping = Some(now);
+        self.sent = self.sent.wrapping_add(1);
+        let timestamp = now.duration_since(self.epoch).as_millis() as u64;
+        Some((self.sent, timestamp))
+    }
+
+    /// Takes in a pong, returning the measured round trip.
+    ///
+    /// Pongs for pings we never sent or that were already answered are ignored.
+    pub fn on_pong(&mut self, nonce: u32, timestamp: u64, now: Instant) -> Option<Duration> {
+        // Only nonces between the last answered and the last sent ping are pending
+        let pending = self.sent.wrapping_sub(self.answered);
+        let age = self.sent.wrapping_sub(nonce);
+        if age >= pending {
+            return None;
+        }
+
+        let now_ms = now.duration_since(self.epoch).as_millis() as u64;
+        let sample = Duration::from_millis(now_ms.checked_sub(timestamp)?);
+        self.answered = nonce;
+        self.missed = 0;
+
+        self.rtt = Some(match self.rtt {
+            None => {
+                self.jitter = sample / 2;
+                sample
+            }
+            Some(rtt) => {
+                let deviation = rtt.abs_diff(sample);
+                self.jitter = (self.jitter * 3 + deviation) / 4;
+                (rtt * 7 + sample) / 8
+            }
+        });
+        Some(sample)
+    }
+
+    /// Whether too many pings in a row went unanswered.
+    pub fn timed_out(&self) -> bool {
+        self.missed >= self.max_missed
+    }
+
+    /// Smoothed round trip time, once a pong has arrived.
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    /// Mean deviation of the round trip time.
+    pub fn jitter(&self) -> Duration {
+        self.jitter
+    }
+
+    /// Starts over for a new connection.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.interval, self.max_missed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn test_pings_follow_the_interval() {
+        let mut heartbeat = Heartbeat::new(SECOND, 3);
+        let start = heartbeat.epoch;
+        assert_eq!(heartbeat.poll(start), Some((1, 0)));
+        assert_eq!(heartbeat.poll(start + SECOND / 2), None);
+        assert_eq!(heartbeat.poll(start + SECOND), Some((2, 1000)));
+    }
+
+    #[test]
+    fn test_round_trip_is_smoothed() {
+        let mut heartbeat = Heartbeat::new(SECOND, 3);
+        let start = heartbeat.epoch;
+        assert_eq!(heartbeat.rtt(), None);
+
+        let (nonce, timestamp) = heartbeat.poll(start).unwrap();
+        let sample = heartbeat.on_pong(nonce, timestamp, start + Duration::from_millis(80));
+        assert_eq!(sample, Some(Duration::from_millis(80)));
+        assert_eq!(heartbeat.rtt(), Some(Duration::from_millis(80)));
+        assert_eq!(heartbeat.jitter(), Duration::from_millis(40));
+
+        let (nonce, timestamp) = heartbeat.poll(start + SECOND).unwrap();
+        heartbeat.on_pong(
+            nonce,
+            timestamp,
+            start + SECOND + Duration::from_millis(160),
+        );
+        assert_eq!(heartbeat.rtt(), Some(Duration::from_millis(90)));
+        assert_eq!(heartbeat.jitter(), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn test_unknown_and_repeated_pongs_are_ignored() {
+        let mut heartbeat = Heartbeat::new(SECOND, 3);
+        let start = heartbeat.epoch;
+        assert_eq!(heartbeat.on_pong(1, 0, start), None);
+
+        let (nonce, timestamp) = heartbeat.poll(start).unwrap();
+        assert_eq!(heartbeat.on_pong(nonce + 1, timestamp, start), None);
+        assert!(heartbeat.on_pong(nonce, timestamp, start).is_some());
+        assert_eq!(heartbeat.on_pong(nonce, timestamp, start), None);
+    }
+
+    #[test]
+    fn test_missed_pongs_time_out() {
+        let mut heartbeat = Heartbeat::new(SECOND, 2);
+        let start = heartbeat.epoch;
+        heartbeat.poll(start);
+        heartbeat.poll(start + SECOND);
+        assert!(!heartbeat.timed_out());
+
+        // A late answer to an older ping still proves the connection is alive
+        let (nonce, timestamp) = heartbeat.poll(start + SECOND * 2).unwrap();
+        assert!(
+            heartbeat
+                .on_pong(nonce - 1, timestamp - 1000, start + SECOND * 2)
+                .is_some()
+        );
+        assert!(!heartbeat.timed_out());
+
+        heartbeat.poll(start + SECOND * 3);
+        heartbeat.poll(start + SECOND * 4);
+        assert!(heartbeat.timed_out());
+
+        heartbeat.reset();
+        assert!(!heartbeat.timed_out());
+        assert_eq!(heartbeat.rtt(), None);
+    }
+}
@@ -19,12 +19,27 @@ pub enum ProtocolError {
 }
 */
 
+pub mod auth;
+#[cfg(feature = "binary")]
+pub mod binary;
+pub mod entity;
+pub mod escape;
+pub mod framing;
+pub mod handshake;
+pub mod heartbeat;
+pub mod template;
 pub mod zones;
+#[cfg(test)]
+mod test_vectors;
+use auth::{Challenge, Verifier};
+pub use entity::{EntityId, EntityKind};
+use handshake::Codec;
 use zones::ZoneLink;
 
 
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Position {
     pub x: i32,
     pub y: i32,
@@ -35,6 +50,7 @@ impl Position {
     }
 }
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Facing {
     North,
     East,
@@ -69,73 +85,464 @@ impl std::fmt::Display for Facing {
     }
 }
 
-#[derive(Debug)]
+/// Errors produced while decoding protocol lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+// Only serialized: the argument names borrow from the parser
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "args", rename_all = "snake_case"))]
+pub enum ProtocolError {
+    /// A zone link or similar embedded value could not be parsed.
+    ServerLineUnparsable,
+    /// The line was empty once the line terminator was stripped.
+    EmptyLine,
+    /// The line had no `SRV:`/`USR-(name):` style prefix in front of the command.
+    MissingPrefix,
+    /// The command is not one the client understands.
+    UnknownCommand(String),
+    /// A command was missing one of its arguments.
+    MissingArgument {
+        command: &'static str,
+        argument: &'static str,
+    },
+    /// A coordinate argument was not a number.
+    InvalidCoordinate {
+        command: &'static str,
+        value: String,
+    },
+    /// A facing argument was not one of North, East, South or West.
+    InvalidFacingDirection(String),
+    /// A line grew past the framing limit before its terminator arrived.
+    LineTooLong { limit: usize },
+    /// A complete line was not valid UTF-8.
+    InvalidUtf8,
+    /// The protocol version in a hello was not a number.
+    InvalidVersion(String),
+    /// A quoted argument was missing its closing quote.
+    UnterminatedQuote,
+    /// A quoted argument used an unknown escape or was glued to the next one.
+    InvalidEscape(String),
+    /// A binary frame announced a payload over the decoder's limit.
+    FrameTooLong { limit: usize },
+    /// A binary payload was truncated or held an out of range value.
+    MalformedFrame(&'static str),
+    /// A binary payload started with a tag no message uses.
+    UnknownTag(u8),
+    /// An entity id was not a number.
+    InvalidEntityId(String),
+    /// An entity kind keyword was not one the client knows.
+    InvalidEntityKind(String),
+    /// Template text used a `{x}` placeholder that does not exist.
+    UnknownPlaceholder(String),
+    /// Template text opened a placeholder without closing it.
+    UnterminatedPlaceholder,
+    /// A salt, nonce or proof was not valid hex.
+    InvalidHex(String),
+    /// A login challenge asked for an unusable number of KDF iterations.
+    InvalidIterations(String),
+    /// A nonce or timestamp was not a number.
+    InvalidNumber(String),
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use ProtocolError::*;
+        match self {
+            ServerLineUnparsable => write!(f, "server line unparsable"),
+            EmptyLine => write!(f, "empty line"),
+            MissingPrefix => write!(f, "line has no sender prefix"),
+            UnknownCommand(cmd) => write!(f, "unknown command `{}`", cmd),
+            MissingArgument { command, argument } => {
+                write!(f, "`{}` is missing its {} argument", command, argument)
+            }
+            InvalidCoordinate { command, value } => {
+                write!(f, "`{}` has an invalid coordinate `{}`", command, value)
+            }
+            InvalidFacingDirection(value) => write!(f, "invalid facing direction `{}`", value),
+            LineTooLong { limit } => write!(f, "line longer than {} bytes", limit),
+            InvalidUtf8 => write!(f, "line is not valid UTF-8"),
+            InvalidVersion(value) => write!(f, "invalid protocol version `{}`", value),
+            UnterminatedQuote => write!(f, "quoted argument is not terminated"),
+            InvalidEscape(value) => write!(f, "invalid escape `{}` in quoted argument", value),
+            FrameTooLong { limit } => write!(f, "frame longer than {} bytes", limit),
+            MalformedFrame(reason) => write!(f, "malformed frame: {}", reason),
+            UnknownTag(tag) => write!(f, "unknown message tag {}", tag),
+            InvalidEntityId(value) => write!(f, "invalid entity id `{}`", value),
+            InvalidEntityKind(value) => write!(f, "invalid entity kind `{}`", value),
+            UnknownPlaceholder(value) => write!(f, "unknown placeholder `{{{}}}`", value),
+            UnterminatedPlaceholder => write!(f, "placeholder is not closed"),
+            InvalidHex(value) => write!(f, "invalid hex `{}`", value),
+            InvalidIterations(value) => write!(f, "invalid KDF iteration count `{}`", value),
+            InvalidNumber(value) => write!(f, "invalid number `{}`", value),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "args", rename_all = "snake_case"))]
 pub enum ServerToClient {
-    EntityMoved(Position),
-    // TODO: Remove pos and facing from here and move it to the above
-    PlayerJoined(String, Position, Facing),
+    /// Something came into view: a player, NPC, item or projectile.
+    EntitySpawned {
+        id: EntityId,
+        kind: EntityKind,
+        pos: Position,
+        facing: Facing,
+    },
+    EntityMoved(EntityId, Position, Facing),
+    EntityDespawned(EntityId),
+
+    // Username keyed updates from servers that predate entity ids
+    // TODO: Remove these once the server sends the entity messages above
+    /// A player came into the world, last with the zone path of the room they are
+    /// in, e.g. `["hub", "room1"]`, empty if the server did not say.
+    PlayerJoined(String, Position, Facing, Vec<String>),
     PlayerLeft(String),
-    PlayerMoved(String, Position, Facing),
+    /// A player moved, with their zone path like `PlayerJoined`.
+    PlayerMoved(String, Position, Facing, Vec<String>),
+    /// A player turned without moving, sent as `USR-(name): Facing X`.
+    PlayerFaced(String, Facing),
     ChatMessage(String, String),
+
+    /// The server accepted our login.
+    LoginOk,
+    /// The server rejected our login, with its reason.
+    LoginFailed(String),
+    /// The server created our account and logged us in.
+    RegisterOk,
+    /// The server refused to create the account, with its reason.
+    RegisterFailed(String),
+    /// The server dropped us from the game, with its reason.
+    Kicked(String),
+    /// Answer to `AuthStart`, see the `auth` module.
+    AuthChallenge(Challenge),
+    /// Token for `ClientToServer::Resume`, sent after a successful login.
+    Session(String),
+    /// Echo of a `ClientToServer::Ping`, with its nonce and timestamp.
+    Pong(u32, u64),
+    /// Where our player stands after the server applied every `ClientToServer::Input`
+    /// up to the given sequence number.
+    PositionAck(u32, Position, Facing),
+
+    /// Handshake reply carrying the server's protocol version and capabilities.
+    Hello(u32, Vec<String>),
 }
 
+
 impl std::str::FromStr for ServerToClient {
     type Err = ProtocolError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut msg = s.split(" ");
-        let srv_conf = msg.next().unwrap();
-        // let user_msg = msg.next().unwrap();
+        // The server terminates lines with \r\n, but the pre-auth path uses \n\r.
+        let line = s.trim_matches(|c| c == '\r' || c == '\n');
+        if line.trim().is_empty() {
+            return Err(ProtocolError::EmptyLine);
+        }
 
-        let cmd = msg.next().unwrap();
-        match cmd {
-            "chat" => {
-                let username: &str = msg.next().unwrap();
-                let mut rest = String::new();
-                for word in msg {
-                    rest.push_str(&format!(" {}", word));
+        let (prefix, body) = line.split_once(' ').ok_or(ProtocolError::MissingPrefix)?;
+
+        // Lines echoed on behalf of another player look like `USR-(name): Facing North`
+        if let Some(username) = prefix
+            .strip_prefix("USR-(")
+            .and_then(|rest| rest.strip_suffix("):"))
+        {
+            let mut args = body.split_whitespace();
+            return match args.next() {
+                Some("Facing") => {
+                    let facing = facing_arg("Facing", args.next())?;
+                    Ok(Self::PlayerFaced(username.to_string(), facing))
                 }
+                Some(cmd) => Err(ProtocolError::UnknownCommand(cmd.to_string())),
+                None => Err(ProtocolError::EmptyLine),
+            };
+        }
 
-                Ok(Self::ChatMessage(username.to_string(), rest))
-            }
-            "Facing" => Err(ProtocolError::ServerLineUnparsable),
-            "Username" => Err(ProtocolError::ServerLineUnparsable),
+        let body = body.trim();
+        if let Some(reply) = legacy_auth_reply(body) {
+            return Ok(reply);
+        }
 
-            err => {
-                log::error!("{}", err);
+        let (cmd, rest) = body.split_once(' ').unwrap_or((body, ""));
+        // Chat text and reasons are free form, so they are not split like the other commands
+        match cmd {
+            "chat_message" => return chat_args("chat_message", rest),
+            // Older servers relayed chat with the same keyword the client sends
+            "chat" => return chat_args("chat", rest),
+            "login_failed" => return Ok(Self::LoginFailed(escape::unquote_text(rest))),
+            "register_failed" => return Ok(Self::RegisterFailed(escape::unquote_text(rest))),
+            "kicked" => return Ok(Self::Kicked(escape::unquote_text(rest))),
+            _ => {}
+        }
 
-                Err(ProtocolError::ServerLineUnparsable)
+        let args = escape::split_args(rest)?;
+        let mut args = args.iter().map(String::as_str);
+        match cmd {
+            "player_joined" => {
+                let (username, pos, facing, zone) = player_args("player_joined", &mut args)?;
+                Ok(Self::PlayerJoined(username, pos, facing, zone))
+            }
+            "player_moved" => {
+                let (username, pos, facing, zone) = player_args("player_moved", &mut args)?;
+                Ok(Self::PlayerMoved(username, pos, facing, zone))
+            }
+            "entity_spawned" => {
+                let id = entity_id_arg("entity_spawned", args.next())?;
+                let keyword = required_arg("entity_spawned", "kind", args.next())?;
+                let name = args.next().ok_or(ProtocolError::MissingArgument {
+                    command: "entity_spawned",
+                    argument: "name",
+                })?;
+                let kind = EntityKind::new(keyword, name.to_string())?;
+                let x = coordinate_arg("entity_spawned", args.next())?;
+                let y = coordinate_arg("entity_spawned", args.next())?;
+                let facing = facing_arg("entity_spawned", args.next())?;
+                Ok(Self::EntitySpawned {
+                    id,
+                    kind,
+                    pos: Position::new(x, y),
+                    facing,
+                })
+            }
+            "entity_moved" => {
+                let id = entity_id_arg("entity_moved", args.next())?;
+                let x = coordinate_arg("entity_moved", args.next())?;
+                let y = coordinate_arg("entity_moved", args.next())?;
+                let facing = facing_arg("entity_moved", args.next())?;
+                Ok(Self::EntityMoved(id, Position::new(x, y), facing))
+            }
+            "entity_despawned" => {
+                let id = entity_id_arg("entity_despawned", args.next())?;
+                Ok(Self::EntityDespawned(id))
+            }
+            "auth_challenge" => {
+                let salt = auth::from_hex(required_arg("auth_challenge", "salt", args.next())?)?;
+                let iterations = iterations_arg("auth_challenge", args.next())?;
+                let nonce = auth::from_hex(required_arg("auth_challenge", "nonce", args.next())?)?;
+                Ok(Self::AuthChallenge(Challenge {
+                    salt,
+                    iterations,
+                    nonce,
+                }))
+            }
+            "session" => {
+                let token = required_arg("session", "token", args.next())?;
+                Ok(Self::Session(token.to_string()))
+            }
+            "pong" => {
+                let nonce = required_arg("pong", "nonce", args.next())?;
+                let nonce = nonce
+                    .parse::<u32>()
+                    .map_err(|_| ProtocolError::InvalidNumber(nonce.to_string()))?;
+                let timestamp = required_arg("pong", "timestamp", args.next())?;
+                let timestamp = timestamp
+                    .parse::<u64>()
+                    .map_err(|_| ProtocolError::InvalidNumber(timestamp.to_string()))?;
+                Ok(Self::Pong(nonce, timestamp))
+            }
+            "position_ack" => {
+                let seq = required_arg("position_ack", "sequence", args.next())?;
+                let seq = seq
+                    .parse::<u32>()
+                    .map_err(|_| ProtocolError::InvalidNumber(seq.to_string()))?;
+                let x = coordinate_arg("position_ack", args.next())?;
+                let y = coordinate_arg("position_ack", args.next())?;
+                let facing = facing_arg("position_ack", args.next())?;
+                Ok(Self::PositionAck(seq, Position::new(x, y), facing))
             }
+            "login_ok" => Ok(Self::LoginOk),
+            "register_ok" => Ok(Self::RegisterOk),
+            "player_left" => {
+                let username = required_arg("player_left", "username", args.next())?;
+                Ok(Self::PlayerLeft(username.to_string()))
+            }
+            "hello" => {
+                let version = required_arg("hello", "version", args.next())?;
+                let version = version
+                    .parse::<u32>()
+                    .map_err(|_| ProtocolError::InvalidVersion(version.to_string()))?;
+                Ok(Self::Hello(version, args.map(String::from).collect()))
+            }
+            other => Err(ProtocolError::UnknownCommand(other.to_string())),
         }
     }
 }
-#[derive(Debug)]
+
+// Plain sentences servers from before the result messages answer auth with.
+// They send `Logged in` once the player is in the world, after a registration too.
+fn legacy_auth_reply(body: &str) -> Option<ServerToClient> {
+    use ServerToClient::*;
+    match body {
+        "Logged in" | "Login Success" => Some(LoginOk),
+        "Registered user." => Some(RegisterOk),
+        "Login error." | "Password incorrect" => Some(LoginFailed(body.to_string())),
+        "Already a player." => Some(RegisterFailed(body.to_string())),
+        _ => None,
+    }
+}
+
+fn required_arg<'a>(
+    command: &'static str,
+    argument: &'static str,
+    value: Option<&'a str>,
+) -> Result<&'a str, ProtocolError> {
+    match value {
+        Some(value) if !value.is_empty() => Ok(value),
+        _ => Err(ProtocolError::MissingArgument { command, argument }),
+    }
+}
+
+// Coordinates are integers on the wire, except for freshly spawned players
+// whose position the server still stores as `0.0`. Decimals that round to no
+// `i32`, like `NaN`, `inf` or `1e20`, are refused rather than clamped.
+fn coordinate_arg(command: &'static str, value: Option<&str>) -> Result<i32, ProtocolError> {
+    let value = required_arg(command, "coordinate", value)?;
+    let decimal = || {
+        let rounded = value.parse::<f64>().ok()?.round();
+        (rounded >= i32::MIN as f64 && rounded <= i32::MAX as f64).then_some(rounded as i32)
+    };
+    value
+        .parse::<i32>()
+        .ok()
+        .or_else(decimal)
+        .ok_or_else(|| ProtocolError::InvalidCoordinate {
+            command,
+            value: value.to_string(),
+        })
+}
+
+fn entity_id_arg(command: &'static str, value: Option<&str>) -> Result<EntityId, ProtocolError> {
+    let value = required_arg(command, "id", value)?;
+    value
+        .parse::<u32>()
+        .map(EntityId)
+        .map_err(|_| ProtocolError::InvalidEntityId(value.to_string()))
+}
+
+fn iterations_arg(command: &'static str, value: Option<&str>) -> Result<u32, ProtocolError> {
+    let value = required_arg(command, "iterations", value)?;
+    value
+        .parse::<u32>()
+        .map_err(|_| ProtocolError::InvalidIterations(value.to_string()))
+}
+
+fn facing_arg(command: &'static str, value: Option<&str>) -> Result<Facing, ProtocolError> {
+    match required_arg(command, "facing", value)? {
+        "North" => Ok(Facing::North),
+        "East" => Ok(Facing::East),
+        "South" => Ok(Facing::South),
+        "West" => Ok(Facing::West),
+        other => Err(ProtocolError::InvalidFacingDirection(other.to_string())),
+    }
+}
+
+// Chat is `username message...`, where the message may be quoted free text.
+fn chat_args(command: &'static str, rest: &str) -> Result<ServerToClient, ProtocolError> {
+    let (username, message) = escape::take_arg(rest)?.unwrap_or_default();
+    let username = required_arg(command, "username", Some(&username))?;
+    Ok(ServerToClient::ChatMessage(
+        username.to_string(),
+        escape::unquote_text(message),
+    ))
+}
+
+// Shared argument layout of `player_joined` and `player_moved`: `username x y facing [zone]`,
+// where servers from before zones leave out the slash separated zone path
+fn player_args<'a>(
+    command: &'static str,
+    args: &mut impl Iterator<Item = &'a str>,
+) -> Result<(String, Position, Facing, Vec<String>), ProtocolError> {
+    let username = required_arg(command, "username", args.next())?.to_string();
+    let x = coordinate_arg(command, args.next())?;
+    let y = coordinate_arg(command, args.next())?;
+    let facing = facing_arg(command, args.next())?;
+    let zone = match args.next() {
+        Some(path) if !path.is_empty() => path.split('/').map(String::from).collect(),
+        _ => Vec::new(),
+    };
+    Ok((username, Position::new(x, y), facing, zone))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "args", rename_all = "snake_case"))]
 pub enum ClientToServer {
     AttemptPlayerMove(Position),
     AttemptPlayerFacingChange(Facing),
 
+    /// Plaintext registration, only for servers without challenge auth.
     Register(String, String),
+    /// Plaintext login, only for servers without challenge auth.
     Login(String, String),
     ChatMessage(String),
     SetUsername(String),
     SetPosition(i32, i32),
 
     Goto(ZoneLink),
+
+    /// Opens the handshake with our protocol version and supported features.
+    Hello(u32, Vec<String>),
+    /// Everything the client sends after this uses the given codec.
+    UseCodec(Codec),
+
+    /// Opens a challenge-response login for the username.
+    AuthStart(String),
+    /// Answers the server's `AuthChallenge`, see `auth::client_proof`.
+    AuthProof(Vec<u8>),
+    /// Creates an account from a verifier rather than the password.
+    RegisterVerifier(String, Verifier),
+    /// Logs in again with the username and a session token from an earlier login.
+    Resume(String, String),
+    /// Keepalive with a nonce and our timestamp in milliseconds, see the `heartbeat` module.
+    Ping(u32, u64),
+    /// One frame of movement by dx, dy, numbered so the server can answer with
+    /// `ServerToClient::PositionAck`.
+    Input(u32, i32, i32, Facing),
 }
 
 impl ClientToServer {
+    /// Encodes the message as a protocol line, quoting user supplied arguments.
     pub fn as_line(&self) -> String {
         use ClientToServer::*;
+        use escape::{quote_arg, quote_text};
 
         match self {
             AttemptPlayerMove(position) => format!("pos {} {}\r\n", position.x, position.y),
             AttemptPlayerFacingChange(facing) => format!("face {}\r\n", facing),
-            Register(username, password) => format!("register {} {}\r\n", username, password),
-            Login(username, password) => format!("login {} {}\r\n", username, password),
-            ChatMessage(message) => format!("chat {}\r\n", message),
-            SetUsername(username) => format!("username {}\r\n", username),
+            Register(username, password) => {
+                format!("register {} {}\r\n", quote_arg(username), quote_arg(password))
+            }
+            Login(username, password) => {
+                format!("login {} {}\r\n", quote_arg(username), quote_arg(password))
+            }
+            ChatMessage(message) => format!("chat {}\r\n", quote_text(message)),
+            SetUsername(username) => format!("username {}\r\n", quote_arg(username)),
             SetPosition(x, y) => format!("pos {} {}\r\n", x, y),
             Goto(link) => format!("goto {}\r\n", link),
+            Hello(version, features) => {
+                let mut line = format!("hello {}", version);
+                for feature in features {
+                    line.push(' ');
+                    line.push_str(&quote_arg(feature));
+                }
+                line.push_str("\r\n");
+                line
+            }
+            UseCodec(codec) => format!("codec {}\r\n", codec),
+            AuthStart(username) => format!("auth_start {}\r\n", quote_arg(username)),
+            AuthProof(proof) => format!("auth_proof {}\r\n", auth::to_hex(proof)),
+            RegisterVerifier(username, verifier) => format!(
+                "register_verifier {} {} {} {}\r\n",
+                quote_arg(username),
+                auth::to_hex(&verifier.salt),
+                verifier.iterations,
+                auth::to_hex(&verifier.stored_key)
+            ),
+            Resume(username, token) => {
+                format!("resume {} {}\r\n", quote_arg(username), quote_arg(token))
+            }
+            Ping(nonce, timestamp) => format!("ping {} {}\r\n", nonce, timestamp),
+            Input(seq, dx, dy, facing) => format!("input {} {} {} {}\r\n", seq, dx, dy, facing),
         }
     }
 }
@@ -175,7 +582,7 @@ mod tests {
         let chat_msg = "SERVER chat user1 Hello World!";
         if let Ok(ServerToClient::ChatMessage(username, message)) = chat_msg.parse() {
             assert_eq!(username, "user1");
-            assert_eq!(message, " Hello World!");
+            assert_eq!(message, "Hello World!");
         } else {
             panic!("Failed to parse chat message");
         }
@@ -185,6 +592,295 @@ mod tests {
         assert!("SERVER Username test".parse::<ServerToClient>().is_err());
     }
 
+    #[test]
+    fn test_server_to_client_player_lines() {
+        assert_eq!(
+            "SRV: player_joined alice 32 48 East\r\n".parse::<ServerToClient>(),
+            Ok(ServerToClient::PlayerJoined(
+                "alice".to_string(),
+                Position::new(32, 48),
+                Facing::East,
+                Vec::new()
+            ))
+        );
+        assert_eq!(
+            "SRV: player_moved bob -4 12 West\r\n".parse::<ServerToClient>(),
+            Ok(ServerToClient::PlayerMoved(
+                "bob".to_string(),
+                Position::new(-4, 12),
+                Facing::West,
+                Vec::new()
+            ))
+        );
+        assert_eq!(
+            "SRV: player_left bob\r\n".parse::<ServerToClient>(),
+            Ok(ServerToClient::PlayerLeft("bob".to_string()))
+        );
+        assert_eq!(
+            "USR-(carol): Facing North".parse::<ServerToClient>(),
+            Ok(ServerToClient::PlayerFaced(
+                "carol".to_string(),
+                Facing::North
+            ))
+        );
+
+        // Freshly spawned players are reported with float coordinates
+        assert_eq!(
+            "SRV: player_joined dave 0.0 0.0 North".parse::<ServerToClient>(),
+            Ok(ServerToClient::PlayerJoined(
+                "dave".to_string(),
+                Position::new(0, 0),
+                Facing::North,
+                Vec::new()
+            ))
+        );
+
+        // Servers with zones say which room the player is in
+        assert_eq!(
+            "SRV: player_moved erin 8 8 South hub/room2".parse::<ServerToClient>(),
+            Ok(ServerToClient::PlayerMoved(
+                "erin".to_string(),
+                Position::new(8, 8),
+                Facing::South,
+                vec!["hub".to_string(), "room2".to_string()]
+            ))
+        );
+    }
+
+    #[test]
+    fn test_server_to_client_chat_and_auth() {
+        assert_eq!(
+            "SRV: chat_message alice hi   there \r\n".parse::<ServerToClient>(),
+            Ok(ServerToClient::ChatMessage(
+                "alice".to_string(),
+                "hi   there".to_string()
+            ))
+        );
+        assert_eq!(
+            "SRV: Logged in\r\n".parse::<ServerToClient>(),
+            Ok(ServerToClient::LoginOk)
+        );
+        // The pre-auth replies use a reversed terminator
+        assert_eq!(
+            "SRV: Password incorrect\n\r".parse::<ServerToClient>(),
+            Ok(ServerToClient::LoginFailed("Password incorrect".to_string()))
+        );
+        assert_eq!(
+            "SRV: Already a player.\n\r".parse::<ServerToClient>(),
+            Ok(ServerToClient::RegisterFailed("Already a player.".to_string()))
+        );
+        assert_eq!(
+            "SRV: register_failed Name   taken ".parse::<ServerToClient>(),
+            Ok(ServerToClient::RegisterFailed("Name   taken".to_string()))
+        );
+        assert_eq!(
+            "SRV: kicked \"Server\\nrestart\"".parse::<ServerToClient>(),
+            Ok(ServerToClient::Kicked("Server\nrestart".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_server_to_client_errors() {
+        assert_eq!(
+            "\r\n".parse::<ServerToClient>(),
+            Err(ProtocolError::EmptyLine)
+        );
+        assert_eq!(
+            "player_left".parse::<ServerToClient>(),
+            Err(ProtocolError::MissingPrefix)
+        );
+        assert_eq!(
+            "SRV: Invalid protocol.".parse::<ServerToClient>(),
+            Err(ProtocolError::UnknownCommand("Invalid".to_string()))
+        );
+        assert_eq!(
+            "SRV: player_moved bob 1".parse::<ServerToClient>(),
+            Err(ProtocolError::MissingArgument {
+                command: "player_moved",
+                argument: "coordinate"
+            })
+        );
+        assert_eq!(
+            "SRV: player_moved bob 1 abc North".parse::<ServerToClient>(),
+            Err(ProtocolError::InvalidCoordinate {
+                command: "player_moved",
+                value: "abc".to_string()
+            })
+        );
+        // Decimals only pass if they round to a coordinate
+        for value in ["NaN", "inf", "-inf", "1e20", "2147483647.5", "-2147483649"] {
+            assert_eq!(
+                format!("SRV: player_moved bob {} 1 North", value).parse::<ServerToClient>(),
+                Err(ProtocolError::InvalidCoordinate {
+                    command: "player_moved",
+                    value: value.to_string()
+                })
+            );
+        }
+        assert_eq!(
+            "SRV: player_moved bob -2147483648.4 2.5 North".parse::<ServerToClient>(),
+            Ok(ServerToClient::PlayerMoved(
+                "bob".to_string(),
+                Position::new(i32::MIN, 3),
+                Facing::North,
+                Vec::new()
+            ))
+        );
+        assert_eq!(
+            "SRV: player_joined bob 1 2 Up".parse::<ServerToClient>(),
+            Err(ProtocolError::InvalidFacingDirection("Up".to_string()))
+        );
+        assert_eq!(
+            "SRV: entity_moved bob 1 2 North".parse::<ServerToClient>(),
+            Err(ProtocolError::InvalidEntityId("bob".to_string()))
+        );
+        assert_eq!(
+            "SRV: entity_spawned 1 dragon Smaug 0 0 North".parse::<ServerToClient>(),
+            Err(ProtocolError::InvalidEntityKind("dragon".to_string()))
+        );
+        assert_eq!(
+            "SRV: player_left".parse::<ServerToClient>(),
+            Err(ProtocolError::MissingArgument {
+                command: "player_left",
+                argument: "username"
+            })
+        );
+        assert_eq!(
+            "SRV: auth_challenge xyz 4096 01".parse::<ServerToClient>(),
+            Err(ProtocolError::InvalidHex("xyz".to_string()))
+        );
+        assert_eq!(
+            "SRV: auth_challenge ab -1 01".parse::<ServerToClient>(),
+            Err(ProtocolError::InvalidIterations("-1".to_string()))
+        );
+        assert_eq!(
+            "SRV: pong 1 -5".parse::<ServerToClient>(),
+            Err(ProtocolError::InvalidNumber("-5".to_string()))
+        );
+        assert_eq!(
+            "SRV: position_ack -1 0 0 North".parse::<ServerToClient>(),
+            Err(ProtocolError::InvalidNumber("-1".to_string()))
+        );
+        assert_eq!(
+            "SRV: position_ack 1 0 0".parse::<ServerToClient>(),
+            Err(ProtocolError::MissingArgument {
+                command: "position_ack",
+                argument: "facing"
+            })
+        );
+        assert_eq!(
+            "SRV: auth_challenge ab 4096".parse::<ServerToClient>(),
+            Err(ProtocolError::MissingArgument {
+                command: "auth_challenge",
+                argument: "nonce"
+            })
+        );
+    }
+
+    #[test]
+    fn test_hello_exchange() {
+        let hello = ClientToServer::Hello(1, vec!["chat".to_string(), "zones".to_string()]);
+        assert_eq!(hello.as_line(), "hello 1 chat zones\r\n");
+        assert_eq!(ClientToServer::Hello(1, vec![]).as_line(), "hello 1\r\n");
+
+        assert_eq!(
+            "SRV: hello 2 chat gamemode:tag\r\n".parse::<ServerToClient>(),
+            Ok(ServerToClient::Hello(
+                2,
+                vec!["chat".to_string(), "gamemode:tag".to_string()]
+            ))
+        );
+        assert_eq!(
+            "SRV: hello two".parse::<ServerToClient>(),
+            Err(ProtocolError::InvalidVersion("two".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_quoted_arguments() {
+        let login = ClientToServer::Login("bob smith".to_string(), "p\"w".to_string());
+        assert_eq!(login.as_line(), "login \"bob smith\" \"p\\\"w\"\r\n");
+
+        let chat = ClientToServer::ChatMessage("hi\r\npos 0 0".to_string());
+        assert_eq!(chat.as_line(), "chat \"hi\\r\\npos 0 0\"\r\n");
+
+        assert_eq!(
+            "SRV: player_moved \"bob smith\" 1 2 North".parse::<ServerToClient>(),
+            Ok(ServerToClient::PlayerMoved(
+                "bob smith".to_string(),
+                Position::new(1, 2),
+                Facing::North,
+                Vec::new()
+            ))
+        );
+        assert_eq!(
+            "SRV: chat_message \"bob smith\" \"two\\nlines\"".parse::<ServerToClient>(),
+            Ok(ServerToClient::ChatMessage(
+                "bob smith".to_string(),
+                "two\nlines".to_string()
+            ))
+        );
+        assert_eq!(
+            "SRV: player_left \"bob".parse::<ServerToClient>(),
+            Err(ProtocolError::UnterminatedQuote)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_shapes() {
+        use serde_json::json;
+
+        let moved = ServerToClient::PlayerMoved(
+            "bob".to_string(),
+            Position::new(1, -2),
+            Facing::West,
+            vec!["hub".to_string()],
+        );
+        let value = serde_json::to_value(&moved).unwrap();
+        assert_eq!(
+            value,
+            json!({"type": "player_moved", "args": ["bob", {"x": 1, "y": -2}, "West", ["hub"]]})
+        );
+        assert_eq!(serde_json::from_value::<ServerToClient>(value).unwrap(), moved);
+
+        assert_eq!(
+            serde_json::to_value(ServerToClient::LoginOk).unwrap(),
+            json!({"type": "login_ok"})
+        );
+        assert_eq!(
+            serde_json::to_value(ServerToClient::PlayerLeft("bob".to_string())).unwrap(),
+            json!({"type": "player_left", "args": "bob"})
+        );
+
+        let goto = ClientToServer::Goto("hub/room1@x20y30".parse().unwrap());
+        let value = serde_json::to_value(&goto).unwrap();
+        assert_eq!(
+            value,
+            json!({"type": "goto", "args": {"zones": ["hub", "room1"], "pos": {"x": 20, "y": 30}}})
+        );
+        assert_eq!(serde_json::from_value::<ClientToServer>(value).unwrap(), goto);
+
+        assert_eq!(
+            serde_json::to_value(ProtocolError::MissingArgument {
+                command: "player_left",
+                argument: "username"
+            })
+            .unwrap(),
+            json!({"type": "missing_argument", "args": {"command": "player_left", "argument": "username"}})
+        );
+    }
+
+    #[test]
+    fn test_text_codec_vectors() {
+        for vector in test_vectors::client_to_server() {
+            assert_eq!(vector.message.as_line(), vector.line);
+        }
+        for vector in test_vectors::server_to_client() {
+            assert_eq!(vector.line.parse::<ServerToClient>(), Ok(vector.message));
+        }
+    }
+
     #[test]
     fn test_client_to_server_formatting() {
         // Test movement command
@@ -0,0 +1,355 @@
+// Placeholder text such as `You hold {i}` or `Hello ~{name}!`.
+//
+// `{i}`, `{h}`, `{c}` and `{b}` are filled in by the server with the held item
+// and the head, chest and boots equipment. `~{key}` placeholders travel to the
+// client untouched and are filled in there, right before the text is shown.
+// `{{` and `}}` stand for literal braces and `~~` for a literal tilde, so text
+// ending in `~` stays text when a placeholder follows it.
+
+use crate::ProtocolError;
+use std::fmt;
+use std::str::FromStr;
+
+/// A placeholder that can appear in template text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "type", content = "args", rename_all = "snake_case")
+)]
+pub enum Placeholder {
+    /// `{i}`, the currently held item.
+    HeldItem,
+    /// `{h}`, the head equipment.
+    Head,
+    /// `{c}`, the chest equipment.
+    Chest,
+    /// `{b}`, the boots.
+    Boots,
+    /// `~{key}`, filled in by the client.
+    Client(String),
+}
+
+impl Placeholder {
+    /// Whether the client rather than the server fills this in.
+    pub fn is_client_side(&self) -> bool {
+        matches!(self, Placeholder::Client(_))
+    }
+}
+
+impl fmt::Display for Placeholder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use Placeholder::*;
+        match self {
+            HeldItem => write!(f, "{{i}}"),
+            Head => write!(f, "{{h}}"),
+            Chest => write!(f, "{{c}}"),
+            Boots => write!(f, "{{b}}"),
+            Client(key) => write!(f, "~{{{}}}", key),
+        }
+    }
+}
+
+/// A piece of template text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(tag = "type", content = "args", rename_all = "snake_case")
+)]
+pub enum Token {
+    Text(String),
+    Placeholder(Placeholder),
+}
+
+/// Text split into literal runs and placeholders.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Template {
+    pub tokens: Vec<Token>,
+}
+
+impl FromStr for Template {
+    type Err = ProtocolError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut tokens = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.char_indices().peekable();
+
+        while let Some((i, c)) = chars.next() {
+            let placeholder = match c {
+                '{' if chars.next_if(|&(_, c)| c == '{').is_some() => {
+                    text.push('{');
+                    continue;
+                }
+                '}' if chars.next_if(|&(_, c)| c == '}').is_some() => {
+                    text.push('}');
+                    continue;
+                }
+                '~' if chars.next_if(|&(_, c)| c == '~').is_some() => {
+                    text.push('~');
+                    continue;
+                }
+                '~' if chars.next_if(|&(_, c)| c == '{').is_some() => {
+                    let key = take_until_brace(s, &mut chars, i + 2)?;
+                    Placeholder::Client(key.to_string())
+                }
+                '{' => match take_until_brace(s, &mut chars, i + 1)? {
+                    "i" => Placeholder::HeldItem,
+                    "h" => Placeholder::Head,
+                    "c" => Placeholder::Chest,
+                    "b" => Placeholder::Boots,
+                    other => return Err(ProtocolError::UnknownPlaceholder(other.to_string())),
+                },
+                '}' => return Err(ProtocolError::UnknownPlaceholder("}".to_string())),
+                c => {
+                    text.push(c);
+                    continue;
+                }
+            };
+
+            if !text.is_empty() {
+                tokens.push(Token::Text(std::mem::take(&mut text)));
+            }
+            tokens.push(Token::Placeholder(placeholder));
+        }
+
+        if !text.is_empty() {
+            tokens.push(Token::Text(text));
+        }
+        Ok(Self { tokens })
+    }
+}
+
+// Returns the placeholder name from `start` up to the closing brace
+fn take_until_brace<'a>(
+    source: &'a str,
+    chars: &mut std::iter::Peekable<std::str::CharIndices<'_>>,
+    start: usize,
+) -> Result<&'a str, ProtocolError> {
+    for (i, c) in chars.by_ref() {
+        if c == '}' {
+            return Ok(&source[start..i]);
+        }
+    }
+    Err(ProtocolError::UnterminatedPlaceholder)
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for token in &self.tokens {
+            match token {
+                Token::Text(text) => write!(
+                    f,
+                    "{}",
+                    text.replace('~', "~~")
+                        .replace('{', "{{")
+                        .replace('}', "}}")
+                )?,
+                Token::Placeholder(placeholder) => write!(f, "{}", placeholder)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Template {
+    pub fn placeholders(&self) -> impl Iterator<Item = &Placeholder> {
+        self.tokens.iter().filter_map(|token| match token {
+            Token::Placeholder(placeholder) => Some(placeholder),
+            Token::Text(_) => None,
+        })
+    }
+
+    /// Fills in every placeholder `resolve` knows a value for.
+    ///
+    /// The result is a template again, so whatever `resolve` left alone can be
+    /// filled in later, e.g. server placeholders first and client ones on
+    /// arrival.
+    pub fn fill(&self, mut resolve: impl FnMut(&Placeholder) -> Option<String>) -> Template {
+        let mut tokens: Vec<Token> = Vec::new();
+        for token in &self.tokens {
+            let token = match token {
+                Token::Placeholder(placeholder) => match resolve(placeholder) {
+                    Some(value) => Token::Text(value),
+                    None => token.clone(),
+                },
+                Token::Text(_) => token.clone(),
+            };
+
+            // Keep neighbouring text in a single token
+            match (tokens.last_mut(), token) {
+                (Some(Token::Text(last)), Token::Text(text)) => last.push_str(&text),
+                (_, token) => tokens.push(token),
+            }
+        }
+        Template { tokens }
+    }
+
+    /// Renders the template for display, showing unfilled placeholders as written.
+    pub fn render(&self, resolve: impl FnMut(&Placeholder) -> Option<String>) -> String {
+        let mut out = String::new();
+        for token in self.fill(resolve).tokens {
+            match token {
+                Token::Text(text) => out.push_str(&text),
+                Token::Placeholder(placeholder) => out.push_str(&placeholder.to_string()),
+            }
+        }
+        out
+    }
+}
+
+/// Expands the client-side `~{key}` placeholders of received text for display.
+///
+/// Text that is not a valid template is shown exactly as it arrived.
+pub fn expand_client_placeholders(
+    text: &str,
+    mut resolve: impl FnMut(&str) -> Option<String>,
+) -> String {
+    match text.parse::<Template>() {
+        Ok(template) => template.render(|placeholder| match placeholder {
+            Placeholder::Client(key) => resolve(key),
+            _ => None,
+        }),
+        Err(_) => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Token {
+        Token::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_parse_placeholders() {
+        let template: Template = "Hi ~{name}, you hold {i} and wear {h}{c}{b}"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            template.tokens,
+            vec![
+                text("Hi "),
+                Token::Placeholder(Placeholder::Client("name".to_string())),
+                text(", you hold "),
+                Token::Placeholder(Placeholder::HeldItem),
+                text(" and wear "),
+                Token::Placeholder(Placeholder::Head),
+                Token::Placeholder(Placeholder::Chest),
+                Token::Placeholder(Placeholder::Boots),
+            ]
+        );
+        assert_eq!(
+            template
+                .placeholders()
+                .filter(|p| p.is_client_side())
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_literal_braces_and_tildes() {
+        let template: Template = "{{not}} ~ a ~placeholder~".parse().unwrap();
+        assert_eq!(template.tokens, vec![text("{not} ~ a ~placeholder~")]);
+        // A lone tilde is read as text, but written escaped like the braces
+        assert_eq!(template.to_string(), "{{not}} ~~ a ~~placeholder~~");
+        assert_eq!(
+            "~~ and ~~~{x}".parse::<Template>().unwrap().tokens,
+            vec![
+                text("~ and ~"),
+                Token::Placeholder(Placeholder::Client("x".to_string()))
+            ]
+        );
+        assert_eq!("".parse::<Template>().unwrap().tokens, vec![]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            "{x}".parse::<Template>(),
+            Err(ProtocolError::UnknownPlaceholder("x".to_string()))
+        );
+        assert_eq!(
+            "oops {i".parse::<Template>(),
+            Err(ProtocolError::UnterminatedPlaceholder)
+        );
+        assert_eq!(
+            "a } b".parse::<Template>(),
+            Err(ProtocolError::UnknownPlaceholder("}".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_fill_in_two_stages() {
+        let template: Template = "~{name} holds {i} ({b})".parse().unwrap();
+
+        // The server fills in what it knows and forwards the rest
+        let from_server = template.fill(|placeholder| match placeholder {
+            Placeholder::HeldItem => Some("a {shiny} sword".to_string()),
+            _ => None,
+        });
+        let wire = from_server.to_string();
+        assert_eq!(wire, "~{name} holds a {{shiny}} sword ({b})");
+
+        let shown =
+            expand_client_placeholders(&wire, |key| (key == "name").then(|| "alice".to_string()));
+        assert_eq!(shown, "alice holds a {shiny} sword ({b})");
+    }
+
+    #[test]
+    fn test_invalid_text_is_shown_verbatim() {
+        assert_eq!(
+            expand_client_placeholders("50% off {today}", |_| None),
+            "50% off {today}"
+        );
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        for source in [
+            "",
+            "plain",
+            "{i}{h}",
+            "~{} and ~{key}",
+            "{{x}} {c}",
+            "~~{{literal}} ~~",
+        ] {
+            let template: Template = source.parse().unwrap();
+            assert_eq!(template.to_string(), source);
+            assert_eq!(template.to_string().parse::<Template>().unwrap(), template);
+        }
+
+        // Text ending in a tilde stays text in front of a placeholder
+        for placeholder in [Placeholder::Head, Placeholder::Client("name".to_string())] {
+            let template = Template {
+                tokens: vec![
+                    Token::Text("a~".to_string()),
+                    Token::Placeholder(placeholder),
+                ],
+            };
+            assert_eq!(template.to_string().parse::<Template>().unwrap(), template);
+        }
+
+        // A server filling `{i}` with `Sword~` keeps `{h}` a server placeholder
+        let filled = "{i}{h}".parse::<Template>().unwrap().fill(|placeholder| {
+            (*placeholder == Placeholder::HeldItem).then(|| "Sword~".to_string())
+        });
+        assert_eq!(filled.to_string(), "Sword~~{h}");
+        let sent: Template = filled.to_string().parse().unwrap();
+        assert_eq!(
+            sent.placeholders().collect::<Vec<_>>(),
+            vec![&Placeholder::Head]
+        );
+
+        // A literal `~{` is written as well
+        let template = Template {
+            tokens: vec![Token::Text("~{".to_string())],
+        };
+        assert_eq!(template.to_string(), "~~{{");
+        assert_eq!(template.to_string().parse::<Template>().unwrap(), template);
+    }
+}
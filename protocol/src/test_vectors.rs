@@ -0,0 +1,216 @@
+// Messages with their expected text line and binary frame, shared by the tests
+// of both codecs so they cannot drift apart.
+
+use crate::auth::{Challenge, Verifier};
+use crate::handshake::Codec;
+use crate::{ClientToServer, EntityId, EntityKind, Facing, Position, ServerToClient};
+
+pub struct Vector<T> {
+    pub message: T,
+    pub line: &'static str,
+    #[cfg_attr(not(feature = "binary"), allow(dead_code))]
+    pub frame: Vec<u8>,
+}
+
+fn vector<T>(message: T, line: &'static str, frame: &[&[u8]]) -> Vector<T> {
+    Vector {
+        message,
+        line,
+        frame: frame.concat(),
+    }
+}
+
+pub fn client_to_server() -> Vec<Vector<ClientToServer>> {
+    use ClientToServer::*;
+    vec![
+        vector(
+            AttemptPlayerMove(Position::new(100, 200)),
+            "pos 100 200\r\n",
+            &[&[5, 0, 0xc8, 0x01, 0x90, 0x03]],
+        ),
+        vector(
+            AttemptPlayerFacingChange(Facing::North),
+            "face North\r\n",
+            &[&[2, 1, 0]],
+        ),
+        vector(
+            Login("bob smith".to_string(), "pw".to_string()),
+            "login \"bob smith\" pw\r\n",
+            &[&[14, 3, 9], b"bob smith", &[2], b"pw"],
+        ),
+        vector(
+            ChatMessage("hi\nthere".to_string()),
+            "chat \"hi\\nthere\"\r\n",
+            &[&[10, 4, 8], b"hi\nthere"],
+        ),
+        vector(SetPosition(-1, 2), "pos -1 2\r\n", &[&[3, 6, 1, 4]]),
+        vector(
+            Goto("hub/room1@x20y30".parse().unwrap()),
+            "goto hub/room1@x20y30\r\n",
+            &[&[14, 7, 2, 3], b"hub", &[5], b"room1", &[40, 60]],
+        ),
+        vector(
+            Hello(1, vec!["chat".to_string(), "binary".to_string()]),
+            "hello 1 chat binary\r\n",
+            &[&[15, 8, 1, 2, 4], b"chat", &[6], b"binary"],
+        ),
+        vector(UseCodec(Codec::Binary), "codec binary\r\n", &[&[2, 9, 1]]),
+        vector(
+            AuthStart("bob".to_string()),
+            "auth_start bob\r\n",
+            &[&[5, 10, 3], b"bob"],
+        ),
+        vector(
+            AuthProof(vec![0xde, 0xad]),
+            "auth_proof dead\r\n",
+            &[&[4, 11, 2, 0xde, 0xad]],
+        ),
+        vector(
+            RegisterVerifier(
+                "bob".to_string(),
+                Verifier {
+                    salt: vec![1, 2],
+                    iterations: 4096,
+                    stored_key: vec![0xff],
+                },
+            ),
+            "register_verifier bob 0102 4096 ff\r\n",
+            &[&[12, 12, 3], b"bob", &[2, 1, 2, 0x80, 0x20, 1, 0xff]],
+        ),
+        vector(
+            Resume("bob".to_string(), "t0k3n".to_string()),
+            "resume bob t0k3n\r\n",
+            &[&[11, 13, 3], b"bob", &[5], b"t0k3n"],
+        ),
+        vector(Ping(3, 300), "ping 3 300\r\n", &[&[4, 14, 3, 0xac, 0x02]]),
+        vector(
+            Input(7, 1, -1, Facing::East),
+            "input 7 1 -1 East\r\n",
+            &[&[5, 15, 7, 2, 1, 1]],
+        ),
+    ]
+}
+
+pub fn server_to_client() -> Vec<Vector<ServerToClient>> {
+    use ServerToClient::*;
+    vec![
+        vector(
+            PlayerJoined(
+                "alice".to_string(),
+                Position::new(32, 48),
+                Facing::East,
+                Vec::new(),
+            ),
+            "SRV: player_joined alice 32 48 East\r\n",
+            &[&[10, 1, 5], b"alice", &[64, 96, 1]],
+        ),
+        vector(
+            PlayerMoved(
+                "bob smith".to_string(),
+                Position::new(-4, 12),
+                Facing::West,
+                Vec::new(),
+            ),
+            "SRV: player_moved \"bob smith\" -4 12 West\r\n",
+            &[&[14, 3, 9], b"bob smith", &[7, 24, 3]],
+        ),
+        vector(
+            PlayerMoved(
+                "bob".to_string(),
+                Position::new(1, 2),
+                Facing::North,
+                vec!["hub".to_string(), "room1".to_string()],
+            ),
+            "SRV: player_moved bob 1 2 North hub/room1\r\n",
+            &[
+                &[19, 3, 3],
+                b"bob",
+                &[2, 4, 0, 2, 3],
+                b"hub",
+                &[5],
+                b"room1",
+            ],
+        ),
+        vector(
+            PlayerLeft("bob".to_string()),
+            "SRV: player_left bob\r\n",
+            &[&[5, 2, 3], b"bob"],
+        ),
+        vector(
+            PlayerFaced("carol".to_string(), Facing::North),
+            "USR-(carol): Facing North\r\n",
+            &[&[8, 4, 5], b"carol", &[0]],
+        ),
+        vector(
+            ChatMessage("alice".to_string(), "hi there".to_string()),
+            "SRV: chat_message alice hi there\r\n",
+            &[&[16, 5, 5], b"alice", &[8], b"hi there"],
+        ),
+        vector(
+            EntitySpawned {
+                id: EntityId(300),
+                kind: EntityKind::Npc("Old Man".to_string()),
+                pos: Position::new(5, -5),
+                facing: Facing::South,
+            },
+            "SRV: entity_spawned 300 npc \"Old Man\" 5 -5 South\r\n",
+            &[&[15, 9, 0xac, 0x02, 1, 7], b"Old Man", &[10, 9, 2]],
+        ),
+        vector(
+            EntityMoved(EntityId(7), Position::new(1, 2), Facing::East),
+            "SRV: entity_moved 7 1 2 East\r\n",
+            &[&[5, 18, 7, 2, 4, 1]],
+        ),
+        vector(
+            EntityDespawned(EntityId(7)),
+            "SRV: entity_despawned 7\r\n",
+            &[&[2, 10, 7]],
+        ),
+        vector(LoginOk, "SRV: login_ok\r\n", &[&[1, 6]]),
+        vector(
+            LoginFailed("Password incorrect".to_string()),
+            "SRV: Password incorrect\r\n",
+            &[&[20, 7, 18], b"Password incorrect"],
+        ),
+        vector(RegisterOk, "SRV: register_ok\r\n", &[&[1, 11]]),
+        vector(
+            RegisterFailed("Name taken".to_string()),
+            "SRV: register_failed Name taken\r\n",
+            &[&[12, 12, 10], b"Name taken"],
+        ),
+        vector(
+            Kicked("Idle for too long".to_string()),
+            "SRV: kicked Idle for too long\r\n",
+            &[&[19, 13, 17], b"Idle for too long"],
+        ),
+        vector(
+            AuthChallenge(Challenge {
+                salt: vec![0xab, 0xcd],
+                iterations: 4096,
+                nonce: vec![1],
+            }),
+            "SRV: auth_challenge abcd 4096 01\r\n",
+            &[&[8, 14, 2, 0xab, 0xcd, 0x80, 0x20, 1, 1]],
+        ),
+        vector(
+            Session("t0k3n".to_string()),
+            "SRV: session t0k3n\r\n",
+            &[&[7, 15, 5], b"t0k3n"],
+        ),
+        vector(
+            Pong(3, 300),
+            "SRV: pong 3 300\r\n",
+            &[&[4, 16, 3, 0xac, 0x02]],
+        ),
+        vector(
+            PositionAck(7, Position::new(32, -48), Facing::South),
+            "SRV: position_ack 7 32 -48 South\r\n",
+            &[&[5, 17, 7, 64, 95, 2]],
+        ),
+        vector(
+            Hello(1, vec!["chat".to_string(), "binary".to_string()]),
+            "SRV: hello 1 chat binary\r\n",
+            &[&[15, 8, 1, 2, 4], b"chat", &[6], b"binary"],
+        ),
+    ]
+}
@@ -4,7 +4,8 @@ use std::str::FromStr;
 use std::fmt;
 
 // This is a teleportation link to be used by doors. hub/room1@x20y30
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ZoneLink {
     // A slash separated list of zones
     pub zones: Vec<String>,